@@ -0,0 +1,288 @@
+use crate::dates::{archive_timestamp, iso8601, unix_now};
+use anyhow::{Context, Result};
+use reqwest::header::{self, HeaderMap};
+use reqwest::StatusCode;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use url::Url;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+pub const WARC_FILE: &str = "crawl.warc";
+pub const WACZ_FILE: &str = "crawl.wacz";
+
+// A response as it goes into the archive. The body is what reqwest handed
+// us, so `Transfer-Encoding` no longer describes it and is left out.
+pub struct Capture<'a> {
+    pub url: &'a Url,
+    pub status: StatusCode,
+    pub headers: &'a HeaderMap,
+    pub body: &'a [u8],
+    // Pages are listed in the WACZ pages index; their resources aren't
+    pub is_page: bool,
+}
+
+struct Written {
+    file: File,
+    offset: u64,
+    records: u64,
+    // CDXJ lines, sorted when the index is written
+    index: Vec<String>,
+    pages: Vec<String>,
+}
+
+// Records every response the crawl reads into a WARC file in the output
+// directory, optionally packaged as a WACZ bundle when the run ends
+pub struct Archive {
+    dir: PathBuf,
+    wacz: bool,
+    // Remove the WARC once it's inside the WACZ
+    keep_warc: bool,
+    written: Mutex<Written>,
+}
+
+impl Archive {
+    pub async fn create(dir: &Path, keep_warc: bool, wacz: bool) -> Result<Archive> {
+        let path = dir.join(WARC_FILE);
+        let file = File::create(&path).await.with_context(|| format!("Failed to create {}", path.display()))?;
+        let archive = Archive {
+            dir: dir.to_path_buf(),
+            wacz,
+            keep_warc,
+            written: Mutex::new(Written { file, offset: 0, records: 0, index: Vec::new(), pages: Vec::new() }),
+        };
+
+        let info = format!("software: image_crawler/{}\r\nformat: WARC File Format 1.1\r\n", env!("CARGO_PKG_VERSION"));
+        let mut written = archive.written.lock().await;
+        let record = warc_record(&mut written, "warcinfo", None, "application/warc-fields", info.as_bytes(), None);
+        written.file.write_all(&record).await?;
+        written.offset += record.len() as u64;
+        drop(written);
+        Ok(archive)
+    }
+
+    pub async fn record(&self, capture: Capture<'_>) -> std::io::Result<()> {
+        let mut block = format!(
+            "HTTP/1.1 {} {}\r\n",
+            capture.status.as_u16(),
+            capture.status.canonical_reason().unwrap_or_default()
+        )
+        .into_bytes();
+        for (name, value) in capture.headers {
+            if name != header::TRANSFER_ENCODING {
+                block.extend_from_slice(name.as_str().as_bytes());
+                block.extend_from_slice(b": ");
+                block.extend_from_slice(value.as_bytes());
+                block.extend_from_slice(b"\r\n");
+            }
+        }
+        block.extend_from_slice(b"\r\n");
+        block.extend_from_slice(capture.body);
+
+        let digest = format!("sha256:{:x}", Sha256::digest(capture.body));
+        let now = unix_now();
+        let mime = capture
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .unwrap_or("application/octet-stream")
+            .trim()
+            .to_string();
+
+        let mut written = self.written.lock().await;
+        let record = warc_record(
+            &mut written,
+            "response",
+            Some(capture.url),
+            "application/http; msgtype=response",
+            &block,
+            Some(&digest),
+        );
+        let entry = json!({
+            "url": capture.url.as_str(),
+            "mime": mime,
+            "status": capture.status.as_u16().to_string(),
+            "digest": digest,
+            "length": record.len().to_string(),
+            "offset": written.offset.to_string(),
+            "filename": "data.warc",
+        });
+        written.index.push(format!("{} {} {}", surt(capture.url), archive_timestamp(now), entry));
+        if capture.is_page {
+            written.pages.push(json!({ "url": capture.url.as_str(), "ts": iso8601(now) }).to_string());
+        }
+        written.file.write_all(&record).await?;
+        written.offset += record.len() as u64;
+        Ok(())
+    }
+
+    // Flush the WARC and, with `--wacz`, package it with `main_page` as the
+    // page archive viewers open first
+    pub async fn finish(&self, main_page: &str) -> Result<()> {
+        let mut written = self.written.lock().await;
+        written.file.sync_all().await?;
+        let warc = self.dir.join(WARC_FILE);
+        if self.keep_warc {
+            println!("WARC written to {} ({} records)", warc.display(), written.records);
+        }
+        if !self.wacz {
+            return Ok(());
+        }
+
+        written.index.sort();
+        let index = lines(&written.index);
+        let mut pages = json!({ "format": "json-pages-1.0", "id": "pages", "title": "All Pages" }).to_string();
+        pages.push('\n');
+        pages.push_str(&lines(&written.pages));
+
+        let wacz = self.dir.join(WACZ_FILE);
+        let main_page = main_page.to_string();
+        let (source, target) = (warc.clone(), wacz.clone());
+        tokio::task::spawn_blocking(move || write_wacz(&source, &target, &main_page, index, pages)).await??;
+        println!("WACZ bundle written to {}", wacz.display());
+
+        if !self.keep_warc {
+            fs::remove_file(&warc).await?;
+        }
+        Ok(())
+    }
+}
+
+fn warc_record(
+    written: &mut Written,
+    kind: &str,
+    target: Option<&Url>,
+    content_type: &str,
+    block: &[u8],
+    payload_digest: Option<&str>,
+) -> Vec<u8> {
+    written.records += 1;
+    let now = unix_now();
+    let id = Sha256::digest(format!("{} {:?} {} {}", kind, target.map(Url::as_str), now, written.records));
+    let id = format!("{:x}", id);
+
+    let mut head = format!(
+        "WARC/1.1\r\nWARC-Type: {}\r\nWARC-Record-ID: <urn:uuid:{}-{}-{}-{}-{}>\r\nWARC-Date: {}\r\n",
+        kind,
+        &id[..8],
+        &id[8..12],
+        &id[12..16],
+        &id[16..20],
+        &id[20..32],
+        iso8601(now)
+    );
+    if let Some(url) = target {
+        head.push_str(&format!("WARC-Target-URI: {}\r\n", url));
+    }
+    if let Some(digest) = payload_digest {
+        head.push_str(&format!("WARC-Payload-Digest: {}\r\n", digest));
+    }
+    head.push_str(&format!(
+        "WARC-Block-Digest: sha256:{:x}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        Sha256::digest(block),
+        content_type,
+        block.len()
+    ));
+
+    let mut record = head.into_bytes();
+    record.extend_from_slice(block);
+    record.extend_from_slice(b"\r\n\r\n");
+    record
+}
+
+fn lines(entries: &[String]) -> String {
+    entries.iter().map(|entry| format!("{}\n", entry)).collect()
+}
+
+// The Sort-friendly URI Reordering Transform archive indexes are keyed by:
+// `https://www.Example.com/a?b=2&a=1` becomes `com,example)/a?a=1&b=2`
+fn surt(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    let mut key: String = host.rsplit('.').collect::<Vec<_>>().join(",");
+    if let Some(port) = url.port() {
+        key.push_str(&format!(":{}", port));
+    }
+    key.push(')');
+    key.push_str(&url.path().to_ascii_lowercase());
+    if let Some(query) = url.query() {
+        let mut params: Vec<String> = query.split('&').map(str::to_ascii_lowercase).collect();
+        params.sort();
+        key.push('?');
+        key.push_str(&params.join("&"));
+    }
+    key
+}
+
+// Package the WARC with its index, page list and a datapackage.json
+// describing every file, as the WACZ 1.1.1 spec lays out. Everything is
+// stored uncompressed so viewers can seek straight to a record.
+fn write_wacz(warc: &Path, wacz: &Path, main_page: &str, index: String, pages: String) -> Result<()> {
+    let tmp = wacz.with_extension("wacz.tmp");
+    let mut zip = ZipWriter::new(std::fs::File::create(&tmp)?);
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .large_file(true);
+
+    let mut resources = Vec::new();
+    let mut add = |zip: &mut ZipWriter<std::fs::File>, name: &str, bytes: &[u8]| -> Result<()> {
+        zip.start_file(name, options)?;
+        zip.write_all(bytes)?;
+        resources.push(resource(name, &format!("{:x}", Sha256::digest(bytes)), bytes.len() as u64));
+        Ok(())
+    };
+
+    // The WARC can be large, so it's streamed in and hashed on the way
+    zip.start_file("archive/data.warc", options)?;
+    let mut source = std::fs::File::open(warc)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = source.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        zip.write_all(&buffer[..read])?;
+        size += read as u64;
+    }
+    let warc_resource = resource("archive/data.warc", &format!("{:x}", hasher.finalize()), size);
+
+    add(&mut zip, "indexes/index.cdxj", index.as_bytes())?;
+    add(&mut zip, "pages/pages.jsonl", pages.as_bytes())?;
+    resources.insert(0, warc_resource);
+
+    let package = serde_json::to_vec_pretty(&json!({
+        "profile": "data-package",
+        "wacz_version": "1.1.1",
+        "title": main_page,
+        "created": iso8601(unix_now()),
+        "software": format!("image_crawler/{}", env!("CARGO_PKG_VERSION")),
+        "mainPageUrl": main_page,
+        "resources": resources,
+    }))?;
+    zip.start_file("datapackage.json", options)?;
+    zip.write_all(&package)?;
+    let digest = serde_json::to_vec_pretty(&json!({
+        "path": "datapackage.json",
+        "hash": format!("sha256:{:x}", Sha256::digest(&package)),
+    }))?;
+    zip.start_file("datapackage-digest.json", options)?;
+    zip.write_all(&digest)?;
+
+    zip.finish()?;
+    std::fs::rename(&tmp, wacz)?;
+    Ok(())
+}
+
+fn resource(path: &str, sha256: &str, bytes: u64) -> serde_json::Value {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    json!({ "name": name, "path": path, "hash": format!("sha256:{}", sha256), "bytes": bytes })
+}
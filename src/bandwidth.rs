@@ -0,0 +1,36 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+// How far ahead of the limit a transfer may run after a quiet spell
+const BURST: Duration = Duration::from_secs(1);
+
+// A `--max-bandwidth` cap on the bytes every job in the process reads,
+// together. Each read books the time its bytes take at the capped rate, and
+// waits if the bookings have got ahead of the clock.
+pub struct Bandwidth {
+    bytes_per_sec: u64,
+    // When the bytes booked so far will have been paid off
+    booked_until: Mutex<Instant>,
+}
+
+impl Bandwidth {
+    pub fn new(bytes_per_sec: u64) -> Bandwidth {
+        Bandwidth { bytes_per_sec: bytes_per_sec.max(1), booked_until: Mutex::new(Instant::now()) }
+    }
+
+    // Account for `bytes` just read, pausing until the rate is back in bounds
+    pub async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut booked_until = self.booked_until.lock().unwrap();
+            let now = Instant::now();
+            let idle_floor = now.checked_sub(BURST).unwrap_or(now);
+            let start = (*booked_until).max(idle_floor);
+            *booked_until = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+            booked_until.saturating_duration_since(now)
+        };
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::fs;
+
+// Written to the output directory, one host per line, for `--blacklist` to
+// read back in a later run
+pub const BLACKLIST_FILE: &str = "blacklist.txt";
+
+// Timeouts or 403s in a row after which `--learn-blacklist` gives up on a host
+const LEARN_AFTER: u32 = 5;
+
+// Hosts no requests are sent to: the ones `--blacklist` listed, and with
+// `--learn-blacklist` the ones that kept timing out or refusing us during
+// the run
+pub struct Blacklist {
+    path: PathBuf,
+    learn: bool,
+    hosts: Mutex<BTreeSet<String>>,
+    // Failures in a row so far, for the hosts that aren't listed yet
+    strikes: Mutex<HashMap<String, u32>>,
+}
+
+impl Blacklist {
+    pub fn load(output_dir: &Path, preload: Option<&Path>, learn: bool) -> Result<Blacklist> {
+        let mut hosts = BTreeSet::new();
+        if let Some(preload) = preload {
+            let text =
+                std::fs::read_to_string(preload).with_context(|| format!("Failed to read {}", preload.display()))?;
+            hosts.extend(
+                text.lines()
+                    .map(|line| line.trim().to_ascii_lowercase())
+                    .filter(|line| !line.is_empty() && !line.starts_with('#')),
+            );
+        }
+        Ok(Blacklist {
+            path: output_dir.join(BLACKLIST_FILE),
+            learn,
+            hosts: Mutex::new(hosts),
+            strikes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // False for hosts on the list
+    pub fn allows(&self, host: &str) -> bool {
+        !self.hosts.lock().unwrap().contains(host)
+    }
+
+    // Note how a request to `host` went: `failed` for a timeout or a 403
+    pub fn record(&self, host: &str, failed: bool) {
+        if !self.learn {
+            return;
+        }
+        let mut strikes = self.strikes.lock().unwrap();
+        if !failed {
+            strikes.remove(host);
+            return;
+        }
+        let count = strikes.entry(host.to_string()).or_default();
+        *count += 1;
+        if *count >= LEARN_AFTER && self.hosts.lock().unwrap().insert(host.to_string()) {
+            println!("Blacklisting {} for the rest of the run after {} timeouts or 403s in a row", host, count);
+        }
+    }
+
+    // With `--learn-blacklist`, write the list out, preloaded hosts included
+    pub async fn save(&self) -> Result<()> {
+        if !self.learn {
+            return Ok(());
+        }
+        let hosts = self.hosts.lock().unwrap().iter().map(|host| format!("{}\n", host)).collect::<String>();
+        fs::write(&self.path, hosts).await.with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
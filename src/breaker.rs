@@ -0,0 +1,177 @@
+use crate::verbosity::progress;
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::{sleep_until, Instant};
+
+#[derive(Default)]
+struct Circuit {
+    failures_in_a_row: u32,
+    open_until: Option<Instant>,
+    // Set while the one request let through after the cooldown is out,
+    // until when the others wait for it before another may try instead
+    trial_until: Option<Instant>,
+    // Until when the host asked us to stay away, with Retry-After
+    held_until: Option<Instant>,
+}
+
+// Per-host circuit breakers. A host that fails `threshold` requests in a row
+// has its requests held for `cooldown` while other hosts carry on. After
+// that a single trial request goes through while the rest keep waiting: its
+// success closes the circuit, its failure opens it again.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    hosts: Mutex<HashMap<String, Circuit>>,
+    // Woken when a trial request settles
+    settled: Notify,
+}
+
+impl CircuitBreaker {
+    // A threshold of 0 never opens a circuit
+    pub fn new(threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker { threshold, cooldown, hosts: Mutex::new(HashMap::new()), settled: Notify::new() }
+    }
+
+    // Wait until requests to `host` are allowed
+    pub async fn wait(&self, host: &str) {
+        loop {
+            // Taken before looking, so a trial settling in between still wakes us
+            let settled = self.settled.notified();
+            let until = {
+                let mut hosts = self.hosts.lock().unwrap();
+                let Some(circuit) = hosts.get_mut(host) else { return };
+                let now = Instant::now();
+                match circuit.open_until.max(circuit.held_until) {
+                    Some(until) if until > now => until,
+                    // Half open: the first request through is the trial and
+                    // the rest hear how it went. One that never reports back
+                    // (its task stopped, say) gives way after another cooldown.
+                    _ if circuit.open_until.is_some() => match circuit.trial_until {
+                        Some(trial) if trial > now => trial,
+                        _ => {
+                            circuit.trial_until = Some(now + self.cooldown);
+                            return;
+                        }
+                    },
+                    _ => return,
+                }
+            };
+            tokio::select! {
+                _ = settled => {}
+                _ = sleep_until(until) => {}
+            }
+        }
+    }
+
+    // Hold every request to `host` for `duration`, as its Retry-After asked.
+    // This doesn't count as a failure, and the circuit stays as it was.
+    pub fn hold(&self, host: &str, duration: Duration) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let circuit = hosts.entry(host.to_string()).or_default();
+        let until = Instant::now() + duration;
+        if circuit.held_until.is_none_or(|held| held < until) {
+            progress!("Rate-limited by {}, holding its requests for {}s", host, duration.as_secs_f32().ceil());
+            circuit.held_until = Some(until);
+        }
+    }
+
+    pub fn record_success(&self, host: &str) {
+        if let Some(circuit) = self.hosts.lock().unwrap().get_mut(host) {
+            circuit.trial_until = None;
+            if circuit.open_until.take().is_some() {
+                progress!("Circuit closed for {}, requests resume", host);
+                self.settled.notify_waiters();
+            }
+            circuit.failures_in_a_row = 0;
+        }
+    }
+
+    pub fn record_failure(&self, host: &str) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut hosts = self.hosts.lock().unwrap();
+        let circuit = hosts.entry(host.to_string()).or_default();
+        circuit.failures_in_a_row += 1;
+        let now = Instant::now();
+        if circuit.trial_until.take().is_some() {
+            progress!(
+                "Circuit reopened for {}: the trial request failed, holding its requests for {}s",
+                host,
+                self.cooldown.as_secs()
+            );
+            circuit.open_until = Some(now + self.cooldown);
+            self.settled.notify_waiters();
+        } else if circuit.failures_in_a_row >= self.threshold {
+            if circuit.open_until.is_none_or(|until| until <= now) {
+                progress!(
+                    "Circuit open for {} after {} failed requests in a row, holding its requests for {}s",
+                    host,
+                    circuit.failures_in_a_row,
+                    self.cooldown.as_secs()
+                );
+            }
+            circuit.open_until = Some(now + self.cooldown);
+        }
+    }
+}
+
+// Responses that count against a host's circuit: it answered, but only to
+// say it can't serve us right now
+pub fn is_failure_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::time::{sleep, timeout};
+
+    const COOLDOWN: Duration = Duration::from_millis(50);
+
+    fn open_breaker() -> Arc<CircuitBreaker> {
+        let breaker = Arc::new(CircuitBreaker::new(2, COOLDOWN));
+        breaker.record_failure("example.com");
+        breaker.record_failure("example.com");
+        breaker
+    }
+
+    #[tokio::test]
+    async fn half_open_lets_one_trial_through() {
+        let breaker = open_breaker();
+        let started = Instant::now();
+        breaker.wait("example.com").await;
+        assert!(started.elapsed() >= COOLDOWN);
+
+        let second = tokio::spawn({
+            let breaker = breaker.clone();
+            async move { breaker.wait("example.com").await }
+        });
+        sleep(COOLDOWN / 2).await;
+        assert!(!second.is_finished(), "a second request went through beside the trial");
+
+        breaker.record_success("example.com");
+        timeout(COOLDOWN, second).await.expect("closing the circuit didn't release the others").unwrap();
+        breaker.wait("example.com").await;
+    }
+
+    #[tokio::test]
+    async fn failed_trial_reopens_the_circuit() {
+        let breaker = open_breaker();
+        breaker.wait("example.com").await;
+        breaker.record_failure("example.com");
+        assert!(timeout(COOLDOWN / 2, breaker.wait("example.com")).await.is_err());
+        // The next cooldown over, the next trial goes through
+        timeout(COOLDOWN * 2, breaker.wait("example.com")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_hosts_carry_on() {
+        let breaker = open_breaker();
+        timeout(COOLDOWN / 2, breaker.wait("example.org")).await.unwrap();
+    }
+}
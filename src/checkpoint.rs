@@ -0,0 +1,191 @@
+use crate::dates::unix_now;
+use crate::manifest::{ErrorEntry, ImageEntry, InventoryEntry, PageEntry};
+use crate::verbosity::progress;
+use crate::{CrawlState, PageOrigin};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::time::sleep;
+
+// Everything needed to see how far a job got
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub job: String,
+    pub taken_at: u64,
+    // Pages discovered but not finished yet, including those in progress
+    pub frontier: Vec<String>,
+    pub visited: Vec<String>,
+    pub downloaded_images: Vec<String>,
+    pub pages_visited: usize,
+    pub images_downloaded: usize,
+    // What `--resume` needs besides: how each frontier page was reached and
+    // the manifest records so far. Older snapshots don't have them.
+    #[serde(default)]
+    pub origins: HashMap<String, PageOrigin>,
+    #[serde(default)]
+    pub pages: Vec<PageEntry>,
+    #[serde(default)]
+    pub images: Vec<ImageEntry>,
+    #[serde(default)]
+    pub inventory: Vec<InventoryEntry>,
+    #[serde(default)]
+    pub errors: Vec<ErrorEntry>,
+    #[serde(default)]
+    pub bytes_saved: u64,
+}
+
+// Where a job that hasn't finished can be resumed from, in its state directory
+const RESUME_FILE: &str = "resume.json";
+
+// How often a running job updates its resume point
+const RESUME_INTERVAL: Duration = Duration::from_secs(60);
+
+// Per-job directory for crawl bookkeeping, kept next to the downloads
+pub fn state_dir(output_dir: &Path, job: &str) -> PathBuf {
+    let slug: String = job
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    output_dir.join(".state").join(slug)
+}
+
+async fn take_snapshot(state: &CrawlState) -> Snapshot {
+    let origins = state.pending_urls.lock().await.clone();
+    let frontier: Vec<String> = origins.keys().cloned().collect();
+    let visited = state.visited_urls.to_vec();
+    let downloaded_images = state.downloaded_images.to_vec();
+
+    Snapshot {
+        job: state.name.clone(),
+        taken_at: unix_now(),
+        pages_visited: visited.len(),
+        images_downloaded: downloaded_images.len(),
+        frontier,
+        visited,
+        downloaded_images,
+        origins,
+        pages: state.page_records.lock().await.values().cloned().collect(),
+        images: state.image_records.lock().await.values().cloned().collect(),
+        inventory: state.inventory.lock().await.values().cloned().collect(),
+        errors: state.errors.lock().await.clone(),
+        bytes_saved: state.bytes_saved.load(std::sync::atomic::Ordering::Relaxed),
+    }
+}
+
+// Write a snapshot of the job to `name` in its state directory, under a
+// temporary name first so a crash mid-write never leaves a truncated
+// snapshot behind
+async fn write_to(state: &CrawlState, name: &str) -> Result<PathBuf> {
+    let snapshot = take_snapshot(state).await;
+    let dir = state_dir(&state.output_dir, &state.name);
+    fs::create_dir_all(&dir).await?;
+
+    let path = dir.join(name);
+    let tmp = dir.join(format!(".{}.tmp", name));
+    fs::write(&tmp, serde_json::to_vec(&snapshot)?).await?;
+    fs::rename(&tmp, &path)
+        .await
+        .with_context(|| format!("Failed to move snapshot into place at {}", path.display()))?;
+    Ok(path)
+}
+
+// Only the newest `keep` snapshots are retained
+pub async fn write_snapshot(state: &CrawlState, keep: usize) -> Result<PathBuf> {
+    let path = write_to(state, &format!("snapshot-{}.json", unix_now())).await?;
+    if let Some(dir) = path.parent() {
+        prune_snapshots(dir, keep).await?;
+    }
+    Ok(path)
+}
+
+pub async fn write_resume_point(state: &CrawlState) -> Result<PathBuf> {
+    write_to(state, RESUME_FILE).await
+}
+
+// Called once a job has finished, so there's nothing to resume
+pub async fn clear_resume_point(state: &CrawlState) -> Result<()> {
+    match fs::remove_file(state_dir(&state.output_dir, &state.name).join(RESUME_FILE)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+pub fn has_resume_point(dir: &Path) -> bool {
+    dir.join(RESUME_FILE).exists()
+}
+
+// The resume point a job left in `dir`, or failing that its newest
+// `--checkpoint-interval` snapshot
+pub async fn load_resume_point(dir: &Path) -> Result<Option<Snapshot>> {
+    let mut path = dir.join(RESUME_FILE);
+    if !path.exists() {
+        match list_snapshots(dir).await.ok().and_then(|snapshots| snapshots.last().cloned()) {
+            Some(snapshot) => path = snapshot,
+            None => return Ok(None),
+        }
+    }
+    let bytes = fs::read(&path).await.with_context(|| format!("Failed to read {}", path.display()))?;
+    let snapshot = serde_json::from_slice(&bytes).with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(snapshot))
+}
+
+// Snapshot paths in a state directory, oldest first
+pub async fn list_snapshots(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut snapshots = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with("snapshot-") && name.ends_with(".json") {
+            snapshots.push(entry.path());
+        }
+    }
+    // Timestamps all have the same width for the foreseeable future, so
+    // lexical order is chronological order
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+async fn prune_snapshots(dir: &Path, keep: usize) -> Result<()> {
+    let snapshots = list_snapshots(dir).await?;
+    let excess = snapshots.len().saturating_sub(keep.max(1));
+    for old in &snapshots[..excess] {
+        fs::remove_file(old).await?;
+    }
+    Ok(())
+}
+
+// Keep a running job's resume point up to date until it finishes, so a
+// crawl that dies can be carried on with `--resume`
+pub fn spawn_resume_points(state: Arc<CrawlState>) {
+    tokio::spawn(async move {
+        loop {
+            sleep(RESUME_INTERVAL).await;
+            if state.finished.load(std::sync::atomic::Ordering::Relaxed) {
+                return;
+            }
+            if let Err(e) = write_resume_point(&state).await {
+                eprintln!("Resume point for {} failed: {:#}", state.name, e);
+            }
+        }
+    });
+}
+
+// Snapshot a running job every `interval` until it finishes
+pub fn spawn_checkpoints(state: Arc<CrawlState>, interval: Duration, keep: usize) {
+    tokio::spawn(async move {
+        loop {
+            sleep(interval).await;
+            if state.finished.load(std::sync::atomic::Ordering::Relaxed) {
+                return;
+            }
+            match write_snapshot(&state, keep).await {
+                Ok(path) => progress!("Checkpoint written to {}", path.display()),
+                Err(e) => eprintln!("Checkpoint for {} failed: {:#}", state.name, e),
+            }
+        }
+    });
+}
@@ -0,0 +1,129 @@
+use crate::maintenance;
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+// How many bits apart two hashes can be and still count as the same picture,
+// for `cluster` and `--dedupe perceptual` alike
+pub const DEFAULT_MAX_DISTANCE: u32 = 10;
+
+// 64-bit difference hash: each bit says whether a pixel of a 9x8 grayscale
+// thumbnail is brighter than its right-hand neighbour. Resizing, recompression
+// and small edits barely move it, so near-duplicates land a few bits apart.
+fn dhash(image: &DynamicImage) -> u64 {
+    let thumb = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = thumb.get_pixel(x, y)[0] > thumb.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(brighter);
+        }
+    }
+    hash
+}
+
+fn dhash_file(path: &Path) -> Result<u64> {
+    Ok(dhash(&image::io::Reader::open(path)?.with_guessed_format()?.decode()?))
+}
+
+// What `--dedupe perceptual` tells images apart by: the hash, and the pixel
+// count that decides which of two near-duplicates is kept
+#[derive(Clone, Copy)]
+pub struct Fingerprint {
+    hash: u64,
+    pub pixels: u64,
+}
+
+impl Fingerprint {
+    pub fn of(bytes: &[u8]) -> Result<Fingerprint> {
+        let image = image::io::Reader::new(Cursor::new(bytes)).with_guessed_format()?.decode()?;
+        Ok(Fingerprint { hash: dhash(&image), pixels: u64::from(image.width()) * u64::from(image.height()) })
+    }
+
+    pub fn resembles(&self, other: &Fingerprint) -> bool {
+        (self.hash ^ other.hash).count_ones() <= DEFAULT_MAX_DISTANCE
+    }
+}
+
+fn find(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    // Point everything on the path straight at the root
+    let mut node = i;
+    while parents[node] != root {
+        node = std::mem::replace(&mut parents[node], root);
+    }
+    root
+}
+
+// `cluster` subcommand: group the images in a download directory whose
+// hashes are within `max_distance` bits of each other, and optionally lay
+// each group out as a directory of symlinks under `link_dir`
+pub fn cluster(dir: &Path, max_distance: u32, link_dir: Option<&Path>) -> Result<()> {
+    let mut hashed = Vec::new();
+    for relative in maintenance::image_files(dir)? {
+        match dhash_file(&dir.join(&relative)) {
+            Ok(hash) => hashed.push((relative, hash)),
+            Err(e) => eprintln!("Skipping {}: {}", relative.display(), e),
+        }
+    }
+
+    let mut parents: Vec<usize> = (0..hashed.len()).collect();
+    for i in 0..hashed.len() {
+        for j in i + 1..hashed.len() {
+            if (hashed[i].1 ^ hashed[j].1).count_ones() <= max_distance {
+                let (a, b) = (find(&mut parents, i), find(&mut parents, j));
+                parents[a] = b;
+            }
+        }
+    }
+
+    let mut clusters: Vec<Vec<&PathBuf>> = vec![Vec::new(); hashed.len()];
+    for (i, (path, _)) in hashed.iter().enumerate() {
+        clusters[find(&mut parents, i)].push(path);
+    }
+    let mut clusters: Vec<_> = clusters.into_iter().filter(|members| members.len() > 1).collect();
+    clusters.sort_by_key(|members| std::cmp::Reverse(members.len()));
+
+    for (n, members) in clusters.iter().enumerate() {
+        println!("Cluster {} ({} images):", n + 1, members.len());
+        for path in members {
+            println!("  {}", path.display());
+        }
+        if let Some(link_dir) = link_dir {
+            link_cluster(dir, &link_dir.join(format!("cluster-{:03}", n + 1)), members)?;
+        }
+    }
+
+    let clustered: usize = clusters.iter().map(Vec::len).sum();
+    println!(
+        "{} of {} images fall into {} clusters of near-duplicates",
+        clustered,
+        hashed.len(),
+        clusters.len()
+    );
+    Ok(())
+}
+
+fn link_cluster(dir: &Path, cluster_dir: &Path, members: &[&PathBuf]) -> Result<()> {
+    std::fs::create_dir_all(cluster_dir)?;
+    let dir = dir.canonicalize()?;
+    for path in members {
+        // Flatten the format/domain/size layout into the link's name
+        let name = path.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "_");
+        let link = cluster_dir.join(name);
+        if link.exists() {
+            continue;
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join(path), &link)
+            .with_context(|| format!("Failed to link {}", link.display()))?;
+        #[cfg(not(unix))]
+        std::fs::copy(dir.join(path), &link).with_context(|| format!("Failed to copy to {}", link.display()))?;
+    }
+    Ok(())
+}
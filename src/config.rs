@@ -0,0 +1,1410 @@
+use crate::cluster;
+use crate::login::LoginSettings;
+use crate::preset::Preset;
+use crate::retry::{Failure, Retries, Retry};
+use crate::robots::ROBOTS_AGENT;
+use crate::schedule::ActiveHours;
+use crate::status::{DAEMON_STATUS_ADDR, DEFAULT_STATUS_ADDR, TERMINATION_LOG};
+use crate::verbosity::Verbosity;
+use anyhow::{bail, Context, Result};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use url::Url;
+
+// Query parameters that carry a session ID rather than selecting content.
+// Left in place they make every link look unique and the crawl never ends.
+const DEFAULT_STRIP_PARAMS: &[&str] = &[
+    "phpsessid",
+    "jsessionid",
+    "aspsessionid",
+    "sid",
+    "sessionid",
+    "session_id",
+    "cfid",
+    "cftoken",
+];
+
+// Where `<img>` keeps its URL, most trusted first. Lazy-loading scripts put
+// a placeholder in `src` and the real image in one of the others, and a
+// responsive image's `srcset` has bigger sizes than its `src`.
+const DEFAULT_IMAGE_ATTRS: &[&str] = &["data-srcset", "data-src", "data-lazy-src", "data-original", "srcset", "src"];
+
+const DEFAULT_DELAY: Duration = Duration::from_millis(500);
+const DEFAULT_BREAKER_THRESHOLD: u32 = 5;
+const DEFAULT_BREAKER_COOLDOWN: Duration = Duration::from_secs(60);
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const DEFAULT_OUTPUT_DIR: &str = "downloads";
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+const DEFAULT_CONCURRENCY: usize = 8;
+const DEFAULT_MIN_DATA_URI_SIZE: usize = 1024;
+const DEFAULT_MAX_PAGE_SIZE: u64 = 5 << 20;
+const DEFAULT_NETWORK_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_NETWORK_PROBE_SECS: u64 = 30;
+const DEFAULT_CHECKPOINT_KEEP: usize = 3;
+const DEFAULT_EXEC_CONCURRENCY: usize = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HttpsPolicy {
+    // Fetch URLs with whatever scheme they were discovered with
+    Keep,
+    // Rewrite in-scope http:// links to https:// when the host supports it
+    Prefer,
+    // Never issue a plaintext request
+    Strict,
+}
+
+// What to do with http:// images referenced from an https:// page
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MixedContentPolicy {
+    Fetch,
+    Upgrade,
+    Skip,
+}
+
+impl FromStr for MixedContentPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fetch" => Ok(MixedContentPolicy::Fetch),
+            "upgrade" => Ok(MixedContentPolicy::Upgrade),
+            "skip" => Ok(MixedContentPolicy::Skip),
+            other => bail!("Unknown mixed-content policy '{}' (expected fetch, upgrade or skip)", other),
+        }
+    }
+}
+
+// `--retry FAILURE=N[/MS]`: how one kind of failure is retried, instead of
+// `--retries` and `--retry-backoff`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RetryRule {
+    pub failure: Failure,
+    pub attempts: u32,
+    // None keeps `--retry-backoff`
+    pub backoff: Option<Duration>,
+}
+
+impl FromStr for RetryRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((failure, budget)) = s.split_once('=') else {
+            bail!("Expected FAILURE=N or FAILURE=N/MS, like timeout=1 or server=5/2000, not '{}'", s)
+        };
+        let (attempts, backoff) = match budget.split_once('/') {
+            Some((attempts, backoff)) => {
+                let ms = backoff.trim().parse().with_context(|| format!("{} isn't a number of milliseconds", backoff))?;
+                (attempts, Some(Duration::from_millis(ms)))
+            }
+            None => (budget, None),
+        };
+        Ok(RetryRule {
+            failure: failure.trim().parse()?,
+            attempts: attempts.trim().parse().with_context(|| format!("{} isn't a number of retries", attempts))?,
+            backoff,
+        })
+    }
+}
+
+// Which URLs count as the site being crawled
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CrawlScope {
+    // The seed's scheme, host and port
+    Origin,
+    // The seed's host, over http or https and on any port
+    Host,
+    // The seed's host and its subdomains, `www.` aside, so `example.com`
+    // takes in `blog.example.com` and `cdn.example.com`
+    Domain,
+}
+
+impl FromStr for CrawlScope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "origin" => Ok(CrawlScope::Origin),
+            "host" => Ok(CrawlScope::Host),
+            "domain" => Ok(CrawlScope::Domain),
+            other => bail!("Unknown scope '{}' (expected origin, host or domain)", other),
+        }
+    }
+}
+
+// Where a redirect may lead and still be followed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RedirectScope {
+    // Only to URLs the crawl would have fetched anyway
+    Same,
+    Any,
+}
+
+impl FromStr for RedirectScope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "same" => Ok(RedirectScope::Same),
+            "any" => Ok(RedirectScope::Any),
+            other => bail!("Unknown redirect scope '{}' (expected same or any)", other),
+        }
+    }
+}
+
+// Which candidates of a responsive image's `srcset` are downloaded
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Srcset {
+    // The widest, or densest, one
+    Largest,
+    All,
+}
+
+impl FromStr for Srcset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "largest" => Ok(Srcset::Largest),
+            "all" => Ok(Srcset::All),
+            other => bail!("Unknown srcset choice '{}' (expected largest or all)", other),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TlsBackend {
+    Native,
+    Rustls,
+}
+
+impl TlsBackend {
+    pub fn compiled_in(self) -> bool {
+        match self {
+            TlsBackend::Native => cfg!(feature = "native-tls"),
+            TlsBackend::Rustls => cfg!(feature = "rustls"),
+        }
+    }
+}
+
+impl FromStr for TlsBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let backend = match s {
+            "native" => TlsBackend::Native,
+            "rustls" => TlsBackend::Rustls,
+            other => bail!("Unknown TLS backend '{}' (expected native or rustls)", other),
+        };
+        if !backend.compiled_in() {
+            bail!("this build doesn't include the {} TLS backend; rebuild with its cargo feature", s);
+        }
+        Ok(backend)
+    }
+}
+
+// Which discovered images to download first when the crawl collects them
+// before downloading any
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DownloadOrder {
+    LargestFirst,
+    SmallestFirst,
+}
+
+impl FromStr for DownloadOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "largest-first" => Ok(DownloadOrder::LargestFirst),
+            "smallest-first" => Ok(DownloadOrder::SmallestFirst),
+            other => bail!("Unknown download order '{}' (expected largest-first or smallest-first)", other),
+        }
+    }
+}
+
+// An image format the crawl can keep, `--formats` choosing which
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImageType {
+    Jpg,
+    Gif,
+    Png,
+    Webp,
+    Avif,
+    Bmp,
+    Tiff,
+}
+
+impl ImageType {
+    pub const ALL: [ImageType; 7] = [
+        ImageType::Jpg,
+        ImageType::Gif,
+        ImageType::Png,
+        ImageType::Webp,
+        ImageType::Avif,
+        ImageType::Bmp,
+        ImageType::Tiff,
+    ];
+
+    // Saved files get this extension, and sit in a directory of that name
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageType::Jpg => "jpg",
+            ImageType::Gif => "gif",
+            ImageType::Png => "png",
+            ImageType::Webp => "webp",
+            ImageType::Avif => "avif",
+            ImageType::Bmp => "bmp",
+            ImageType::Tiff => "tiff",
+        }
+    }
+}
+
+impl FromStr for ImageType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Ok(ImageType::Jpg),
+            "gif" => Ok(ImageType::Gif),
+            "png" => Ok(ImageType::Png),
+            "webp" => Ok(ImageType::Webp),
+            "avif" => Ok(ImageType::Avif),
+            "bmp" => Ok(ImageType::Bmp),
+            "tif" | "tiff" => Ok(ImageType::Tiff),
+            other => bail!("Unknown image format '{}' (expected jpg, gif, png, webp, avif, bmp or tiff)", other),
+        }
+    }
+}
+
+// How saved images are arranged in the output directory
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layout {
+    // <format>/<domain>/<size category>/
+    Standard,
+    // <year>/<month>/ the photo was taken, or undated/
+    Date,
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "standard" => Ok(Layout::Standard),
+            "date" => Ok(Layout::Date),
+            other => bail!("Unknown layout '{}' (expected standard or date)", other),
+        }
+    }
+}
+
+// Which images count as the same one, so only one of them is saved
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dedupe {
+    // Identical bytes
+    Exact,
+    // Ones that look alike, such as resized copies, keeping the largest
+    Perceptual,
+}
+
+impl FromStr for Dedupe {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "exact" => Ok(Dedupe::Exact),
+            "perceptual" => Ok(Dedupe::Perceptual),
+            other => bail!("Unknown dedupe mode '{}' (expected exact or perceptual)", other),
+        }
+    }
+}
+
+// What kind of crawl to run
+pub enum Command {
+    // Crawl the given seed or jobs in this process
+    Crawl,
+    // Download the images on one page without following its links
+    Fetch,
+    // Pull work from a frontier shared through Redis
+    Worker { redis_url: String },
+}
+
+#[derive(Parser)]
+#[command(name = "image_crawler", version, about = "Crawl a website and download its images")]
+pub struct Cli {
+    #[arg(short, long = "output", env = "RUSTY_SPIDER_OUTPUT", global = true, value_name = "DIR",
+          help = "Where to save images and crawl state [default: downloads]")]
+    pub output_dir: Option<PathBuf>,
+    #[arg(short, long, env = "RUSTY_SPIDER_QUIET", global = true, conflicts_with = "verbose",
+          help = "Only print warnings, errors and summaries, not a line per page and image")]
+    pub quiet: bool,
+    #[arg(short, long, env = "RUSTY_SPIDER_VERBOSE", global = true,
+          help = "Also say why links and images were passed over")]
+    pub verbose: bool,
+    #[command(subcommand)]
+    pub command: CliCommand,
+}
+
+impl Cli {
+    // For the commands that don't crawl, and so have no config file to
+    // take it from
+    pub fn output_dir(&self) -> PathBuf {
+        self.output_dir.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_OUTPUT_DIR))
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        match (self.quiet, self.verbose) {
+            (true, _) => Verbosity::Quiet,
+            (_, true) => Verbosity::Verbose,
+            _ => Verbosity::Normal,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum CliCommand {
+    #[command(about = "Crawl a site, or every job in a jobs file, and download its images")]
+    Crawl {
+        #[arg(required_unless_present_any = ["jobs", "config_file"], value_name = "URL",
+              help = "Page to start crawling from; several seeds are crawled side by side, each into its own directory")]
+        urls: Vec<String>,
+        #[arg(long, env = "RUSTY_SPIDER_JOBS", value_name = "FILE", conflicts_with = "urls",
+              help = "Run every [[job]] in a TOML file concurrently")]
+        jobs: Option<PathBuf>,
+        #[command(flatten)]
+        options: CrawlOptions,
+    },
+    #[command(about = "Download the images on a single page without following links")]
+    Fetch {
+        #[arg(help = "Page whose images to download")]
+        url: String,
+        #[command(flatten)]
+        options: CrawlOptions,
+    },
+    #[command(about = "Fetch only the seed, robots.txt and sitemaps, and print what a crawl would do")]
+    Plan {
+        #[arg(help = "Page a crawl would start from")]
+        url: String,
+        #[command(flatten)]
+        options: CrawlOptions,
+    },
+    #[command(about = "Crawl from a frontier shared with other workers through Redis")]
+    Worker {
+        #[arg(help = "Seed for the shared frontier, used by the first worker")]
+        url: String,
+        #[arg(long = "redis", env = "RUSTY_SPIDER_REDIS", value_name = "URL", help = "Redis holding the shared frontier")]
+        redis_url: String,
+        #[command(flatten)]
+        options: CrawlOptions,
+    },
+    #[command(about = "Show trends from the stats recorded by earlier runs", visible_alias = "stats")]
+    Report {
+        #[arg(help = "Only show runs for this site")]
+        site: Option<String>,
+    },
+    #[command(about = "Check the saved images against the manifest")]
+    Verify,
+    #[command(about = "Search the manifest for images (or pages) whose URL or path matches")]
+    Query {
+        #[arg(help = "Case-insensitive text to look for")]
+        pattern: String,
+        #[arg(long, help = "Search pages instead of images")]
+        pages: bool,
+    },
+    #[command(about = "Group downloaded images that look alike")]
+    Cluster {
+        #[arg(help = "Directory to scan [default: the --output directory]")]
+        dir: Option<PathBuf>,
+        #[arg(long, value_name = "BITS", default_value_t = cluster::DEFAULT_MAX_DISTANCE,
+              help = "Largest perceptual-hash difference (0-64) still counted as similar")]
+        max_distance: u32,
+        #[arg(long, value_name = "DIR", help = "Also create a directory of symlinks per cluster here")]
+        link: Option<PathBuf>,
+    },
+    #[command(about = "Compare the manifests of two output directories")]
+    Diff {
+        #[arg(help = "Output directory of the earlier crawl")]
+        old: PathBuf,
+        #[arg(help = "Output directory of the later crawl")]
+        new: PathBuf,
+    },
+    #[command(about = "Combine the output of several crawls into the --output directory")]
+    Merge {
+        #[arg(required = true, num_args = 2.., value_name = "DIR", help = "Output directories of the crawls to merge")]
+        dirs: Vec<PathBuf>,
+    },
+    #[command(about = "Delete images the latest crawl no longer references")]
+    Prune {
+        #[arg(long, help = "List what would be removed without removing it")]
+        dry_run: bool,
+    },
+    #[command(about = "Drop metadata whose files are gone, files no metadata accounts for and leftovers of interrupted writes")]
+    Gc {
+        #[arg(long, help = "List what would be removed without removing it")]
+        dry_run: bool,
+    },
+    #[command(about = "Write the manifest's images to a file for analytics tools")]
+    Export {
+        #[arg(long, value_name = "FILE", help = "Parquet file to write, one row per image")]
+        parquet: PathBuf,
+    },
+    #[command(about = "Print a shell completion script")]
+    Completions {
+        #[arg(help = "bash, elvish, fish, powershell or zsh")]
+        shell: clap_complete::Shell,
+    },
+}
+
+// Options shared by every command that crawls
+#[derive(Args)]
+pub struct CrawlOptions {
+    #[arg(long, env = "RUSTY_SPIDER_PROFILE", value_name = "PRESET",
+          help = "Start from a preset: polite, aggressive, archive or audit; other options override it")]
+    profile: Option<Preset>,
+    #[arg(long = "strip-param", env = "RUSTY_SPIDER_STRIP_PARAM", value_name = "NAME", value_delimiter = ',',
+          help = "Strip a session query parameter (repeatable)")]
+    strip_params: Vec<String>,
+    #[arg(long, env = "RUSTY_SPIDER_NO_DEFAULT_STRIP_PARAMS", help = "Keep PHPSESSID, sid, jsessionid, ... in URLs")]
+    no_default_strip_params: bool,
+    #[arg(long, env = "RUSTY_SPIDER_NO_UNIFY_HOSTS", help = "Treat www.example.com and example.com as different sites")]
+    no_unify_hosts: bool,
+    #[arg(long, env = "RUSTY_SPIDER_SCOPE", value_name = "SCOPE",
+          help = "What counts as the site: the seed's origin, its host on any scheme or port, or its domain with subdomains [default: origin]")]
+    scope: Option<CrawlScope>,
+    #[arg(long, env = "RUSTY_SPIDER_EXTERNAL_IMAGES",
+          help = "Download images from any host, e.g. a third-party CDN; pages are still kept to the scope")]
+    external_images: bool,
+    #[arg(long, env = "RUSTY_SPIDER_ORIGINAL_IMAGES",
+          help = "Drop resizing parameters like ?w=300 or &quality=60 from image URLs to fetch the originals")]
+    original_images: bool,
+    #[arg(long = "keep-image-params", env = "RUSTY_SPIDER_KEEP_IMAGE_PARAMS", value_name = "HOST", value_delimiter = ',',
+          requires = "original_images", help = "Leave image URLs on this host as they are with --original-images (repeatable)")]
+    keep_image_params: Vec<String>,
+    #[arg(long, env = "RUSTY_SPIDER_IMAGE_ATTRS", value_name = "ATTRS", value_delimiter = ',',
+          help = "Attributes an <img> URL is read from, first present wins [default: data-srcset,data-src,data-lazy-src,data-original,srcset,src]")]
+    image_attrs: Vec<String>,
+    #[arg(long, env = "RUSTY_SPIDER_PREFER_HTTPS", help = "Upgrade http:// links to https:// when the site supports it")]
+    prefer_https: bool,
+    #[arg(long, env = "RUSTY_SPIDER_HTTPS_ONLY", help = "Refuse to make any plaintext HTTP request")]
+    https_only: bool,
+    #[arg(long, env = "RUSTY_SPIDER_REDIRECT_SCOPE", value_name = "SCOPE",
+          help = "Follow redirects only within the crawl scope (same) or anywhere (any) [default: same]")]
+    redirect_scope: Option<RedirectScope>,
+    #[arg(long, env = "RUSTY_SPIDER_MAX_REDIRECTS", value_name = "N", help = "Redirects to follow per request [default: 10]")]
+    max_redirects: Option<usize>,
+    #[arg(long, env = "RUSTY_SPIDER_TLS", value_name = "BACKEND", help = "TLS stack when built with both: native or rustls")]
+    tls: Option<TlsBackend>,
+    #[arg(long, env = "RUSTY_SPIDER_PROXY", value_name = "URL", value_parser = parse_proxy,
+          help = "Send every request through this proxy: http://, https://, socks5:// or socks5h:// [default: HTTP_PROXY/HTTPS_PROXY]")]
+    proxy: Option<Url>,
+    #[arg(long, env = "RUSTY_SPIDER_PROXY_AUTH", value_name = "USER:PASSWORD", requires = "proxy",
+          help = "Credentials for --proxy, when they aren't in its URL")]
+    proxy_auth: Option<String>,
+    #[arg(long, env = "RUSTY_SPIDER_NO_STYLESHEETS", help = "Don't look for images in linked stylesheets")]
+    no_stylesheets: bool,
+    #[arg(long, env = "RUSTY_SPIDER_IGNORE_ROBOTS", help = "Crawl without fetching or honoring robots.txt")]
+    ignore_robots: bool,
+    #[arg(long, env = "RUSTY_SPIDER_IGNORE_NOIMAGEINDEX",
+          help = "Save images from pages whose robots meta tag or X-Robots-Tag says noimageindex")]
+    ignore_noimageindex: bool,
+    #[arg(long, env = "RUSTY_SPIDER_FOLLOW_AMP", value_name = "BOOL", num_args = 0..=1, require_equals = true,
+          default_missing_value = "true", help = "Also fetch each page's AMP variant for its images")]
+    follow_amp: Option<bool>,
+    #[arg(long, env = "RUSTY_SPIDER_FOLLOW_IFRAMES", value_name = "BOOL", num_args = 0..=1, require_equals = true,
+          default_missing_value = "true", help = "Crawl same-site iframe documents for their images")]
+    follow_iframes: Option<bool>,
+    #[arg(long, env = "RUSTY_SPIDER_FROM", value_name = "EMAIL", value_parser = parse_email,
+          help = "Send this address in a From header, so site operators can reach whoever runs the crawl")]
+    from: Option<String>,
+    #[arg(long, env = "RUSTY_SPIDER_CONTACT_URL", value_name = "URL",
+          help = "Name a page about the crawl in the User-Agent, as in image_crawler/1.0 (+URL)")]
+    contact_url: Option<Url>,
+    #[arg(long, env = "RUSTY_SPIDER_USER_AGENT", value_name = "UA", conflicts_with = "contact_url",
+          help = "Send this User-Agent instead of image_crawler/<version> (+<repository>); robots.txt rules are read for its first word")]
+    user_agent: Option<String>,
+    #[arg(long, env = "RUSTY_SPIDER_ACCEPT_LANGUAGE", value_name = "LANGS",
+          help = "Ask for pages in these languages, e.g. de-DE or \"de-DE,de;q=0.9\"")]
+    accept_language: Option<String>,
+    #[arg(long = "header", env = "RUSTY_SPIDER_HEADER", value_name = "NAME: VALUE", value_parser = parse_header,
+          help = "Send this header with every request, e.g. 'Referer: https://example.com/' (repeatable)")]
+    headers: Vec<(String, String)>,
+    #[arg(long, env = "RUSTY_SPIDER_COOKIE_FILE", value_name = "FILE",
+          help = "Start with the cookies in this Netscape cookie file, e.g. a browser's cookies.txt export")]
+    cookie_file: Option<PathBuf>,
+    #[arg(long, env = "RUSTY_SPIDER_FOLLOW_HREFLANG", requires = "accept_language",
+          help = "Also crawl the hreflang alternates of each page that are in the --accept-language languages")]
+    follow_hreflang: bool,
+    #[arg(long, env = "RUSTY_SPIDER_MIN_DATA_URI_SIZE", value_name = "BYTES",
+          help = "Smallest embedded data: image worth saving [default: 1024]")]
+    min_data_uri_size: Option<usize>,
+    #[arg(long, env = "RUSTY_SPIDER_SRCSET", value_name = "CHOICE",
+          help = "From each srcset, download the largest candidate or all of them [default: largest]")]
+    srcset: Option<Srcset>,
+    #[arg(long, env = "RUSTY_SPIDER_MIXED_CONTENT", value_name = "POLICY", help = "http images on https pages: fetch (default), upgrade or skip")]
+    mixed_content: Option<MixedContentPolicy>,
+    #[arg(long, env = "RUSTY_SPIDER_CONCURRENCY", value_name = "N", value_parser = parse_count,
+          help = "Pages to crawl at the same time [default: 8]")]
+    concurrency: Option<usize>,
+    #[arg(long, env = "RUSTY_SPIDER_MAX_DEPTH", value_name = "N",
+          help = "Don't follow links from pages N links away from the seed (0 crawls only the seed)")]
+    max_depth: Option<usize>,
+    #[arg(long = "delay", env = "RUSTY_SPIDER_DELAY", value_name = "MS",
+          help = "Time between page requests to the same host [default: 500, or the profile's]")]
+    delay_ms: Option<u64>,
+    #[arg(long, env = "RUSTY_SPIDER_ADAPTIVE_DELAY",
+          help = "Also lengthen a host's delay when its responses slow down or its server errors pile up")]
+    adaptive_delay: bool,
+    #[arg(long = "config", env = "RUSTY_SPIDER_CONFIG", value_name = "FILE",
+          help = "TOML file with crawl settings; the command line wins, and some can be changed mid-crawl")]
+    config_file: Option<PathBuf>,
+    #[arg(long, env = "RUSTY_SPIDER_NETWORK_FAILURE_THRESHOLD", value_name = "N", default_value_t = DEFAULT_NETWORK_FAILURE_THRESHOLD,
+          help = "Connection failures in a row that pause the crawl")]
+    network_failure_threshold: u32,
+    #[arg(long = "network-probe-interval", env = "RUSTY_SPIDER_NETWORK_PROBE_INTERVAL", value_name = "SECS", default_value_t = DEFAULT_NETWORK_PROBE_SECS,
+          help = "How often to check whether the network is back")]
+    network_probe_secs: u64,
+    #[arg(long, env = "RUSTY_SPIDER_BREAKER_THRESHOLD", value_name = "N",
+          help = "Failed requests in a row that hold a host's requests for a cool-down (0 = never) [default: 5]")]
+    breaker_threshold: Option<u32>,
+    #[arg(long = "breaker-cooldown", env = "RUSTY_SPIDER_BREAKER_COOLDOWN", value_name = "SECS",
+          help = "How long a host's requests are held once its circuit opens [default: 60]")]
+    breaker_cooldown_secs: Option<u64>,
+    #[arg(long, env = "RUSTY_SPIDER_RETRIES", value_name = "N",
+          help = "Times a request is retried after a 5xx or no response (0 = never) [default: 3]")]
+    retries: Option<u32>,
+    #[arg(long = "retry-backoff", env = "RUSTY_SPIDER_RETRY_BACKOFF", value_name = "MS",
+          help = "Wait before the first retry, doubling for each one after, with jitter [default: 500]")]
+    retry_backoff_ms: Option<u64>,
+    #[arg(long = "retry", env = "RUSTY_SPIDER_RETRY", value_name = "FAILURE=N[/MS]", value_delimiter = ',',
+          help = "Retries, and the first backoff, for timeout, server, connection or tls failures (repeatable)")]
+    retry_rules: Vec<RetryRule>,
+    #[arg(long = "timeout", env = "RUSTY_SPIDER_TIMEOUT", value_name = "SECS",
+          help = "Give up on a request whose whole response hasn't arrived in this long (0 = never) [default: 60]")]
+    timeout_secs: Option<u64>,
+    #[arg(long = "connect-timeout", env = "RUSTY_SPIDER_CONNECT_TIMEOUT", value_name = "SECS",
+          help = "Give up on a connection that isn't made in this long [default: 10]")]
+    connect_timeout_secs: Option<u64>,
+    #[arg(long, env = "RUSTY_SPIDER_LEARN_BLACKLIST",
+          help = "Stop requesting from hosts that keep timing out or answering 403, and list them in <output>/blacklist.txt")]
+    learn_blacklist: bool,
+    #[arg(long, env = "RUSTY_SPIDER_BLACKLIST", value_name = "FILE",
+          help = "Never request from the hosts in this file, one per line (as --learn-blacklist writes it)")]
+    blacklist: Option<PathBuf>,
+    #[arg(long, env = "RUSTY_SPIDER_MAX_ERROR_RATE", value_name = "PERCENT", value_parser = parse_percent,
+          help = "Abort a job once more than this share of its pages and images fail")]
+    max_error_rate: Option<f64>,
+    #[arg(long, env = "RUSTY_SPIDER_MAX_BYTES", value_name = "SIZE", value_parser = parse_size,
+          help = "Stop downloading once saved images add up to this many bytes (K, M and G suffixes allowed)")]
+    max_bytes: Option<u64>,
+    #[arg(long, env = "RUSTY_SPIDER_MAX_BANDWIDTH", value_name = "SIZE", value_parser = parse_size,
+          help = "Read at most this many bytes per second, across every job (K, M and G suffixes allowed)")]
+    max_bandwidth: Option<u64>,
+    #[arg(long, env = "RUSTY_SPIDER_MAX_PAGE_SIZE", value_name = "SIZE", value_parser = parse_size,
+          help = "Give up on pages and other text responses larger than this (K, M and G suffixes allowed) [default: 5M]")]
+    max_page_size: Option<u64>,
+    #[arg(long, env = "RUSTY_SPIDER_DOWNLOAD_ORDER", value_name = "ORDER", conflicts_with_all = ["interactive", "estimate"],
+          help = "Crawl first, then download largest-first or smallest-first by HEAD Content-Length")]
+    download_order: Option<DownloadOrder>,
+    #[arg(long, env = "RUSTY_SPIDER_FORMATS", value_name = "FORMATS", value_delimiter = ',',
+          help = "Only keep images in these formats: jpg, gif, png, webp, avif, bmp, tiff [default: all]")]
+    formats: Vec<ImageType>,
+    #[arg(long, env = "RUSTY_SPIDER_MIN_WIDTH", value_name = "PX", help = "Skip images narrower than this")]
+    min_width: Option<u32>,
+    #[arg(long, env = "RUSTY_SPIDER_MIN_HEIGHT", value_name = "PX", help = "Skip images shorter than this")]
+    min_height: Option<u32>,
+    #[arg(long, env = "RUSTY_SPIDER_LAYOUT", value_name = "LAYOUT",
+          help = "standard (format/domain/size, the default) or date (YYYY/MM from EXIF, else Last-Modified)")]
+    layout: Option<Layout>,
+    #[arg(long, env = "RUSTY_SPIDER_HARDLINK_DUPLICATES",
+          help = "Hard-link images whose bytes were already saved under another URL, instead of skipping them")]
+    hardlink_duplicates: bool,
+    #[arg(long, env = "RUSTY_SPIDER_DEDUPE", value_name = "MODE",
+          help = "exact (identical bytes, the default) or perceptual (only the largest of images that look alike)")]
+    dedupe: Option<Dedupe>,
+    #[arg(long, env = "RUSTY_SPIDER_WARC", help = "Record every response the crawl reads in <output>/crawl.warc")]
+    warc: bool,
+    #[arg(long, env = "RUSTY_SPIDER_WACZ", value_name = "BOOL", num_args = 0..=1, require_equals = true,
+          default_missing_value = "true", help = "Package the recorded responses as <output>/crawl.wacz for web archive viewers")]
+    wacz: Option<bool>,
+    #[arg(long, env = "RUSTY_SPIDER_QUARANTINE", help = "Keep downloads that would be dropped in <output>/quarantine, with the reason")]
+    quarantine: bool,
+    #[arg(long, env = "RUSTY_SPIDER_SKIP_LOG", help = "List every page and image left alone in <output>/skipped.jsonl, with the reason")]
+    skip_log: bool,
+    #[arg(long, env = "RUSTY_SPIDER_SIGN_KEY", value_name = "FILE",
+          help = "Sign the manifest with this ed25519 private key (PKCS#8 PEM)")]
+    sign_key: Option<PathBuf>,
+    #[arg(long, env = "RUSTY_SPIDER_EXEC_PER_IMAGE", value_name = "CMD",
+          help = "Run this shell command after each image is saved, with {path}, {url} and {sha256} filled in")]
+    exec_per_image: Option<String>,
+    #[arg(long, env = "RUSTY_SPIDER_EXEC_CONCURRENCY", value_name = "N", value_parser = parse_count,
+          requires = "exec_per_image", help = "--exec-per-image commands running at once [default: 2]")]
+    exec_concurrency: Option<usize>,
+    #[arg(long, env = "RUSTY_SPIDER_INTERACTIVE", help = "List what the crawl found and choose which groups to download")]
+    interactive: bool,
+    #[arg(long, env = "RUSTY_SPIDER_ESTIMATE", value_name = "BOOL", num_args = 0..=1, require_equals = true,
+          default_missing_value = "true", conflicts_with = "interactive",
+          help = "Only HEAD images and report expected count and bytes per size bucket")]
+    estimate: Option<bool>,
+    #[arg(long, env = "RUSTY_SPIDER_METADATA_ONLY", conflicts_with_all = ["interactive", "estimate", "download_order"],
+          help = "Catalogue each image's format, dimensions, size and referring pages without saving it")]
+    metadata_only: bool,
+    #[arg(long, env = "RUSTY_SPIDER_ACTIVE_HOURS", value_name = "HH:MM-HH:MM", help = "Only crawl during this daily local-time window, e.g. 22:00-06:00")]
+    active_hours: Option<ActiveHours>,
+    #[arg(long = "checkpoint-interval", env = "RUSTY_SPIDER_CHECKPOINT_INTERVAL", value_name = "MINUTES",
+          help = "Snapshot crawl progress to <output>/.state periodically")]
+    checkpoint_minutes: Option<u64>,
+    #[arg(long, env = "RUSTY_SPIDER_CHECKPOINT_KEEP", value_name = "N", default_value_t = DEFAULT_CHECKPOINT_KEEP,
+          help = "Number of snapshots to retain per job")]
+    checkpoint_keep: usize,
+    #[arg(long, env = "RUSTY_SPIDER_RESUME",
+          help = "Carry on from where an interrupted crawl into the same output directory stopped")]
+    resume: bool,
+    #[arg(long, env = "RUSTY_SPIDER_CONTAINER", conflicts_with = "interactive",
+          help = "Run as a container job: serve status on 0.0.0.0:8080, write the exit status to /dev/termination-log")]
+    container: bool,
+    #[arg(long, env = "RUSTY_SPIDER_DAEMON", conflicts_with_all = ["interactive", "estimate", "download_order"],
+          help = "Keep running once the crawl is done, crawling pages POSTed to /crawl on the status address \
+                  (default 127.0.0.1:8080, even with --container, as /crawl is unauthenticated)")]
+    daemon: bool,
+    #[arg(long, env = "RUSTY_SPIDER_STATUS_ADDR", value_name = "ADDR",
+          help = "Serve /healthz and /progress on this address, e.g. 127.0.0.1:8080")]
+    status_addr: Option<SocketAddr>,
+    #[arg(long, env = "RUSTY_SPIDER_STATUS_FILE", value_name = "FILE", help = "Write how the run ended to this file as JSON")]
+    status_file: Option<PathBuf>,
+}
+
+pub struct Config {
+    pub command: Command,
+    // Start URLs, one job each, or a jobs file
+    pub start_urls: Vec<String>,
+    pub jobs_file: Option<PathBuf>,
+    pub output_dir: PathBuf,
+    // Lowercased names of query parameters removed before dedup and fetching
+    pub strip_params: Vec<String>,
+    // Treat `www.example.com` and `example.com` as one site when they redirect
+    // to or declare each other canonical
+    pub unify_hosts: bool,
+    pub scope: CrawlScope,
+    // Images may come from anywhere; only pages are kept to `scope`
+    pub external_images: bool,
+    // Strip resizing parameters from image URLs, except on these hosts
+    pub original_images: bool,
+    pub keep_image_params: Vec<String>,
+    // `<img>` attributes holding its URL, in priority order, and the sites
+    // that need their own order, by host
+    pub image_attrs: Vec<String>,
+    pub site_image_attrs: BTreeMap<String, Vec<String>>,
+    pub https: HttpsPolicy,
+    pub redirect_scope: RedirectScope,
+    pub max_redirects: usize,
+    // None leaves the choice to reqwest (native-tls when both are built in)
+    pub tls: Option<TlsBackend>,
+    // Where requests go instead of straight to the site, credentials
+    // included; None leaves it to the HTTP_PROXY and HTTPS_PROXY variables
+    pub proxy: Option<Url>,
+    // Fetch same-site stylesheets to find background and sprite images
+    pub follow_stylesheets: bool,
+    // Skip what robots.txt disallows and wait its Crawl-delay between pages
+    pub honor_robots: bool,
+    // Take images from `noimageindex` pages anyway; `honor_robots` off does too
+    pub ignore_noimageindex: bool,
+    // Also fetch the `<link rel="amphtml">` variant of each page for its images
+    pub follow_amp: bool,
+    // Crawl same-site `<iframe>` documents as if they were linked
+    pub follow_iframes: bool,
+    // Who runs the crawl, for site operators: a From header, and a page
+    // about the crawl named in the User-Agent
+    pub from: Option<String>,
+    pub contact_url: Option<Url>,
+    // Replaces the User-Agent built from the name and version (and the
+    // contact URL, or else the repository)
+    pub user_agent: Option<String>,
+    // Sent as Accept-Language, and with `follow_hreflang` the languages
+    // whose `<link rel="alternate" hreflang>` versions of a page are crawled
+    pub accept_language: Option<String>,
+    pub follow_hreflang: bool,
+    // Embedded `data:` images smaller than this are placeholders, not content
+    pub min_data_uri_size: usize,
+    // Candidates taken from `srcset` on `<img>` and `<picture>` sources
+    pub srcset: Srcset,
+    // Settings that can also come from the config file are only `Some` when
+    // given on the command line, so they can take precedence over it
+    pub mixed_content: Option<MixedContentPolicy>,
+    pub delay: Option<Duration>,
+    // Pages in progress at once, each fetching its images one at a time
+    pub concurrency: Option<usize>,
+    // The delay and concurrency when neither the command line nor the file
+    // sets them
+    pub default_delay: Duration,
+    pub default_concurrency: usize,
+    // Back off from hosts that slow down or fail, not only ones that say so
+    pub adaptive_delay: bool,
+    pub config_file: Option<PathBuf>,
+    // Consecutive connection failures after which the network is considered down
+    pub network_failure_threshold: u32,
+    pub network_probe_interval: Duration,
+    // Failures in a row after which requests to a host are held for the cooldown
+    pub breaker_threshold: u32,
+    pub breaker_cooldown: Duration,
+    // Retries of a request that failed in a way that may pass, and the
+    // wait before the first of them
+    pub retries: u32,
+    pub retry_backoff: Duration,
+    // The kinds of failure retried some other way
+    pub retry_rules: Vec<RetryRule>,
+    // Limits on a single request, so a hung server can't hold a worker
+    // forever; None for no limit on the whole response
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Duration,
+    // Hosts left alone: listed in a file, and learned from failures
+    pub blacklist: Option<PathBuf>,
+    pub learn_blacklist: bool,
+    // Failed URLs are recorded and skipped; past this fraction of attempts
+    // the job gives up
+    pub max_error_rate: Option<f64>,
+    // Only send requests during this daily window of local time
+    pub active_hours: Option<ActiveHours>,
+    // Saved image bytes after which nothing more is downloaded
+    pub max_bytes: Option<u64>,
+    // Bytes per second all jobs together may read
+    pub max_bandwidth: Option<u64>,
+    // Pages, stylesheets and manifests are read into memory, so a response
+    // that streams on and on is cut off at this many bytes
+    pub max_page_size: u64,
+    pub layout: Layout,
+    // Images with the same bytes as one already saved are only listed as its
+    // aliases; with this they're also hard-linked where they'd have gone
+    pub hardlink_duplicates: bool,
+    // With `Perceptual`, a resized or recompressed copy of an image already
+    // saved is dropped, or replaces it if it's larger
+    pub dedupe: Dedupe,
+    // Record responses in a WARC file, and bundle it as a WACZ at the end
+    pub warc: bool,
+    pub wacz: bool,
+    // Image formats that are saved; the rest are dropped (or quarantined)
+    pub formats: Vec<ImageType>,
+    // Images smaller than this in either direction are skipped, found out
+    // from their first bytes where possible
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    // Set aside unreadable, unwanted and truncated downloads instead of dropping them
+    pub quarantine: bool,
+    // Log each URL skipped, not just count them by reason
+    pub skip_log: bool,
+    // ed25519 key to sign each manifest with, for provenance
+    pub sign_key: Option<PathBuf>,
+    // A shell command for each saved image, and how many may run at once
+    pub exec_per_image: Option<String>,
+    pub exec_concurrency: usize,
+    // Sent with every request, the config file's first so the command
+    // line's win
+    pub headers: Vec<(String, String)>,
+    // Cookies to start the crawl's cookie jar with
+    pub cookie_file: Option<PathBuf>,
+    // A form to log in with before crawling
+    pub login: Option<LoginSettings>,
+    // Discover images first and download them in this order
+    pub download_order: Option<DownloadOrder>,
+    // Discover images first and download the groups the user approves
+    pub interactive: bool,
+    // Discover and size images without downloading any
+    pub estimate: bool,
+    // List images in the manifest's inventory from the first bytes of each
+    // instead of saving them
+    pub metadata_only: bool,
+    // Snapshot crawl progress this often, keeping the newest few
+    pub checkpoint_interval: Option<Duration>,
+    pub checkpoint_keep: usize,
+    // Start from the frontier, visited set and records an interrupted run
+    // left behind instead of from the seed
+    pub resume: bool,
+    // Running as a container job: a stopped crawl exits non-zero so it
+    // isn't taken for a finished one
+    pub container: bool,
+    // Stay up after the crawl and take pages to crawl from `POST /crawl`
+    pub daemon: bool,
+    pub status_addr: Option<SocketAddr>,
+    pub status_file: Option<PathBuf>,
+    // Links followed from the seed after which pages aren't crawled further
+    pub max_depth: Option<usize>,
+    // Install Ctrl-C, SIGTERM and SIGUSR handlers and read keyboard controls,
+    // as the command line does; an embedding service keeps its own
+    pub handle_signals: bool,
+}
+
+// What a crawl does when nothing is configured, for the `Crawler` builder
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            command: Command::Crawl,
+            start_urls: Vec::new(),
+            jobs_file: None,
+            output_dir: PathBuf::from(DEFAULT_OUTPUT_DIR),
+            strip_params: DEFAULT_STRIP_PARAMS.iter().map(|p| p.to_string()).collect(),
+            unify_hosts: true,
+            scope: CrawlScope::Origin,
+            external_images: false,
+            original_images: false,
+            keep_image_params: Vec::new(),
+            image_attrs: DEFAULT_IMAGE_ATTRS.iter().map(|attr| attr.to_string()).collect(),
+            site_image_attrs: BTreeMap::new(),
+            https: HttpsPolicy::Keep,
+            redirect_scope: RedirectScope::Same,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            tls: None,
+            proxy: None,
+            follow_stylesheets: true,
+            honor_robots: true,
+            ignore_noimageindex: false,
+            follow_amp: false,
+            follow_iframes: false,
+            from: None,
+            contact_url: None,
+            user_agent: None,
+            accept_language: None,
+            follow_hreflang: false,
+            min_data_uri_size: DEFAULT_MIN_DATA_URI_SIZE,
+            srcset: Srcset::Largest,
+            mixed_content: None,
+            delay: None,
+            adaptive_delay: false,
+            concurrency: None,
+            default_delay: DEFAULT_DELAY,
+            default_concurrency: DEFAULT_CONCURRENCY,
+            config_file: None,
+            network_failure_threshold: DEFAULT_NETWORK_FAILURE_THRESHOLD,
+            network_probe_interval: Duration::from_secs(DEFAULT_NETWORK_PROBE_SECS),
+            breaker_threshold: DEFAULT_BREAKER_THRESHOLD,
+            breaker_cooldown: DEFAULT_BREAKER_COOLDOWN,
+            retries: DEFAULT_RETRIES,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+            retry_rules: Vec::new(),
+            timeout: Some(DEFAULT_TIMEOUT),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            blacklist: None,
+            learn_blacklist: false,
+            max_error_rate: None,
+            active_hours: None,
+            max_bytes: None,
+            max_bandwidth: None,
+            max_page_size: DEFAULT_MAX_PAGE_SIZE,
+            layout: Layout::Standard,
+            hardlink_duplicates: false,
+            dedupe: Dedupe::Exact,
+            warc: false,
+            wacz: false,
+            formats: ImageType::ALL.to_vec(),
+            min_width: None,
+            min_height: None,
+            quarantine: false,
+            skip_log: false,
+            sign_key: None,
+            exec_per_image: None,
+            exec_concurrency: DEFAULT_EXEC_CONCURRENCY,
+            headers: Vec::new(),
+            cookie_file: None,
+            login: None,
+            download_order: None,
+            interactive: false,
+            estimate: false,
+            metadata_only: false,
+            checkpoint_interval: None,
+            checkpoint_keep: DEFAULT_CHECKPOINT_KEEP,
+            resume: false,
+            container: false,
+            daemon: false,
+            status_addr: None,
+            status_file: None,
+            max_depth: None,
+            handle_signals: false,
+        }
+    }
+}
+
+impl Config {
+    // Options from the command line (or environment) first, then the
+    // `--config` file, then the profile, then the defaults
+    pub fn new(
+        command: Command,
+        mut start_urls: Vec<String>,
+        jobs_file: Option<PathBuf>,
+        output_dir: Option<PathBuf>,
+        options: CrawlOptions,
+    ) -> Result<Config> {
+        let file = match &options.config_file {
+            Some(path) => FileSettings::load(path)?,
+            None => FileSettings::default(),
+        };
+        if start_urls.is_empty() && jobs_file.is_none() {
+            start_urls = file.urls.clone();
+        }
+        if file.concurrency == Some(0) {
+            bail!("concurrency in the config file must be at least 1");
+        }
+        let mut proxy = options.proxy.or(file.proxy);
+        if let Some(auth) = options.proxy_auth.or(file.proxy_auth) {
+            let Some(proxy) = &mut proxy else { bail!("proxy_auth needs a proxy") };
+            let (user, password) = auth.split_once(':').unwrap_or((&auth, ""));
+            if proxy.set_username(user).is_err() || proxy.set_password(Some(password)).is_err() {
+                bail!("The proxy URL can't hold credentials");
+            }
+        }
+        if file.exec_concurrency == Some(0) {
+            bail!("exec_concurrency in the config file must be at least 1");
+        }
+        if let Some(from) = &file.from {
+            parse_email(from).map_err(|e| anyhow::anyhow!("from in the config file {}", e))?;
+        }
+        if file.follow_hreflang && options.accept_language.is_none() && file.accept_language.is_none() {
+            bail!("follow_hreflang in the config file needs accept_language");
+        }
+        if file.max_error_rate.is_some_and(|percent| !(0.0..=100.0).contains(&percent)) {
+            bail!("max_error_rate in the config file must be a percentage between 0 and 100");
+        }
+        if let Some(login) = &file.login {
+            login.check()?;
+        }
+        if let Some((site, _)) = file.site_image_attrs.iter().find(|(_, attrs)| attrs.is_empty()) {
+            bail!("site_image_attrs for {} in the config file needs at least one attribute", site);
+        }
+
+        let mut strip_params: Vec<String> = options.strip_params.iter().map(|p| p.to_ascii_lowercase()).collect();
+        if !options.no_default_strip_params {
+            strip_params.extend(DEFAULT_STRIP_PARAMS.iter().map(|p| p.to_string()));
+        }
+        let mut keep_image_params = options.keep_image_params;
+        keep_image_params.extend(file.keep_image_params.iter().cloned());
+
+        let preset = options.profile.or(file.profile).map(Preset::settings).unwrap_or_default();
+        // Explicit choices of what to do with discovered images beat the preset's
+        let estimate = options.estimate.unwrap_or_else(|| {
+            preset.estimate.unwrap_or(false) && !options.interactive && options.download_order.is_none() && !options.metadata_only
+        });
+
+        let https = if options.https_only {
+            HttpsPolicy::Strict
+        } else if options.prefer_https {
+            HttpsPolicy::Prefer
+        } else {
+            HttpsPolicy::Keep
+        };
+
+        Ok(Config {
+            command,
+            start_urls,
+            jobs_file,
+            output_dir: output_dir.or(file.output).unwrap_or_else(|| PathBuf::from(DEFAULT_OUTPUT_DIR)),
+            strip_params,
+            unify_hosts: !(options.no_unify_hosts || file.no_unify_hosts),
+            scope: options.scope.or(file.scope).unwrap_or(CrawlScope::Origin),
+            external_images: options.external_images || file.external_images,
+            original_images: options.original_images || file.original_images,
+            keep_image_params: keep_image_params.iter().map(|host| host.to_ascii_lowercase()).collect(),
+            image_attrs: match (options.image_attrs.is_empty(), file.image_attrs.is_empty()) {
+                (false, _) => options.image_attrs,
+                (true, false) => file.image_attrs,
+                (true, true) => DEFAULT_IMAGE_ATTRS.iter().map(|attr| attr.to_string()).collect(),
+            }
+            .iter()
+            .map(|attr| attr.trim().to_ascii_lowercase())
+            .collect(),
+            site_image_attrs: file
+                .site_image_attrs
+                .iter()
+                .map(|(site, attrs)| {
+                    (site.to_ascii_lowercase(), attrs.iter().map(|attr| attr.trim().to_ascii_lowercase()).collect())
+                })
+                .collect(),
+            https,
+            redirect_scope: options.redirect_scope.or(file.redirect_scope).unwrap_or(RedirectScope::Same),
+            max_redirects: options.max_redirects.or(file.max_redirects).unwrap_or(DEFAULT_MAX_REDIRECTS),
+            tls: options.tls.or(file.tls),
+            proxy,
+            follow_stylesheets: !(options.no_stylesheets || file.no_stylesheets),
+            honor_robots: !(options.ignore_robots || file.ignore_robots),
+            ignore_noimageindex: options.ignore_noimageindex || file.ignore_noimageindex,
+            follow_amp: options.follow_amp.or(file.follow_amp).or(preset.follow_amp).unwrap_or(false),
+            follow_iframes: options.follow_iframes.or(file.follow_iframes).or(preset.follow_iframes).unwrap_or(false),
+            from: options.from.or(file.from),
+            contact_url: options.contact_url.or(file.contact_url),
+            user_agent: options.user_agent.or(file.user_agent),
+            accept_language: options.accept_language.or(file.accept_language),
+            follow_hreflang: options.follow_hreflang || file.follow_hreflang,
+            min_data_uri_size: options.min_data_uri_size.or(file.min_data_uri_size).unwrap_or(DEFAULT_MIN_DATA_URI_SIZE),
+            srcset: options.srcset.or(file.srcset).unwrap_or(Srcset::Largest),
+            mixed_content: options.mixed_content,
+            delay: options.delay_ms.map(Duration::from_millis),
+            adaptive_delay: options.adaptive_delay || file.adaptive_delay,
+            concurrency: options.concurrency,
+            default_delay: preset.delay.unwrap_or(DEFAULT_DELAY),
+            default_concurrency: preset.concurrency.unwrap_or(DEFAULT_CONCURRENCY),
+            config_file: options.config_file,
+            network_failure_threshold: options.network_failure_threshold,
+            network_probe_interval: Duration::from_secs(options.network_probe_secs),
+            breaker_threshold: options
+                .breaker_threshold
+                .or(preset.breaker_threshold)
+                .unwrap_or(DEFAULT_BREAKER_THRESHOLD),
+            breaker_cooldown: options
+                .breaker_cooldown_secs
+                .map(Duration::from_secs)
+                .or(preset.breaker_cooldown)
+                .unwrap_or(DEFAULT_BREAKER_COOLDOWN),
+            retries: options.retries.or(file.retries).unwrap_or(DEFAULT_RETRIES),
+            retry_backoff: options
+                .retry_backoff_ms
+                .or(file.retry_backoff_ms)
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_RETRY_BACKOFF),
+            retry_rules: if options.retry_rules.is_empty() { file.retry } else { options.retry_rules },
+            timeout: match options.timeout_secs.or(file.timeout_secs) {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => Some(DEFAULT_TIMEOUT),
+            },
+            connect_timeout: options
+                .connect_timeout_secs
+                .or(file.connect_timeout_secs)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            blacklist: options.blacklist.or(file.blacklist),
+            learn_blacklist: options.learn_blacklist || file.learn_blacklist,
+            max_error_rate: options
+                .max_error_rate
+                .or(file.max_error_rate)
+                .map(|percent| percent / 100.0)
+                .or(preset.max_error_rate),
+            active_hours: options.active_hours.or(file.active_hours),
+            max_bytes: options.max_bytes.or(file.max_bytes),
+            max_bandwidth: options.max_bandwidth.or(file.max_bandwidth),
+            max_page_size: options.max_page_size.or(file.max_page_size).unwrap_or(DEFAULT_MAX_PAGE_SIZE),
+            download_order: options.download_order,
+            layout: options.layout.or(file.layout).unwrap_or(Layout::Standard),
+            hardlink_duplicates: options.hardlink_duplicates || file.hardlink_duplicates,
+            dedupe: options.dedupe.or(file.dedupe).unwrap_or(Dedupe::Exact),
+            warc: options.warc || file.warc,
+            wacz: options.wacz.or(file.wacz).or(preset.wacz).unwrap_or(false),
+            formats: match (options.formats.is_empty(), file.formats.is_empty()) {
+                (false, _) => options.formats,
+                (true, false) => file.formats,
+                (true, true) => preset.formats.unwrap_or_else(|| ImageType::ALL.to_vec()),
+            },
+            min_width: options.min_width.or(file.min_width),
+            min_height: options.min_height.or(file.min_height),
+            quarantine: options.quarantine || file.quarantine,
+            skip_log: options.skip_log || file.skip_log,
+            sign_key: options.sign_key.or(file.sign_key),
+            exec_per_image: options.exec_per_image.or(file.exec_per_image),
+            exec_concurrency: options
+                .exec_concurrency
+                .or(file.exec_concurrency)
+                .unwrap_or(DEFAULT_EXEC_CONCURRENCY),
+            headers: file.headers.into_iter().chain(options.headers).collect(),
+            cookie_file: options.cookie_file.or(file.cookie_file),
+            login: file.login,
+            interactive: options.interactive,
+            estimate,
+            metadata_only: options.metadata_only,
+            checkpoint_interval: options
+                .checkpoint_minutes
+                .map(|minutes| Duration::from_secs(minutes * 60))
+                .or(preset.checkpoint_interval),
+            checkpoint_keep: options.checkpoint_keep,
+            resume: options.resume,
+            container: options.container,
+            daemon: options.daemon,
+            status_addr: options.status_addr.or(match (options.daemon, options.container) {
+                (true, _) => Some(DAEMON_STATUS_ADDR),
+                (false, true) => Some(DEFAULT_STATUS_ADDR),
+                (false, false) => None,
+            }),
+            status_file: options.status_file.or_else(|| options.container.then(|| PathBuf::from(TERMINATION_LOG))),
+            max_depth: options.max_depth.or(file.max_depth),
+            handle_signals: true,
+        })
+    }
+
+    // What every request says it's from
+    pub fn user_agent(&self) -> String {
+        if let Some(user_agent) = &self.user_agent {
+            return user_agent.clone();
+        }
+        let about = self.contact_url.as_ref().map_or(env!("CARGO_PKG_REPOSITORY"), Url::as_str);
+        format!("{}/{} (+{})", ROBOTS_AGENT, env!("CARGO_PKG_VERSION"), about)
+    }
+
+    // How each kind of failure is retried: `--retry` where it names the
+    // kind, `--retries` and `--retry-backoff` otherwise
+    pub fn retry_policy(&self) -> Retries {
+        let default = Retry { attempts: self.retries, backoff: self.retry_backoff };
+        let mut retries = Retries::new(default);
+        for rule in &self.retry_rules {
+            retries.set(rule.failure, Retry { attempts: rule.attempts, backoff: rule.backoff.unwrap_or(default.backoff) });
+        }
+        retries
+    }
+
+    // The name robots.txt groups are matched against: the User-Agent's
+    // product token, so a crawl that calls itself something else also gets
+    // the rules for that name
+    pub fn robots_agent(&self) -> String {
+        let user_agent = self.user_agent();
+        let token = user_agent.split(['/', ' ']).next().unwrap_or_default();
+        if token.is_empty() {
+            ROBOTS_AGENT.to_string()
+        } else {
+            token.to_ascii_lowercase()
+        }
+    }
+}
+
+fn parse_size(value: &str) -> Result<u64, String> {
+    let upper = value.to_ascii_uppercase();
+    let digits = upper.trim_end_matches('B');
+    let (number, shift) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 10),
+        Some('M') => (&digits[..digits.len() - 1], 20),
+        Some('G') => (&digits[..digits.len() - 1], 30),
+        _ => (digits, 0),
+    };
+    let number: u64 = number.trim().parse().map_err(|_| format!("{} isn't a size like 500M", value))?;
+    number.checked_mul(1 << shift).ok_or_else(|| format!("{} is too large", value))
+}
+
+fn parse_proxy(value: &str) -> Result<Url, String> {
+    let url = Url::parse(value).map_err(|e| format!("{} isn't a URL: {}", value, e))?;
+    match url.scheme() {
+        "http" | "https" | "socks5" | "socks5h" => Ok(url),
+        other => Err(format!("unknown proxy scheme '{}' (expected http, https, socks5 or socks5h)", other)),
+    }
+}
+
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    let Some((name, value)) = header.split_once(':') else {
+        return Err(format!("{} isn't a header (expected 'Name: value')", header));
+    };
+    let (name, value) = (name.trim(), value.trim());
+    if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+        return Err(format!("'{}' isn't a header name", name));
+    }
+    if reqwest::header::HeaderValue::from_str(value).is_err() {
+        return Err(format!("the value for {} has characters a header can't hold", name));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+fn parse_email(value: &str) -> Result<String, String> {
+    match value.split_once('@') {
+        Some((user, host)) if !user.is_empty() && !host.is_empty() => Ok(value.to_string()),
+        _ => Err(format!("{} isn't an email address", value)),
+    }
+}
+
+fn parse_count(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(count) => Ok(count),
+        Err(_) => Err(format!("{} isn't a number", value)),
+    }
+}
+
+fn parse_percent(value: &str) -> Result<f64, String> {
+    let percent: f64 = value.trim_end_matches('%').parse().map_err(|_| format!("{} isn't a number", value))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("{} isn't between 0 and 100", value));
+    }
+    Ok(percent)
+}
+
+// Settings read from the `--config` file. The first few are safe to change
+// mid-crawl and are picked up whenever the file changes.
+#[derive(Deserialize, Default, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FileSettings {
+    pub delay_ms: Option<u64>,
+    #[serde(default)]
+    pub strip_params: Vec<String>,
+    pub mixed_content: Option<MixedContentPolicy>,
+    pub concurrency: Option<usize>,
+
+    // The rest are only read at startup, named after the flags they stand
+    // in for. A job's own config file can only set the ones above.
+    #[serde(default)]
+    pub urls: Vec<String>,
+    pub output: Option<PathBuf>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub profile: Option<Preset>,
+    pub max_depth: Option<usize>,
+    #[serde(default, deserialize_with = "size")]
+    pub max_bytes: Option<u64>,
+    #[serde(default, deserialize_with = "size")]
+    pub max_bandwidth: Option<u64>,
+    #[serde(default, deserialize_with = "size")]
+    pub max_page_size: Option<u64>,
+    // A percentage, as on the command line
+    pub max_error_rate: Option<f64>,
+    #[serde(default)]
+    pub original_images: bool,
+    #[serde(default)]
+    pub keep_image_params: Vec<String>,
+    #[serde(default)]
+    pub image_attrs: Vec<String>,
+    #[serde(default)]
+    pub site_image_attrs: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub no_stylesheets: bool,
+    #[serde(default)]
+    pub ignore_robots: bool,
+    #[serde(default)]
+    pub ignore_noimageindex: bool,
+    pub follow_amp: Option<bool>,
+    pub follow_iframes: Option<bool>,
+    pub from: Option<String>,
+    pub contact_url: Option<Url>,
+    pub user_agent: Option<String>,
+    pub proxy: Option<Url>,
+    pub proxy_auth: Option<String>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub tls: Option<TlsBackend>,
+    pub accept_language: Option<String>,
+    #[serde(default)]
+    pub follow_hreflang: bool,
+    pub min_data_uri_size: Option<usize>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub srcset: Option<Srcset>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub scope: Option<CrawlScope>,
+    #[serde(default)]
+    pub no_unify_hosts: bool,
+    #[serde(default)]
+    pub external_images: bool,
+    #[serde(default, deserialize_with = "parsed")]
+    pub redirect_scope: Option<RedirectScope>,
+    pub max_redirects: Option<usize>,
+    #[serde(default, deserialize_with = "parsed_list")]
+    pub formats: Vec<ImageType>,
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    #[serde(default)]
+    pub adaptive_delay: bool,
+    pub retries: Option<u32>,
+    pub retry_backoff_ms: Option<u64>,
+    #[serde(default, deserialize_with = "parsed_list")]
+    pub retry: Vec<RetryRule>,
+    pub timeout_secs: Option<u64>,
+    pub connect_timeout_secs: Option<u64>,
+    pub blacklist: Option<PathBuf>,
+    #[serde(default)]
+    pub learn_blacklist: bool,
+    #[serde(default, deserialize_with = "parsed")]
+    pub layout: Option<Layout>,
+    #[serde(default)]
+    pub hardlink_duplicates: bool,
+    #[serde(default, deserialize_with = "parsed")]
+    pub dedupe: Option<Dedupe>,
+    #[serde(default)]
+    pub warc: bool,
+    pub wacz: Option<bool>,
+    #[serde(default)]
+    pub quarantine: bool,
+    #[serde(default)]
+    pub skip_log: bool,
+    pub sign_key: Option<PathBuf>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub active_hours: Option<ActiveHours>,
+    pub exec_per_image: Option<String>,
+    pub exec_concurrency: Option<usize>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub cookie_file: Option<PathBuf>,
+    pub login: Option<LoginSettings>,
+}
+
+// A setting spelled the way its flag takes it, e.g. `layout = "date"`
+fn parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: FromStr<Err = anyhow::Error>,
+{
+    let text = String::deserialize(deserializer)?;
+    text.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+// A list of settings spelled the way their flag takes them
+fn parsed_list<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: FromStr<Err = anyhow::Error>,
+{
+    let texts = Vec::<String>::deserialize(deserializer)?;
+    texts.iter().map(|text| text.parse().map_err(serde::de::Error::custom)).collect()
+}
+
+// A size in bytes, or with a suffix as on the command line: `"2G"`
+fn size<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(Some(bytes)),
+        Size::Text(text) => parse_size(&text).map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+// A job's own config file, which may only hold the live settings: anything
+// else would be read and then quietly ignored
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobFileSettings {
+    delay_ms: Option<u64>,
+    #[serde(default)]
+    strip_params: Vec<String>,
+    mixed_content: Option<MixedContentPolicy>,
+    concurrency: Option<usize>,
+}
+
+impl FileSettings {
+    pub fn load(path: &Path) -> Result<FileSettings> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    pub fn load_job(path: &Path) -> Result<FileSettings> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read job config file {}", path.display()))?;
+        let job: JobFileSettings = toml::from_str(&text).with_context(|| {
+            format!(
+                "Failed to parse job config file {} (it can only set delay_ms, strip_params, mixed_content and concurrency)",
+                path.display()
+            )
+        })?;
+        if job.concurrency == Some(0) {
+            bail!("concurrency in job config file {} must be at least 1", path.display());
+        }
+        Ok(FileSettings {
+            delay_ms: job.delay_ms,
+            strip_params: job.strip_params,
+            mixed_content: job.mixed_content,
+            concurrency: job.concurrency,
+            ..FileSettings::default()
+        })
+    }
+}
+
+pub fn print_completions(shell: clap_complete::Shell) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+}
@@ -0,0 +1,125 @@
+use crate::config::Config;
+use crate::verbosity::detail;
+use anyhow::{bail, Context, Result};
+use reqwest::cookie::Jar;
+use std::path::Path;
+use std::sync::Arc;
+use url::Url;
+
+// The run's cookie jar, started with `--cookie-file` if there is one. It's
+// kept for the whole run, so a session a site hands out sticks.
+pub fn jar(config: &Config) -> Result<Arc<Jar>> {
+    let jar = Jar::default();
+    if let Some(path) = &config.cookie_file {
+        let loaded = load(&jar, path)?;
+        detail!("Loaded {} cookies from {}", loaded, path.display());
+    }
+    Ok(Arc::new(jar))
+}
+
+// Add the cookies in a Netscape cookie file (`curl -c`, or a browser's
+// "cookies.txt" export) to `jar`, returning how many there were. Cookies
+// that have already expired are left out; the rest last the whole crawl.
+fn load(jar: &Jar, path: &Path) -> Result<usize> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read cookie file {}", path.display()))?;
+    let now = chrono::Utc::now().timestamp();
+    let mut loaded = 0;
+    for (number, line) in text.lines().enumerate() {
+        // curl marks HttpOnly cookies this way, rather than with a field
+        let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [domain, subdomains, path_prefix, secure, expires, name, value] = fields[..] else {
+            bail!("{}:{}: expected 7 tab-separated fields, not {}", path.display(), number + 1, fields.len());
+        };
+        let expires: i64 = expires
+            .parse()
+            .with_context(|| format!("{}:{}: {} isn't an expiry time", path.display(), number + 1, expires))?;
+        // 0 is a session cookie
+        if expires != 0 && expires < now {
+            continue;
+        }
+
+        let host = domain.trim_start_matches('.');
+        let mut cookie = format!("{}={}; Path={}", name, value, path_prefix);
+        if subdomains.eq_ignore_ascii_case("true") {
+            cookie.push_str(&format!("; Domain={}", host));
+        }
+        if secure.eq_ignore_ascii_case("true") {
+            cookie.push_str("; Secure");
+        }
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        let url = Url::parse(&format!("https://{}{}", host, path_prefix))
+            .with_context(|| format!("{}:{}: {} isn't a domain", path.display(), number + 1, domain))?;
+        jar.add_cookie_str(&cookie, &url);
+        loaded += 1;
+    }
+    Ok(loaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::cookie::CookieStore;
+
+    fn load_text(name: &str, text: &str) -> (Jar, Result<usize>) {
+        let path = std::env::temp_dir().join(format!("rusty_spider_cookies_{}_{}", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let jar = Jar::default();
+        let loaded = load(&jar, &path);
+        std::fs::remove_file(&path).unwrap();
+        (jar, loaded)
+    }
+
+    // The jar's `Cookie` header for `url`, in name order
+    fn cookies(jar: &Jar, url: &str) -> String {
+        let header = jar.cookies(&Url::parse(url).unwrap()).unwrap_or(reqwest::header::HeaderValue::from_static(""));
+        let mut pairs: Vec<&str> = header.to_str().unwrap().split("; ").filter(|pair| !pair.is_empty()).collect();
+        pairs.sort();
+        pairs.join("; ")
+    }
+
+    #[test]
+    fn skips_comments_and_reads_http_only_lines() {
+        let text = "# Netscape HTTP Cookie File\n\
+                    \n\
+                    #HttpOnly_example.com\tFALSE\t/\tFALSE\t0\tsession\tabc\n\
+                    example.com\tFALSE\t/\tFALSE\t0\ttheme\tdark\n";
+        let (jar, loaded) = load_text("comments", text);
+        assert_eq!(loaded.unwrap(), 2);
+        assert_eq!(cookies(&jar, "https://example.com/"), "session=abc; theme=dark");
+    }
+
+    #[test]
+    fn rejects_the_wrong_field_count() {
+        let (_, loaded) = load_text("fields", "example.com\tFALSE\t/\tFALSE\t0\tname\n");
+        assert!(loaded.unwrap_err().to_string().contains(":1: expected 7 tab-separated fields, not 6"));
+    }
+
+    #[test]
+    fn keeps_session_cookies_and_drops_expired_ones() {
+        let text = "example.com\tFALSE\t/\tFALSE\t0\tsession\tabc\n\
+                    example.com\tFALSE\t/\tFALSE\t1000\told\tgone\n\
+                    example.com\tFALSE\t/\tFALSE\t4102444800\tlater\tkept\n";
+        let (jar, loaded) = load_text("expiry", text);
+        assert_eq!(loaded.unwrap(), 2);
+        assert_eq!(cookies(&jar, "https://example.com/"), "later=kept; session=abc");
+    }
+
+    #[test]
+    fn domain_flag_covers_subdomains() {
+        let text = ".example.com\tTRUE\t/\tFALSE\t0\twide\t1\n\
+                    example.com\tFALSE\t/\tFALSE\t0\thost\t2\n";
+        let (jar, loaded) = load_text("domain", text);
+        assert_eq!(loaded.unwrap(), 2);
+        assert_eq!(cookies(&jar, "https://img.example.com/"), "wide=1");
+        assert_eq!(cookies(&jar, "https://example.com/"), "host=2; wide=1");
+    }
+}
@@ -0,0 +1,95 @@
+// Minimal CSS scanning: enough to find `url(...)` references and `@import`s
+// without pulling in a full CSS parser.
+
+// Extensions of url() targets worth treating as images. Fonts, cursors and
+// nested stylesheets are referenced the same way and must be left out.
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "gif", "png", "webp", "avif", "bmp", "tif", "tiff"];
+
+#[derive(Default)]
+pub struct CssRefs {
+    pub images: Vec<String>,
+    pub imports: Vec<String>,
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    out.push_str(rest);
+    out
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value)
+        .trim()
+}
+
+// The value of every `url(...)` in `css`, in order
+fn url_values(css: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let lower = css.to_ascii_lowercase();
+    let mut offset = 0;
+    while let Some(found) = lower[offset..].find("url(") {
+        let start = offset + found + 4;
+        let Some(len) = css[start..].find(')') else { break };
+        let value = unquote(&css[start..start + len]);
+        if !value.is_empty() {
+            values.push(value.to_string());
+        }
+        offset = start + len + 1;
+    }
+    values
+}
+
+// Whether a url() target names an image rather than a font, cursor or stylesheet
+pub fn is_image_reference(value: &str) -> bool {
+    if let Some(data) = value.strip_prefix("data:") {
+        return data.get(..6).is_some_and(|media_type| media_type.eq_ignore_ascii_case("image/"));
+    }
+    if value.starts_with('#') {
+        return false;
+    }
+    let path = value.split(['?', '#']).next().unwrap_or(value);
+    path.rsplit_once('.')
+        .is_some_and(|(_, ext)| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+// Image references and imported stylesheets in a stylesheet or style block
+pub fn extract(css: &str) -> CssRefs {
+    let css = strip_comments(css);
+    let mut refs = CssRefs::default();
+
+    // `@import "x.css";` and `@import url(x.css);`
+    let lower = css.to_ascii_lowercase();
+    let mut offset = 0;
+    while let Some(found) = lower[offset..].find("@import") {
+        let start = offset + found + "@import".len();
+        let end = css[start..].find(';').map_or(css.len(), |len| start + len);
+        let target = css[start..end].trim();
+        let target = if target.to_ascii_lowercase().starts_with("url(") {
+            target[4..].split(')').next().map(unquote).unwrap_or_default()
+        } else {
+            // Media queries may follow a quoted target
+            unquote(target.split_whitespace().next().unwrap_or_default())
+        };
+        if !target.is_empty() {
+            refs.imports.push(target.to_string());
+        }
+        offset = end;
+    }
+
+    // Imports written as url() end in .css, so they never count as images
+    refs.images = url_values(&css).into_iter().filter(|value| is_image_reference(value)).collect();
+    refs
+}
@@ -0,0 +1,44 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DAY: u64 = 24 * 60 * 60;
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Unix timestamp to YYYY-MM-DD (UTC), using Howard Hinnant's civil-from-days
+pub fn date(timestamp: u64) -> String {
+    let days = (timestamp / DAY) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Unix timestamp to YYYYMMDD-HHMMSS (UTC), for use in file names
+pub fn file_label(timestamp: u64) -> String {
+    let secs = timestamp % DAY;
+    format!(
+        "{}-{:02}{:02}{:02}",
+        date(timestamp).replace('-', ""),
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+// Unix timestamp to an ISO 8601 UTC time, as WARC and WACZ metadata use
+pub fn iso8601(timestamp: u64) -> String {
+    let secs = timestamp % DAY;
+    format!("{}T{:02}:{:02}:{:02}Z", date(timestamp), secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+// Unix timestamp to the 14-digit form web archive indexes sort by
+pub fn archive_timestamp(timestamp: u64) -> String {
+    file_label(timestamp).replace('-', "")
+}
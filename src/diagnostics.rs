@@ -0,0 +1,42 @@
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::time::sleep;
+
+const METRICS_INTERVAL: Duration = Duration::from_secs(30);
+
+// Serve tokio-console on its default port (6669) and log a runtime summary
+// periodically, for debugging hangs in the async pipeline
+pub fn init() {
+    console_subscriber::init();
+    tokio::spawn(report_metrics());
+    println!("tokio-console listening on 127.0.0.1:6669");
+}
+
+async fn report_metrics() {
+    let metrics = Handle::current().metrics();
+    let busy_durations = || -> Vec<Duration> {
+        (0..metrics.num_workers()).map(|worker| metrics.worker_total_busy_duration(worker)).collect()
+    };
+
+    let mut last_busy = busy_durations();
+    loop {
+        sleep(METRICS_INTERVAL).await;
+        let busy = busy_durations();
+        // A worker that was busy the whole interval never got back to its
+        // scheduler, which usually means blocking work on an async thread
+        let blocked = busy
+            .iter()
+            .zip(&last_busy)
+            .filter(|(now, before)| now.saturating_sub(**before) >= METRICS_INTERVAL * 95 / 100)
+            .count();
+        println!(
+            "[runtime] {} tasks alive, {} queued globally, {}/{} workers busy throughout the last {}s",
+            metrics.num_alive_tasks(),
+            metrics.global_queue_depth(),
+            blocked,
+            busy.len(),
+            METRICS_INTERVAL.as_secs()
+        );
+        last_busy = busy;
+    }
+}
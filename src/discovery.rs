@@ -0,0 +1,230 @@
+use crate::config::DownloadOrder;
+use crate::{download_or_record, get_size_category, header_value, save_or_record, send, CrawlState, FetchedImage};
+use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use reqwest::header;
+use std::collections::{BTreeMap, HashSet};
+use url::Url;
+
+// HEAD requests in flight at once while sizing discovered images
+const HEAD_CONCURRENCY: usize = 8;
+
+// An image found while crawling in discovery mode, not yet downloaded
+pub enum Candidate {
+    Remote { url: Url, size: Option<u64> },
+    // `data:` images are already in memory
+    Embedded(Box<FetchedImage>),
+}
+
+impl Candidate {
+    fn size(&self) -> Option<u64> {
+        match self {
+            Candidate::Remote { size, .. } => *size,
+            Candidate::Embedded(image) => Some(image.size),
+        }
+    }
+
+    fn host(&self) -> String {
+        let url = match self {
+            Candidate::Remote { url, .. } => url,
+            Candidate::Embedded(image) => image.embedded_in.as_ref().unwrap_or(&image.url),
+        };
+        url.host_str().unwrap_or("unknown").to_string()
+    }
+
+    fn key(&self) -> String {
+        match self {
+            Candidate::Remote { url, .. } => url.to_string(),
+            Candidate::Embedded(image) => image.url.to_string(),
+        }
+    }
+}
+
+// Candidates from one host in one size category, selected or skipped together
+pub struct Group {
+    pub host: String,
+    pub category: &'static str,
+    pub images: Vec<Candidate>,
+}
+
+impl Group {
+    pub fn known_bytes(&self) -> u64 {
+        self.images.iter().filter_map(Candidate::size).sum()
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+// The images the crawl discovered, sized with HEAD requests
+async fn sized_candidates(state: &CrawlState) -> Vec<Candidate> {
+    let urls: Vec<Url> = state.discovered.lock().await.drain(..).collect();
+    let mut candidates: Vec<Candidate> = stream::iter(urls)
+        .map(|url| async move {
+            let size = match send(state, || state.client.head(url.as_str())).await {
+                Ok(response) => header_value(&response, header::CONTENT_LENGTH).and_then(|len| len.parse().ok()),
+                Err(_) => None,
+            };
+            Candidate::Remote { url, size }
+        })
+        .buffered(HEAD_CONCURRENCY)
+        .collect()
+        .await;
+    candidates.extend(state.discovered_embedded.lock().await.drain(..).map(|image| Candidate::Embedded(Box::new(image))));
+    candidates
+}
+
+// Size the images the crawl discovered and group them by host and size category
+pub async fn discovered_groups(state: &CrawlState) -> Vec<Group> {
+    let candidates = sized_candidates(state).await;
+    let mut groups: BTreeMap<(String, usize), Group> = BTreeMap::new();
+    for candidate in candidates {
+        let category = candidate.size().map_or("unknown size", get_size_category);
+        let order = ["small", "medium", "large", "unknown size"].iter().position(|c| *c == category).unwrap_or(0);
+        let host = candidate.host();
+        groups
+            .entry((host.clone(), order))
+            .or_insert_with(|| Group { host, category, images: Vec::new() })
+            .images
+            .push(candidate);
+    }
+    groups.into_values().collect()
+}
+
+fn print_groups(groups: &[Group]) {
+    let total: usize = groups.iter().map(|group| group.images.len()).sum();
+    let bytes: u64 = groups.iter().map(Group::known_bytes).sum();
+    println!("\nDiscovered {} images ({} with known sizes) in {} groups:", total, format_size(bytes), groups.len());
+    for (i, group) in groups.iter().enumerate() {
+        println!(
+            "  [{}] {:<30} {:<13} {:>6} images {:>10}",
+            i + 1,
+            group.host,
+            group.category,
+            group.images.len(),
+            format_size(group.known_bytes())
+        );
+    }
+}
+
+// Ask which groups to skip. None means the user cancelled.
+async fn ask_deselected(count: usize) -> Result<Option<HashSet<usize>>> {
+    loop {
+        println!("Deselect groups by number (e.g. \"2 3\"), Enter to download the rest, or q to cancel:");
+        let line = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line).map(|read| (read > 0).then_some(line))
+        })
+        .await??;
+        let Some(line) = line else { return Ok(None) };
+        let line = line.trim();
+        if line.eq_ignore_ascii_case("q") {
+            return Ok(None);
+        }
+
+        let picked: Result<HashSet<usize>, _> = line.split([' ', ',']).filter(|s| !s.is_empty()).map(str::parse).collect();
+        match picked {
+            Ok(picked) if picked.iter().all(|n| (1..=count).contains(n)) => return Ok(Some(picked)),
+            _ => println!("Expected group numbers between 1 and {}", count),
+        }
+    }
+}
+
+// `--interactive`: show what the crawl discovered and download the groups
+// the user keeps
+pub async fn select_and_download(state: &CrawlState) -> Result<()> {
+    let groups = discovered_groups(state).await;
+    if groups.is_empty() {
+        println!("No images discovered");
+        return Ok(());
+    }
+    print_groups(&groups);
+
+    let deselected = ask_deselected(groups.len()).await?;
+    for (i, group) in groups.into_iter().enumerate() {
+        let keep = deselected.as_ref().is_some_and(|skipped| !skipped.contains(&(i + 1)));
+        for candidate in group.images {
+            if !keep {
+                // Skipped images shouldn't count as downloaded in the summary
+                state.downloaded_images.remove(&candidate.key());
+                continue;
+            }
+            match candidate {
+                Candidate::Remote { url, .. } => download_or_record(state, url, None).await,
+                Candidate::Embedded(image) => save_or_record(state, *image).await,
+            }
+        }
+    }
+    if deselected.is_none() {
+        println!("Cancelled; nothing downloaded");
+    }
+    Ok(())
+}
+
+// `--download-order`: download what the crawl discovered by size, so a
+// `--max-bytes` budget goes where it's wanted. Images that wouldn't fit in
+// what's left of the budget are passed over for ones that do, and images of
+// unknown size come last.
+pub async fn download_in_order(state: &CrawlState, order: DownloadOrder) {
+    let (mut known, unknown): (Vec<Candidate>, Vec<Candidate>) =
+        sized_candidates(state).await.into_iter().partition(|candidate| candidate.size().is_some());
+    known.sort_by_key(Candidate::size);
+    if order == DownloadOrder::LargestFirst {
+        known.reverse();
+    }
+    println!("\nDownloading {} discovered images", known.len() + unknown.len());
+
+    for candidate in known.into_iter().chain(unknown) {
+        let fits = match (state.bytes_left(), candidate.size()) {
+            (Some(left), Some(size)) => size <= left,
+            (Some(left), None) => left > 0,
+            (None, _) => true,
+        };
+        if !fits || state.cancel.is_cancelled() {
+            // Only what was actually saved counts in the summary
+            state.downloaded_images.remove(&candidate.key());
+            continue;
+        }
+        match candidate {
+            Candidate::Remote { url, .. } => download_or_record(state, url, None).await,
+            Candidate::Embedded(image) => save_or_record(state, *image).await,
+        }
+    }
+}
+
+// `--estimate`: report how much the crawl would download, per size category
+pub async fn print_estimate(state: &CrawlState) {
+    let pages = state.visited_urls.len();
+    let mut categories: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+    for group in discovered_groups(state).await {
+        let totals = categories.entry(group.category).or_default();
+        totals.0 += group.images.len();
+        totals.1 += group.known_bytes();
+    }
+
+    let images: usize = categories.values().map(|(count, _)| count).sum();
+    let bytes: u64 = categories.values().map(|(_, bytes)| bytes).sum();
+    let mut lines = vec![format!("\nEstimate for {}: {} images on {} pages", state.name, images, pages)];
+    for category in ["small", "medium", "large", "unknown size"] {
+        if let Some((count, bytes)) = categories.get(category) {
+            lines.push(format!("  {:<13} {:>6} images {:>10}", category, count, format_size(*bytes)));
+        }
+    }
+    lines.push(format!("  {:<13} {:>6} images {:>10}", "total", images, format_size(bytes)));
+    if let Some((count, _)) = categories.get("unknown size") {
+        lines.push(format!("  ({} images sent no Content-Length, so the total is a lower bound)", count));
+    }
+    println!("{}", lines.join("\n"));
+}
@@ -0,0 +1,51 @@
+// How much of a body the WHATWG sniffing rules look at
+const SNIFF_BYTES: usize = 1445;
+// How much of a generic binary is read to tell whether it's an image, well
+// past where any format keeps its signature
+pub const BINARY_SNIFF_BYTES: usize = 4 * 1024;
+
+// How a crawled URL is read, by the Content-Type it came back with. Links
+// lead to images, API responses and stylesheets as well as pages.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Handler {
+    // Parsed for images and links
+    Html,
+    // Saved like any other image
+    Image,
+    // A generic binary, saved as an image only if its first bytes are one
+    Binary,
+    // Scanned for strings that name images
+    Json,
+    // Scanned for `url()` references and `@import`s
+    Css,
+    // Anything else, e.g. PDFs and archives, is left alone
+    Ignore,
+}
+
+impl Handler {
+    pub fn for_content_type(content_type: Option<&str>) -> Handler {
+        // Servers that send no Content-Type are nearly always serving a page
+        let Some(content_type) = content_type else { return Handler::Html };
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "" | "text/html" | "application/xhtml+xml" => Handler::Html,
+            // Images served as generic binaries are common, but so are
+            // archives and installers
+            "application/octet-stream" => Handler::Binary,
+            "text/css" => Handler::Css,
+            "application/json" | "text/json" => Handler::Json,
+            mime if mime.starts_with("image/") => Handler::Image,
+            mime if mime.ends_with("+json") => Handler::Json,
+            _ => Handler::Ignore,
+        }
+    }
+}
+
+// Whether a body that came without a Content-Type is binary rather than
+// markup, going by the control bytes text never contains (the WHATWG "binary
+// data byte" rule)
+pub fn looks_binary(body: &[u8]) -> bool {
+    body.iter()
+        .take(SNIFF_BYTES)
+        .any(|&byte| matches!(byte, 0x00..=0x08 | 0x0B | 0x0E..=0x1A | 0x1C..=0x1F))
+}
@@ -0,0 +1,146 @@
+use percent_encoding::percent_decode_str;
+
+// Longest filename we'll take from a server, leaving room for the extension
+// and version suffixes within common 255-byte filesystem limits
+const MAX_FILENAME_LEN: usize = 200;
+// A longer "extension" is taken as part of the name when shortening it
+const MAX_EXTENSION_LEN: usize = 16;
+
+// The filename a `Content-Disposition` header suggests, made safe to use as
+// a single path component. The RFC 6266 `filename*` form (percent-encoded,
+// usually UTF-8) wins over plain `filename` when both are given.
+pub fn filename(header: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+    for param in split_params(header).into_iter().skip(1) {
+        let Some((name, value)) = param.split_once('=') else { continue };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "filename*" => extended = decode_extended(value),
+            "filename" => plain = Some(unquote(value)),
+            _ => {}
+        }
+    }
+    sanitize(&extended.or(plain)?)
+}
+
+// Split on `;` outside quoted strings
+fn split_params(header: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in header.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(&header[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    params.push(&header[start..]);
+    params
+}
+
+fn unquote(value: &str) -> String {
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return value.to_string();
+    };
+    let mut unquoted = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        unquoted.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+    }
+    unquoted
+}
+
+// `charset'language'percent-encoded-value`. Only UTF-8 (and its ASCII subset)
+// is decoded; anything else falls back to the plain `filename`.
+fn decode_extended(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let encoded = parts.next()?;
+    if !charset.eq_ignore_ascii_case("utf-8") && !charset.eq_ignore_ascii_case("us-ascii") {
+        return None;
+    }
+    percent_decode_str(encoded).decode_utf8().ok().map(|name| name.into_owned())
+}
+
+// Reduce a server-supplied name to something that can't escape the
+// directory it's saved in or trip up common filesystems: only the last path
+// component, no control or reserved characters, no leading dots. Long names
+// are cut short before their extension.
+fn sanitize(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let mut clean: String = name
+        .chars()
+        .map(|c| if c.is_control() || "<>:\"|?*".contains(c) { '_' } else { c })
+        .collect();
+    clean = clean.trim_start_matches(['.', ' ']).trim_end_matches(['.', ' ']).to_string();
+
+    if clean.len() > MAX_FILENAME_LEN {
+        let (stem, extension) = match clean.rfind('.') {
+            Some(dot) if clean.len() - dot <= MAX_EXTENSION_LEN => clean.split_at(dot),
+            _ => (clean.as_str(), ""),
+        };
+        let mut end = MAX_FILENAME_LEN - extension.len();
+        while !stem.is_char_boundary(end) {
+            end -= 1;
+        }
+        clean = format!("{}{}", stem[..end].trim_end_matches(['.', ' ']), extension);
+    }
+    (!clean.is_empty()).then_some(clean)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_only_the_last_path_component() {
+        assert_eq!(filename(r#"attachment; filename="../../etc/x""#).as_deref(), Some("x"));
+        assert_eq!(filename(r#"attachment; filename="..\\x""#).as_deref(), Some("x"));
+        assert_eq!(filename("attachment; filename=../"), None);
+    }
+
+    #[test]
+    fn strips_leading_dots_and_spaces() {
+        assert_eq!(filename(r#"attachment; filename=" .. .hidden.jpg ""#).as_deref(), Some("hidden.jpg"));
+        assert_eq!(filename("attachment; filename=..."), None);
+    }
+
+    #[test]
+    fn replaces_reserved_characters() {
+        assert_eq!(filename(r#"attachment; filename="a<b>c:d\"e|f?g*h.png""#).as_deref(), Some("a_b_c_d_e_f_g_h.png"));
+    }
+
+    #[test]
+    fn extended_filename_wins() {
+        let header = r#"attachment; filename="fallback.jpg"; filename*=UTF-8''caf%C3%A9.jpg"#;
+        assert_eq!(filename(header).as_deref(), Some("café.jpg"));
+        let header = r#"attachment; filename*=ISO-8859-1''caf%E9.jpg; filename="fallback.jpg""#;
+        assert_eq!(filename(header).as_deref(), Some("fallback.jpg"));
+    }
+
+    #[test]
+    fn quoted_values_may_contain_semicolons() {
+        let header = r#"attachment; filename="one; two.jpg"; size=10"#;
+        assert_eq!(filename(header).as_deref(), Some("one; two.jpg"));
+    }
+
+    #[test]
+    fn shortens_long_names_before_the_extension() {
+        let long = format!("{}.jpeg", "é".repeat(MAX_FILENAME_LEN));
+        let name = filename(&format!("attachment; filename=\"{}\"", long)).unwrap();
+        assert!(name.len() <= MAX_FILENAME_LEN);
+        assert!(name.ends_with("é.jpeg"));
+
+        let long = "x".repeat(MAX_FILENAME_LEN * 2);
+        assert_eq!(filename(&format!("attachment; filename={}", long)).unwrap().len(), MAX_FILENAME_LEN);
+    }
+}
@@ -0,0 +1,401 @@
+use crate::{fetch_image, process_page, record_error, save_image, save_or_record, CrawlState};
+use crate::verbosity::progress;
+use anyhow::{Context, Result};
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use url::Url;
+
+// How long an idle worker waits before checking the frontier again
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// A worker holds the pages it took for this long after it was last heard
+// from; past that it's taken for dead and they go back in the frontier
+const LEASE: Duration = Duration::from_secs(60);
+
+// A run's keys, cleared when the next run starts
+const RUN_KEYS: &[&str] = &["frontier", "visited", "images", "content", "aliases", "workers"];
+
+// Join the run in progress, or start the next one once the last has
+// finished, clearing what it left behind (the rest of KEYS). Scripts may
+// only touch the keys they're given, so the caller names the run it read
+// those from, and gets nil to try again if another worker moved it on.
+const JOIN_SCRIPT: &str = r#"
+local run = tonumber(redis.call('GET', KEYS[1]) or '0')
+if run ~= tonumber(ARGV[1]) then
+    return false
+end
+local finished = tonumber(redis.call('GET', KEYS[2]) or '0')
+if run > 0 and run ~= finished then
+    return run
+end
+for i = 3, #KEYS do
+    redis.call('DEL', KEYS[i])
+end
+return redis.call('INCR', KEYS[1])
+"#;
+
+// Move a URL from the frontier to this worker's processing list, renewing
+// its lease, so a page is always either queued or held by a known worker
+const POP_SCRIPT: &str = r#"
+redis.call('SET', KEYS[3], '1', 'PX', ARGV[2])
+redis.call('SADD', KEYS[4], ARGV[1])
+return redis.call('LMOVE', KEYS[1], KEYS[2], 'LEFT', 'RIGHT')
+"#;
+
+// Put the pages of workers whose lease ran out back in the frontier, and
+// count what's still queued or held by live workers. When that's nothing
+// the run is over, and is marked finished. The caller lists the workers
+// (ARGV after the run) with their processing list and lease (KEYS after the
+// first three), and gets -1 to try again if one joined since.
+const IDLE_SCRIPT: &str = r#"
+local listed = {}
+for i = 2, #ARGV do
+    listed[ARGV[i]] = true
+end
+for _, worker in ipairs(redis.call('SMEMBERS', KEYS[2])) do
+    if not listed[worker] then
+        return -1
+    end
+end
+local held = 0
+for i = 2, #ARGV do
+    local processing, lease = KEYS[2 * i], KEYS[2 * i + 1]
+    if redis.call('EXISTS', lease) == 1 then
+        held = held + redis.call('LLEN', processing)
+    else
+        while redis.call('LMOVE', processing, KEYS[1], 'RIGHT', 'LEFT') do end
+        redis.call('SREM', KEYS[2], ARGV[i])
+    end
+end
+local outstanding = held + redis.call('LLEN', KEYS[1])
+if outstanding == 0 then
+    redis.call('SET', KEYS[3], ARGV[1])
+end
+return outstanding
+"#;
+
+// Give up a content claim, if it's still this URL's
+const RELEASE_SCRIPT: &str = r#"
+if redis.call('HGET', KEYS[1], ARGV[1]) == ARGV[2] then
+    return redis.call('HDEL', KEYS[1], ARGV[1])
+end
+return 0
+"#;
+
+// Frontier, visited set and image claims shared by every worker crawling
+// the same site. Keys are namespaced by the seed host and the run, which
+// starts over once a crawl has finished. The host is a hash tag, so on a
+// Redis Cluster a site's keys share the slot the scripts need.
+pub struct RedisQueue {
+    conn: MultiplexedConnection,
+    pop: redis::Script,
+    idle: redis::Script,
+    // `rusty_spider:{<host>}:<run>`
+    prefix: String,
+    run: u64,
+    finished: String,
+    worker: String,
+    frontier: String,
+    visited: String,
+    images: String,
+    // SHA-256 of stored content -> URL of the copy that was kept, and the
+    // other URLs it was found under, as "<sha256> <url>"
+    content: String,
+    aliases: String,
+    workers: String,
+    processing: String,
+    lease: String,
+}
+
+impl RedisQueue {
+    pub async fn connect(redis_url: &str, seed: &Url) -> Result<RedisQueue> {
+        let client = redis::Client::open(redis_url).context("Invalid Redis URL")?;
+        let mut conn = client
+            .get_multiplexed_tokio_connection()
+            .await
+            .with_context(|| format!("Failed to connect to {}", redis_url))?;
+
+        let site = format!("rusty_spider:{{{}}}", seed.host_str().unwrap_or("unknown"));
+        let (run_key, finished) = (format!("{}:run", site), format!("{}:finished", site));
+        let script = redis::Script::new(JOIN_SCRIPT);
+        let run = loop {
+            let last: Option<u64> = conn.get(&run_key).await?;
+            let last = last.unwrap_or(0);
+            let mut join = script.prepare_invoke();
+            join.key(&run_key).key(&finished).arg(last);
+            if last > 0 {
+                for key in RUN_KEYS {
+                    join.key(format!("{}:{}:{}", site, last, key));
+                }
+            }
+            let joined: Option<u64> = join.invoke_async(&mut conn).await?;
+            if let Some(run) = joined {
+                break run;
+            }
+        };
+
+        let prefix = format!("{}:{}", site, run);
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let worker = format!("{}-{:x}", std::process::id(), nanos);
+        Ok(RedisQueue {
+            conn,
+            pop: redis::Script::new(POP_SCRIPT),
+            idle: redis::Script::new(IDLE_SCRIPT),
+            run,
+            finished,
+            frontier: format!("{}:frontier", prefix),
+            visited: format!("{}:visited", prefix),
+            images: format!("{}:images", prefix),
+            content: format!("{}:content", prefix),
+            aliases: format!("{}:aliases", prefix),
+            workers: format!("{}:workers", prefix),
+            processing: processing_key(&prefix, &worker),
+            lease: lease_key(&prefix, &worker),
+            prefix,
+            worker,
+        })
+    }
+
+    // Keep this worker's lease alive while it's busy with a slow page
+    fn heartbeat(&self) -> tokio::task::JoinHandle<()> {
+        let (mut conn, lease) = (self.conn.clone(), self.lease.clone());
+        tokio::spawn(async move {
+            loop {
+                sleep(LEASE / 3).await;
+                let renewed: redis::RedisResult<()> = conn.pset_ex(&lease, 1, LEASE.as_millis() as u64).await;
+                if let Err(e) = renewed {
+                    eprintln!("Failed to renew the worker lease: {}", e);
+                }
+            }
+        })
+    }
+
+    // Enqueue a page unless some worker has already queued it
+    pub async fn push_if_new(&mut self, url: &Url) -> Result<bool> {
+        let added: bool = self.conn.sadd(&self.visited, url.as_str()).await?;
+        if added {
+            let _: () = self.conn.rpush(&self.frontier, url.as_str()).await?;
+        }
+        Ok(added)
+    }
+
+    async fn pop(&mut self) -> Result<Option<String>> {
+        Ok(self
+            .pop
+            .key(&self.frontier)
+            .key(&self.processing)
+            .key(&self.lease)
+            .key(&self.workers)
+            .arg(&self.worker)
+            .arg(LEASE.as_millis() as u64)
+            .invoke_async(&mut self.conn)
+            .await?)
+    }
+
+    async fn done(&mut self, url: &str) -> Result<()> {
+        let _: () = self.conn.lrem(&self.processing, 1, url).await?;
+        Ok(())
+    }
+
+    // Pages queued or held by live workers, after reclaiming dead ones'
+    async fn outstanding(&mut self) -> Result<i64> {
+        loop {
+            let workers: Vec<String> = self.conn.smembers(&self.workers).await?;
+            let mut idle = self.idle.prepare_invoke();
+            idle.key(&self.frontier).key(&self.workers).key(&self.finished).arg(self.run);
+            for worker in &workers {
+                idle.key(processing_key(&self.prefix, worker)).key(lease_key(&self.prefix, worker)).arg(worker);
+            }
+            let outstanding: i64 = idle.invoke_async(&mut self.conn).await?;
+            if outstanding >= 0 {
+                return Ok(outstanding);
+            }
+        }
+    }
+
+    // Leave the run; what this worker held is already done
+    async fn leave(&mut self) -> Result<()> {
+        let _: () = self.conn.del(&self.lease).await?;
+        let _: () = self.conn.srem(&self.workers, &self.worker).await?;
+        Ok(())
+    }
+
+    // True for exactly one worker per image URL
+    pub async fn claim_image(&mut self, url: &Url) -> Result<bool> {
+        Ok(self.conn.sadd(&self.images, url.as_str()).await?)
+    }
+
+    // Claim a content hash for this URL. Returns the URL that already holds
+    // it if another worker (or this one) stored the same bytes first.
+    pub async fn claim_content(&mut self, hash: &str, url: &Url) -> Result<Option<String>> {
+        let claimed: bool = self.conn.hset_nx(&self.content, hash, url.as_str()).await?;
+        if claimed {
+            return Ok(None);
+        }
+        Ok(Some(self.conn.hget(&self.content, hash).await?))
+    }
+
+    // Let another URL with these bytes be stored, as this one couldn't be
+    pub async fn release_content(&mut self, hash: &str, url: &Url) -> Result<()> {
+        let _: i64 = redis::Script::new(RELEASE_SCRIPT)
+            .key(&self.content)
+            .arg(hash)
+            .arg(url.as_str())
+            .invoke_async(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    // Tell whichever worker stored these bytes that `url` serves them too
+    pub async fn add_alias(&mut self, hash: &str, url: &Url) -> Result<()> {
+        let _: () = self.conn.sadd(&self.aliases, format!("{} {}", hash, url)).await?;
+        Ok(())
+    }
+
+    // Every (hash, URL) alias the workers have reported this run
+    async fn aliases(&mut self) -> Result<Vec<(String, String)>> {
+        let aliases: Vec<String> = self.conn.smembers(&self.aliases).await?;
+        Ok(aliases
+            .into_iter()
+            .filter_map(|alias| alias.split_once(' ').map(|(hash, url)| (hash.to_string(), url.to_string())))
+            .collect())
+    }
+}
+
+// The pages a worker has taken from the frontier and not finished
+fn processing_key(prefix: &str, worker: &str) -> String {
+    format!("{}:processing:{}", prefix, worker)
+}
+
+// Set while the worker is alive, expiring after LEASE
+fn lease_key(prefix: &str, worker: &str) -> String {
+    format!("{}:lease:{}", prefix, worker)
+}
+
+// Pull pages from the shared frontier until it is empty and no worker is
+// still processing a page that could add more.
+pub async fn run_worker(base_url: Url, state: Arc<CrawlState>, redis_url: &str) -> Result<()> {
+    let mut queue = RedisQueue::connect(redis_url, &base_url).await?;
+    progress!("Joined run {} of the shared crawl", queue.run);
+    if queue.push_if_new(&base_url).await? {
+        println!("Seeded shared frontier with {}", base_url);
+    }
+    let heartbeat = queue.heartbeat();
+    let result = work(&state, &mut queue).await;
+    heartbeat.abort();
+    if result.is_ok() {
+        merge_aliases(&state, &mut queue).await?;
+    }
+    queue.leave().await?;
+    result
+}
+
+// List the URLs other workers found this worker's images under, now that
+// the run is over and they've all reported theirs
+async fn merge_aliases(state: &CrawlState, queue: &mut RedisQueue) -> Result<()> {
+    let mut aliases: HashMap<String, Vec<String>> = HashMap::new();
+    for (hash, url) in queue.aliases().await? {
+        aliases.entry(hash).or_default().push(url);
+    }
+    for entry in state.image_records.lock().await.values_mut() {
+        for url in aliases.get(&entry.sha256).into_iter().flatten() {
+            if entry.url != *url && !entry.aliases.contains(url) {
+                entry.aliases.push(url.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn work(state: &CrawlState, queue: &mut RedisQueue) -> Result<()> {
+    loop {
+        // Leave the rest of the frontier to the other workers
+        if state.cancel.is_cancelled() || state.budget_spent() {
+            return Ok(());
+        }
+        let Some(raw) = queue.pop().await? else {
+            if queue.outstanding().await? == 0 {
+                return Ok(());
+            }
+            sleep(POLL_INTERVAL).await;
+            continue;
+        };
+
+        let result = match Url::parse(&raw) {
+            Ok(url) => crawl_shared(url, state, queue).await,
+            Err(e) => Err(e.into()),
+        };
+        queue.done(&raw).await?;
+
+        // Errors for single pages and images are recorded where they happen;
+        // what's left is a malformed URL in the queue or the queue itself
+        if let Err(e) = result {
+            eprintln!("Failed to crawl {}: {:#}", raw, e);
+        }
+    }
+}
+
+async fn crawl_shared(url: Url, state: &CrawlState, queue: &mut RedisQueue) -> Result<()> {
+    state.visited_urls.insert(url.to_string());
+    state.attempts.fetch_add(1, Ordering::Relaxed);
+    let page = match process_page(&url, None, state).await {
+        Ok(page) => page,
+        Err(e) => {
+            record_error(state, &url, None, e).await;
+            return Ok(());
+        }
+    };
+
+    // Share the links before downloading, so a failed image can't lose them
+    for link_url in &page.links {
+        queue.push_if_new(link_url).await?;
+    }
+
+    // Embedded images are keyed by content already, so claiming one is enough
+    for image in page.fetched {
+        if queue.claim_image(&image.url).await? {
+            state.downloaded_images.insert(image.url.to_string());
+            save_or_record(state, image).await;
+        }
+    }
+
+    for img_url in page.images {
+        if !queue.claim_image(&img_url).await? {
+            continue;
+        }
+        state.downloaded_images.insert(img_url.to_string());
+
+        // The same bytes often sit behind several URLs (CDN variants, query
+        // strings); only the first worker to see them stores a copy
+        state.attempts.fetch_add(1, Ordering::Relaxed);
+        let image = match fetch_image(state, img_url.clone(), Some(&url)).await {
+            Ok(Some(image)) => image,
+            Ok(None) => continue,
+            Err(e) => {
+                record_error(state, &img_url, Some(&url), e).await;
+                continue;
+            }
+        };
+        let (hash, image_url) = (image.sha256.clone(), image.url.clone());
+        match queue.claim_content(&hash, &image_url).await? {
+            None => {
+                // Another worker's copy of the same bytes may succeed
+                if let Err(e) = save_image(state, image).await {
+                    record_error(state, &img_url, Some(&url), e).await;
+                    queue.release_content(&hash, &image_url).await?;
+                }
+            }
+            Some(owner) => {
+                progress!("Skipping {}: same content as {}", image_url, owner);
+                queue.add_alias(&hash, &image_url).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
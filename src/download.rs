@@ -0,0 +1,91 @@
+use crate::bandwidth::Bandwidth;
+use crate::error::CrawlError;
+use crate::pool;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+
+// How much of a body stays in memory to tell its format and read its EXIF
+// date from. EXIF sits in a JPEG's first segments, well within this.
+const HEAD_BYTES: usize = 128 * 1024;
+
+// Where a job's image bodies are streamed before they're moved into place,
+// so only the images currently downloading take up memory, and only their
+// first HEAD_BYTES
+pub struct Downloads {
+    dir: PathBuf,
+    next: AtomicU64,
+    bandwidth: Option<Arc<Bandwidth>>,
+}
+
+impl Downloads {
+    pub async fn create(dir: PathBuf, bandwidth: Option<Arc<Bandwidth>>) -> std::io::Result<Downloads> {
+        fs::create_dir_all(&dir).await?;
+        Ok(Downloads { dir, next: AtomicU64::new(0), bandwidth })
+    }
+
+    // Write `start`, the part of the body already read, then the rest of
+    // `response` to a new temporary file, then hash the finished file on the
+    // rayon pool rather than holding up this task per chunk. Workers sharing
+    // an output directory are told apart by process ID; `gc` removes the
+    // `.part` files a crash leaves behind.
+    pub async fn stream(&self, start: Vec<u8>, mut response: reqwest::Response) -> Result<Download, CrawlError> {
+        let number = self.next.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("{}-{}.part", std::process::id(), number));
+        let mut download = Download { path, size: 0, sha256: String::new(), head: Vec::new() };
+        let mut file = File::create(&download.path).await?;
+        let mut start = Some(bytes::Bytes::from(start));
+        loop {
+            let chunk = match start.take() {
+                Some(chunk) => chunk,
+                None => match response.chunk().await? {
+                    Some(chunk) => {
+                        if let Some(bandwidth) = &self.bandwidth {
+                            bandwidth.consume(chunk.len()).await;
+                        }
+                        chunk
+                    }
+                    None => break,
+                },
+            };
+            file.write_all(&chunk).await?;
+            let room = HEAD_BYTES.saturating_sub(download.head.len());
+            download.head.extend_from_slice(&chunk[..room.min(chunk.len())]);
+            download.size += chunk.len() as u64;
+        }
+        file.sync_data().await?;
+        download.sha256 = pool::sha256_file(download.path.clone()).await?;
+        Ok(download)
+    }
+}
+
+// A body on disk in a temporary file, which is removed when this is dropped
+// unless it was moved into place first
+pub struct Download {
+    path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+    // The first HEAD_BYTES of the body
+    pub head: Vec<u8>,
+}
+
+impl Download {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Move the file to `target`, on the same filesystem as the temporary
+    // directory so it's a rename rather than a copy
+    pub async fn persist(self, target: &Path) -> std::io::Result<()> {
+        fs::rename(&self.path, target).await
+    }
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        // Gone already if it was persisted
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
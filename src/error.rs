@@ -0,0 +1,87 @@
+use std::fmt;
+
+// Why a page or image couldn't be crawled. Per-URL work returns this rather
+// than an opaque anyhow error, so callers can tell a server that went away
+// from a file we don't understand or a disk that filled up.
+#[derive(Debug)]
+pub enum CrawlError {
+    // The request failed or its body couldn't be read
+    Network(reqwest::Error),
+    // No connection within `--connect-timeout`, or no whole response within `--timeout`
+    Timeout(reqwest::Error),
+    // A redirect led somewhere `--redirect-scope` or `--max-redirects` doesn't allow
+    Redirect(reqwest::Error),
+    // The body kept arriving shorter than the server said it was
+    Truncated { attempts: u32, detail: String },
+    // The response isn't something we can read, e.g. an unrecognized image
+    Parse(String),
+    // A page's body ran past `--max-page-size`
+    TooLarge { limit: u64 },
+    // Writing to the output directory failed
+    Storage(std::io::Error),
+}
+
+impl CrawlError {
+    // A short stable name for the kind of failure, for logs and reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CrawlError::Network(_) => "network",
+            CrawlError::Timeout(_) => "timeout",
+            CrawlError::Redirect(_) => "redirect",
+            CrawlError::Truncated { .. } => "truncated",
+            CrawlError::Parse(_) => "parse",
+            CrawlError::TooLarge { .. } => "too_large",
+            CrawlError::Storage(_) => "storage",
+        }
+    }
+}
+
+impl fmt::Display for CrawlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CrawlError::Network(e) => write!(f, "request failed: {}", e),
+            CrawlError::Timeout(e) => write!(f, "timed out: {}", e),
+            CrawlError::Redirect(e) => write!(f, "redirect not followed: {}", e),
+            CrawlError::Truncated { attempts, detail } => {
+                write!(f, "truncated in each of {} attempts ({})", attempts, detail)
+            }
+            CrawlError::Parse(detail) => write!(f, "unreadable response: {}", detail),
+            CrawlError::TooLarge { limit } => write!(f, "body larger than --max-page-size ({} bytes)", limit),
+            CrawlError::Storage(e) => write!(f, "failed to write to the output directory: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CrawlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CrawlError::Network(e) | CrawlError::Timeout(e) | CrawlError::Redirect(e) => Some(e),
+            CrawlError::Storage(e) => Some(e),
+            CrawlError::Truncated { .. } | CrawlError::Parse(_) | CrawlError::TooLarge { .. } => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for CrawlError {
+    fn from(e: reqwest::Error) -> CrawlError {
+        if e.is_redirect() {
+            CrawlError::Redirect(e)
+        } else if e.is_timeout() {
+            CrawlError::Timeout(e)
+        } else {
+            CrawlError::Network(e)
+        }
+    }
+}
+
+impl From<std::io::Error> for CrawlError {
+    fn from(e: std::io::Error) -> CrawlError {
+        CrawlError::Storage(e)
+    }
+}
+
+impl From<image::ImageError> for CrawlError {
+    fn from(e: image::ImageError) -> CrawlError {
+        CrawlError::Parse(e.to_string())
+    }
+}
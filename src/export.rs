@@ -0,0 +1,98 @@
+use anyhow::Result;
+use std::path::Path;
+
+#[cfg(feature = "parquet")]
+mod parquet_file {
+    use crate::manifest::Manifest;
+    use anyhow::{bail, Context, Result};
+    use parquet::basic::Compression;
+    use parquet::data_type::{ByteArray, ByteArrayType, DataType, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+    use parquet::schema::parser::parse_message_type;
+    use std::fs::File;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    // One row per manifest image, in the order the columns are written below
+    const SCHEMA: &str = "
+        message image {
+            required binary url (STRING);
+            required binary path (STRING);
+            required int64 size;
+            required binary sha256 (STRING);
+            optional binary etag (STRING);
+            optional binary last_modified (STRING);
+            optional binary embedded_in (STRING);
+            optional int64 ttfb_ms;
+            optional int64 total_ms;
+        }
+    ";
+
+    // Rows per row group, so a catalog of millions of images is written
+    // (and can be read back) a slice at a time
+    const ROW_GROUP_SIZE: usize = 100_000;
+
+    pub fn write(output_dir: &Path, target: &Path) -> Result<()> {
+        let manifest = Manifest::load(output_dir)?;
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let properties = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+        let file = File::create(target).with_context(|| format!("Failed to create {}", target.display()))?;
+        let mut writer = SerializedFileWriter::new(file, schema, properties)?;
+
+        for images in manifest.images.chunks(ROW_GROUP_SIZE) {
+            let mut group = writer.next_row_group()?;
+            let text = |value: &str| Some(ByteArray::from(value));
+            let optional = |value: &Option<String>| value.as_deref().and_then(text);
+            column::<ByteArrayType>(&mut group, images.iter().map(|image| text(&image.url)))?;
+            column::<ByteArrayType>(&mut group, images.iter().map(|image| text(&image.path.to_string_lossy())))?;
+            column::<Int64Type>(&mut group, images.iter().map(|image| Some(image.size as i64)))?;
+            column::<ByteArrayType>(&mut group, images.iter().map(|image| text(&image.sha256)))?;
+            column::<ByteArrayType>(&mut group, images.iter().map(|image| optional(&image.etag)))?;
+            column::<ByteArrayType>(&mut group, images.iter().map(|image| optional(&image.last_modified)))?;
+            column::<ByteArrayType>(&mut group, images.iter().map(|image| optional(&image.embedded_in)))?;
+            column::<Int64Type>(&mut group, images.iter().map(|image| image.timing.map(|t| t.ttfb_ms as i64)))?;
+            column::<Int64Type>(&mut group, images.iter().map(|image| image.timing.map(|t| t.total_ms as i64)))?;
+            group.close()?;
+        }
+        writer.close()?;
+
+        println!("Exported {} images to {}", manifest.images.len(), target.display());
+        Ok(())
+    }
+
+    // Write the next column of the row group. Optional columns get a
+    // definition level per row, 0 where the value is missing.
+    fn column<T: DataType>(
+        group: &mut SerializedRowGroupWriter<'_, File>,
+        values: impl Iterator<Item = Option<T::T>>,
+    ) -> Result<()> {
+        let Some(mut writer) = group.next_column()? else { bail!("More columns written than the schema has") };
+        let mut present = Vec::new();
+        let mut levels = Vec::new();
+        for value in values {
+            levels.push(i16::from(value.is_some()));
+            present.extend(value);
+        }
+        let typed = writer.typed::<T>();
+        let optional = typed.get_descriptor().max_def_level() > 0;
+        typed.write_batch(&present, optional.then_some(&levels[..]), None)?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+// `export` subcommand: write the manifest's images out as a columnar file
+// for analytics tools
+#[cfg(feature = "parquet")]
+pub fn export_parquet(output_dir: &Path, target: &Path) -> Result<()> {
+    parquet_file::write(output_dir, target)
+}
+
+#[cfg(not(feature = "parquet"))]
+pub fn export_parquet(_output_dir: &Path, target: &Path) -> Result<()> {
+    anyhow::bail!(
+        "Can't write {}: this build doesn't include Parquet export (rebuild with --features parquet)",
+        target.display()
+    )
+}
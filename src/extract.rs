@@ -0,0 +1,406 @@
+use crate::config::Srcset;
+use crate::robots;
+use crate::{css, urls};
+use scraper::{Html, Selector};
+use std::collections::BTreeMap;
+use url::Url;
+
+// `<meta>` names and properties whose content is an image URL: social
+// preview cards and Windows start-screen tiles
+const META_IMAGE_NAMES: &[&str] = &[
+    "og:image",
+    "og:image:url",
+    "og:image:secure_url",
+    "twitter:image",
+    "twitter:image:src",
+    "msapplication-tileimage",
+];
+
+// URLs found on a page, resolved and normalized but not yet scope-checked
+pub struct ParsedPage {
+    // The `<title>`, with runs of whitespace collapsed
+    pub title: Option<String>,
+    pub canonical: Vec<Url>,
+    pub images: Vec<Url>,
+    pub links: Vec<Url>,
+    pub stylesheets: Vec<Url>,
+    // Web app manifests, whose icons are fetched separately
+    pub app_manifests: Vec<Url>,
+    // AMP versions of this page (`<link rel="amphtml">`)
+    pub amp_variants: Vec<Url>,
+    // Documents embedded with `<iframe>` or `<frame>`
+    pub frames: Vec<Url>,
+    // Translations of this page (`<link rel="alternate" hreflang>`), by
+    // lowercased language tag
+    pub alternates: Vec<(String, Url)>,
+    // A robots `<meta>`, general or naming our robots agent, says `noimageindex`
+    pub noimageindex: bool,
+}
+
+// The `<img>` attributes to read on `page_url`: its host's own list from
+// `[site_image_attrs]` if it has one, or else `general`
+pub fn image_attrs_for<'a>(page_url: &Url, general: &'a [String], by_site: &'a BTreeMap<String, Vec<String>>) -> &'a [String] {
+    page_url.host_str().and_then(|host| by_site.get(host)).map_or(general, Vec::as_slice)
+}
+
+// Pull out everything the crawler cares about in one pass. Kept synchronous
+// so the (non-Send) parsed document never lives across an await.
+pub fn parse_page(
+    page_url: &Url,
+    html: &str,
+    strip_params: &[String],
+    srcset: Srcset,
+    image_attrs: &[String],
+    robots_agent: &str,
+) -> ParsedPage {
+    let document = Html::parse_document(html);
+    let resolve = |href: &str| urls::resolve(page_url, href, strip_params);
+
+    let title_selector = Selector::parse("title").unwrap();
+    let title = document
+        .select(&title_selector)
+        .next()
+        .map(|title| title.text().flat_map(str::split_whitespace).collect::<Vec<_>>().join(" "))
+        .filter(|title| !title.is_empty());
+
+    let canonical_selector = Selector::parse(r#"link[rel="canonical"]"#).unwrap();
+    let canonical = document
+        .select(&canonical_selector)
+        .filter_map(|link| link.value().attr("href").and_then(resolve))
+        .collect();
+
+    // AMP pages use `<amp-img>` in place of `<img>`. Each element gives one
+    // source, the first of `image_attrs` that's set, so a lazy-loaded image's
+    // `data-src` wins over the placeholder in its `src`. A `srcset` (or
+    // `data-srcset`) that wins gives its candidates instead; `<picture>`
+    // sources only have those.
+    let img_selector = Selector::parse("img, amp-img, picture source").unwrap();
+    let image_sources = |document: &Html| -> Vec<Url> {
+        let mut found = Vec::new();
+        for img in document.select(&img_selector) {
+            let element = img.value();
+            let Some((attr, value)) = image_attrs
+                .iter()
+                .filter_map(|attr| Some((attr, element.attr(attr)?)))
+                .find(|(_, value)| !value.trim().is_empty())
+            else {
+                continue;
+            };
+            match attr.ends_with("srcset") {
+                true => found.extend(srcset_candidates(value, srcset).into_iter().filter_map(resolve)),
+                false => found.extend(resolve(value)),
+            }
+        }
+        found
+    };
+    let mut images = image_sources(&document);
+
+    // The parser treats `<noscript>` contents as text, but lazy-loading
+    // scripts often keep the real `<img>` markup in there
+    let noscript_selector = Selector::parse("noscript").unwrap();
+    for noscript in document.select(&noscript_selector) {
+        let fragment = Html::parse_fragment(&noscript.text().collect::<String>());
+        images.extend(image_sources(&fragment));
+    }
+
+    // `<object>` and `<embed>` also carry plugins and documents, so only
+    // take the ones that are declared or named as images
+    let embed_selector = Selector::parse("object[data], embed[src]").unwrap();
+    images.extend(
+        document
+            .select(&embed_selector)
+            .filter_map(|embed| {
+                let element = embed.value();
+                let src = element.attr("data").or_else(|| element.attr("src"))?;
+                let declared = element
+                    .attr("type")
+                    .is_some_and(|mime| mime.to_ascii_lowercase().starts_with("image/"));
+                (declared || css::is_image_reference(src)).then_some(src)
+            })
+            .filter_map(resolve),
+    );
+
+    // SVG `<image>` uses `href` or the older `xlink:href`. Both have the
+    // local name `href`, but `attr()` only finds the un-namespaced one.
+    let svg_image_selector = Selector::parse("svg image").unwrap();
+    images.extend(
+        document
+            .select(&svg_image_selector)
+            .filter_map(|image| image.value().attrs().find(|(name, _)| *name == "href"))
+            .filter_map(|(_, href)| resolve(href)),
+    );
+
+    // Hero images and galleries are often backgrounds set in a `style`
+    // attribute or a `<style>` block. A block's `@import`s are fetched like
+    // linked stylesheets.
+    let style_attr_selector = Selector::parse("[style]").unwrap();
+    images.extend(
+        document
+            .select(&style_attr_selector)
+            .filter_map(|element| element.value().attr("style"))
+            .flat_map(|style| css::extract(style).images)
+            .filter_map(|value| resolve(&value)),
+    );
+    let style_selector = Selector::parse("style").unwrap();
+    let mut style_imports = Vec::new();
+    for style in document.select(&style_selector) {
+        let refs = css::extract(&style.text().collect::<String>());
+        images.extend(refs.images.iter().filter_map(|value| resolve(value)));
+        style_imports.extend(refs.imports.iter().filter_map(|value| resolve(value)));
+    }
+
+    // Meta names are case-insensitive, so match them here rather than in the selector
+    let meta_selector = Selector::parse("meta[content]").unwrap();
+    images.extend(
+        document
+            .select(&meta_selector)
+            .filter(|meta| {
+                let element = meta.value();
+                element
+                    .attr("name")
+                    .or_else(|| element.attr("property"))
+                    .is_some_and(|name| META_IMAGE_NAMES.contains(&name.to_ascii_lowercase().as_str()))
+            })
+            .filter_map(|meta| meta.value().attr("content").and_then(resolve)),
+    );
+
+    let link_selector = Selector::parse("a").unwrap();
+    let links = document
+        .select(&link_selector)
+        .filter_map(|link| link.value().attr("href").and_then(resolve))
+        .collect();
+
+    let stylesheet_selector = Selector::parse(r#"link[rel~="stylesheet"]"#).unwrap();
+    let mut stylesheets: Vec<Url> = document
+        .select(&stylesheet_selector)
+        .filter_map(|link| link.value().attr("href").and_then(resolve))
+        .collect();
+    stylesheets.extend(style_imports);
+
+    let manifest_selector = Selector::parse(r#"link[rel~="manifest"]"#).unwrap();
+    let app_manifests = document
+        .select(&manifest_selector)
+        .filter_map(|link| link.value().attr("href").and_then(resolve))
+        .collect();
+
+    let amp_selector = Selector::parse(r#"link[rel~="amphtml"]"#).unwrap();
+    let amp_variants = document
+        .select(&amp_selector)
+        .filter_map(|link| link.value().attr("href").and_then(resolve))
+        .collect();
+
+    let frame_selector = Selector::parse("iframe[src], frame[src]").unwrap();
+    let frames = document
+        .select(&frame_selector)
+        .filter_map(|frame| frame.value().attr("src").and_then(resolve))
+        .collect();
+
+    let alternate_selector = Selector::parse(r#"link[rel~="alternate"][hreflang][href]"#).unwrap();
+    let alternates = document
+        .select(&alternate_selector)
+        .filter_map(|link| {
+            let language = link.value().attr("hreflang")?.trim().to_ascii_lowercase();
+            Some((language, link.value().attr("href").and_then(resolve)?))
+        })
+        .collect();
+
+    let robots_selector = Selector::parse("meta[name][content]").unwrap();
+    let noimageindex = document.select(&robots_selector).any(|meta| {
+        let name = meta.value().attr("name").unwrap_or_default();
+        (name.eq_ignore_ascii_case("robots") || name.eq_ignore_ascii_case(robots_agent))
+            && robots::forbids_images(meta.value().attr("content").unwrap_or_default())
+    });
+
+    ParsedPage {
+        title,
+        canonical,
+        images,
+        links,
+        stylesheets,
+        app_manifests,
+        amp_variants,
+        frames,
+        alternates,
+        noimageindex,
+    }
+}
+
+// The language ranges in an Accept-Language value, lowercased and without
+// their `;q=` weights
+pub fn accepted_languages(header: &str) -> Vec<String> {
+    header
+        .split(',')
+        .filter_map(|range| range.split(';').next())
+        .map(|range| range.trim().to_ascii_lowercase())
+        .filter(|range| !range.is_empty() && range != "*")
+        .collect()
+}
+
+// Whether an hreflang tag is in a wanted language: `de` takes in `de-at`,
+// and `de-de` takes in a page marked just `de`. `x-default` is no language.
+pub fn language_matches(hreflang: &str, wanted: &str) -> bool {
+    let within = |tag: &str, range: &str| {
+        tag == range || tag.strip_prefix(range).is_some_and(|rest| rest.starts_with('-'))
+    };
+    hreflang != "x-default" && (within(hreflang, wanted) || within(wanted, hreflang))
+}
+
+// Icon URLs listed in a web app manifest. Anything that isn't valid JSON
+// with an `icons` array yields nothing.
+pub fn app_manifest_icons(manifest_url: &Url, json: &str, strip_params: &[String]) -> Vec<Url> {
+    let Ok(manifest) = serde_json::from_str::<serde_json::Value>(json) else {
+        return Vec::new();
+    };
+    let Some(icons) = manifest.get("icons").and_then(|icons| icons.as_array()) else {
+        return Vec::new();
+    };
+    icons
+        .iter()
+        .filter_map(|icon| icon.get("src")?.as_str())
+        .filter_map(|src| urls::resolve(manifest_url, src, strip_params))
+        .collect()
+}
+
+// Image URLs anywhere in a JSON document, as API responses list them: any
+// string value that resolves against `api_url` and names an image file
+pub fn json_images(api_url: &Url, json: &str, strip_params: &[String]) -> Vec<Url> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(json) else {
+        return Vec::new();
+    };
+    let mut images = Vec::new();
+    let mut stack = vec![&value];
+    while let Some(value) = stack.pop() {
+        match value {
+            serde_json::Value::String(text) if css::is_image_reference(text) => {
+                images.extend(urls::resolve(api_url, text, strip_params));
+            }
+            serde_json::Value::Array(values) => stack.extend(values.iter().rev()),
+            serde_json::Value::Object(fields) => stack.extend(fields.values().rev()),
+            _ => {}
+        }
+    }
+    images
+}
+
+// The URLs a `srcset` offers, or just the largest of them. Candidates are a
+// URL and an optional width (`800w`) or pixel density (`2x`) descriptor;
+// widths outrank densities, and a candidate without either counts as `1x`.
+fn srcset_candidates(value: &str, choice: Srcset) -> Vec<&str> {
+    let mut candidates = Vec::new();
+    let mut rest = value;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        // URLs may contain commas, so only a trailing one ends the candidate
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (url, after) = rest.split_at(end);
+        let (url, descriptor) = match url.strip_suffix(',') {
+            Some(url) => {
+                rest = after;
+                (url.trim_end_matches(','), "")
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                rest = &after[end..];
+                (url, after[..end].trim())
+            }
+        };
+        candidates.push((url, descriptor_rank(descriptor)));
+    }
+
+    match choice {
+        Srcset::All => candidates.into_iter().map(|(url, _)| url).collect(),
+        Srcset::Largest => candidates
+            .into_iter()
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(url, _)| url)
+            .into_iter()
+            .collect(),
+    }
+}
+
+// Orders candidates by size: widths above densities, then by value
+fn descriptor_rank(descriptor: &str) -> (u8, f64) {
+    for part in descriptor.split_whitespace() {
+        if let Some(width) = part.strip_suffix('w').and_then(|n| n.parse().ok()) {
+            return (1, width);
+        }
+        if let Some(density) = part.strip_suffix('x').and_then(|n| n.parse().ok()) {
+            return (0, density);
+        }
+    }
+    (0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn images(html: &str, image_attrs: &[&str]) -> Vec<String> {
+        let page_url = Url::parse("https://example.com/gallery/").unwrap();
+        let image_attrs: Vec<String> = image_attrs.iter().map(|attr| attr.to_string()).collect();
+        parse_page(&page_url, html, &[], Srcset::Largest, &image_attrs, "rusty_spider")
+            .images
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn src_and_srcset_give_one_source() {
+        let html = r#"<img src="thumb.jpg" srcset="small.jpg 400w, large.jpg 1200w">"#;
+        assert_eq!(images(html, &["srcset", "src"]), ["https://example.com/gallery/large.jpg"]);
+        assert_eq!(images(html, &["src", "srcset"]), ["https://example.com/gallery/thumb.jpg"]);
+        assert_eq!(images(html, &["data-src", "src"]), ["https://example.com/gallery/thumb.jpg"]);
+    }
+
+    #[test]
+    fn data_src_wins_over_src() {
+        let html = r#"<img src="placeholder.gif" data-src="real.jpg">"#;
+        assert_eq!(images(html, &["data-src", "src"]), ["https://example.com/gallery/real.jpg"]);
+        assert_eq!(images(html, &["src", "data-src"]), ["https://example.com/gallery/placeholder.gif"]);
+    }
+
+    #[test]
+    fn srcset_urls_may_contain_commas() {
+        let value = "https://res.cloudinary.com/demo/image/upload/w_400,h_300/a.jpg 400w, \
+                     https://res.cloudinary.com/demo/image/upload/w_800,h_600/a.jpg 800w";
+        assert_eq!(
+            srcset_candidates(value, Srcset::All),
+            [
+                "https://res.cloudinary.com/demo/image/upload/w_400,h_300/a.jpg",
+                "https://res.cloudinary.com/demo/image/upload/w_800,h_600/a.jpg",
+            ]
+        );
+        assert_eq!(
+            srcset_candidates(value, Srcset::Largest),
+            ["https://res.cloudinary.com/demo/image/upload/w_800,h_600/a.jpg"]
+        );
+    }
+
+    #[test]
+    fn widths_outrank_densities() {
+        assert_eq!(srcset_candidates("a.jpg 3x, b.jpg 100w, c.jpg 2x", Srcset::Largest), ["b.jpg"]);
+        assert_eq!(srcset_candidates("a.jpg 3x, b.jpg 100w, c.jpg 2x", Srcset::All), ["a.jpg", "b.jpg", "c.jpg"]);
+    }
+
+    #[test]
+    fn candidates_without_descriptors_count_as_1x() {
+        assert_eq!(srcset_candidates("a.jpg, b.jpg 2x", Srcset::Largest), ["b.jpg"]);
+        assert_eq!(srcset_candidates("a.jpg 0.5x, b.jpg", Srcset::Largest), ["b.jpg"]);
+        assert_eq!(srcset_candidates("only.jpg", Srcset::Largest), ["only.jpg"]);
+        assert!(srcset_candidates(" , ", Srcset::All).is_empty());
+    }
+
+    #[test]
+    fn picture_sources_follow_the_same_order() {
+        let html = r#"<picture><source data-srcset="lazy.webp" srcset="blank.gif"><img src="fallback.jpg"></picture>"#;
+        assert_eq!(
+            images(html, &["data-srcset", "srcset", "src"]),
+            ["https://example.com/gallery/lazy.webp", "https://example.com/gallery/fallback.jpg"]
+        );
+        assert_eq!(images(html, &["src"]), ["https://example.com/gallery/fallback.jpg"]);
+    }
+}
@@ -0,0 +1,72 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use url::Url;
+
+// `--exec-per-image`: a shell command run after each image is saved, with
+// `{path}`, `{url}` and `{sha256}` replaced by the image's. At most
+// `concurrency` run at once, in the background; a save waits for a free
+// slot, so a slow hook slows the crawl down rather than piling up processes.
+pub struct Hook {
+    template: String,
+    slots: Arc<Semaphore>,
+    concurrency: u32,
+}
+
+impl Hook {
+    pub fn new(template: String, concurrency: usize) -> Hook {
+        Hook { template, slots: Arc::new(Semaphore::new(concurrency)), concurrency: concurrency as u32 }
+    }
+
+    // Start the command for one image. A hook that fails is reported; the
+    // image stays saved either way.
+    pub async fn run(&self, path: &Path, url: &Url, sha256: &str) {
+        let command = self
+            .template
+            .replace("{path}", &quote(&path.to_string_lossy()))
+            .replace("{url}", &quote(url.as_str()))
+            .replace("{sha256}", sha256);
+        let Ok(slot) = self.slots.clone().acquire_owned().await else { return };
+        let path = path.to_path_buf();
+        tokio::spawn(async move {
+            match shell(&command).stdin(Stdio::null()).status().await {
+                Ok(status) if status.success() => {}
+                Ok(status) => eprintln!("Hook for {} failed: {}", path.display(), status),
+                Err(e) => eprintln!("Hook for {} couldn't be started: {}", path.display(), e),
+            }
+            drop(slot);
+        });
+    }
+
+    // Wait for the commands still running
+    pub async fn finish(&self) {
+        let _ = self.slots.acquire_many(self.concurrency).await;
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+// One shell word, whatever the value holds
+#[cfg(unix)]
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(not(unix))]
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
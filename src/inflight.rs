@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+// Requests currently waiting on a server, kept so a stalled crawl can show
+// what it's stuck on
+#[derive(Default)]
+pub struct InFlight {
+    next_id: AtomicU64,
+    requests: Mutex<HashMap<u64, (String, Instant)>>,
+}
+
+// Removes its request from the set when dropped, however the request ended
+pub struct Tracked<'a> {
+    owner: &'a InFlight,
+    id: u64,
+}
+
+impl Drop for Tracked<'_> {
+    fn drop(&mut self) {
+        self.owner.requests.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl InFlight {
+    pub fn track(&self, url: &Url) -> Tracked<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.requests.lock().unwrap().insert(id, (url.to_string(), Instant::now()));
+        Tracked { owner: self, id }
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    // The `n` longest-running requests, oldest first
+    pub fn slowest(&self, n: usize) -> Vec<(String, Duration)> {
+        let mut requests: Vec<_> = self
+            .requests
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(url, started)| (url.clone(), started.elapsed()))
+            .collect();
+        requests.sort_by_key(|(_, elapsed)| std::cmp::Reverse(*elapsed));
+        requests.truncate(n);
+        requests
+    }
+}
@@ -0,0 +1,98 @@
+use crate::error::CrawlError;
+use crate::manifest::InventoryEntry;
+use crate::probe::{self, PROBE_BYTES};
+use crate::verbosity::progress;
+use crate::skips::Skip;
+use crate::{kept_extension, CrawlState, FetchedImage};
+use std::sync::atomic::Ordering;
+use url::Url;
+
+// Add an image found on `page` to the inventory. The first sighting fetches
+// the start of it; later ones only add the page to its referrers.
+pub async fn record(state: &CrawlState, url: Url, page: &Url) {
+    if !state.host_allowed(&url, Some(page)) {
+        return;
+    }
+    {
+        let mut inventory = state.inventory.lock().await;
+        if let Some(entry) = inventory.get_mut(url.as_str()) {
+            add_referrer(entry, page);
+            return;
+        }
+        let mut entry = InventoryEntry { url: url.to_string(), ..InventoryEntry::default() };
+        add_referrer(&mut entry, page);
+        inventory.insert(url.to_string(), entry);
+    }
+
+    state.attempts.fetch_add(1, Ordering::Relaxed);
+    match probe(state, &url).await {
+        Ok(Some((head, size))) => {
+            let mut inventory = state.inventory.lock().await;
+            if let Some(entry) = inventory.get_mut(url.as_str()) {
+                describe(state, entry, &head);
+                entry.size = size;
+                progress!("Catalogued: {} ({})", url, dimensions(entry));
+            }
+        }
+        // A format left out of --formats
+        Ok(None) => {
+            state.inventory.lock().await.remove(url.as_str());
+            state.skips.record(Skip::Format, &url, Some(page));
+        }
+        Err(e) => {
+            state.inventory.lock().await.remove(url.as_str());
+            crate::record_error(state, &url, Some(page), e).await;
+        }
+    }
+}
+
+// Add an image that's already in hand, embedded in `page` or linked as a
+// page of its own, without saving it
+pub async fn record_fetched(state: &CrawlState, image: FetchedImage, page: &Url) {
+    let mut inventory = state.inventory.lock().await;
+    let entry = inventory
+        .entry(image.url.to_string())
+        .or_insert_with(|| InventoryEntry { url: image.url.to_string(), ..InventoryEntry::default() });
+    add_referrer(entry, page);
+    if entry.format.is_none() {
+        describe(state, entry, image.body.head());
+        entry.size = Some(image.size);
+        progress!("Catalogued: {} ({})", entry.url, dimensions(entry));
+    }
+}
+
+fn add_referrer(entry: &mut InventoryEntry, page: &Url) {
+    if !entry.referrers.iter().any(|referrer| referrer == page.as_str()) {
+        entry.referrers.push(page.to_string());
+    }
+}
+
+// Ask for the first PROBE_BYTES of the image, returning what came back and
+// the full size when the server said. A server that ignores the range is
+// cut off there. None for formats that aren't kept.
+async fn probe(state: &CrawlState, url: &Url) -> Result<Option<(Vec<u8>, Option<u64>)>, CrawlError> {
+    let mut probe = probe::probe(state, url).await?;
+    probe.head.truncate(PROBE_BYTES);
+    let format = image::guess_format(&probe.head)?;
+    if kept_extension(state, format).is_none() {
+        return Ok(None);
+    }
+    Ok(Some((probe.head, probe.size)))
+}
+
+// Fill in the format and, if the header was all there, the dimensions
+fn describe(state: &CrawlState, entry: &mut InventoryEntry, head: &[u8]) {
+    let Ok(format) = image::guess_format(head) else { return };
+    entry.format = kept_extension(state, format).map(String::from);
+    if let Some((width, height)) = probe::dimensions(head, format) {
+        entry.width = Some(width);
+        entry.height = Some(height);
+    }
+}
+
+fn dimensions(entry: &InventoryEntry) -> String {
+    match (entry.width, entry.height) {
+        (Some(width), Some(height)) => format!("{}x{}", width, height),
+        _ => "dimensions unknown".to_string(),
+    }
+}
@@ -0,0 +1,102 @@
+use crate::config::FileSettings;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+// One independent crawl: its own seed, settings and state
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct JobSpec {
+    pub url: String,
+    // Shown in status output; defaults to the seed URL
+    pub name: Option<String>,
+    // Live-reloaded settings for this job only, replacing `--config`
+    pub config: Option<PathBuf>,
+    // Set when `config` is the `--config` file itself rather than the job's
+    // own, which may hold any setting instead of only the live ones
+    #[serde(skip)]
+    pub shared_config: bool,
+    // Where under the output directory this job's images and state go when
+    // several jobs run; defaults to the seed's host
+    pub output: Option<PathBuf>,
+}
+
+impl JobSpec {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.url)
+    }
+
+    // How to read (and reread) the job's config file
+    pub fn config_loader(&self) -> fn(&Path) -> Result<FileSettings> {
+        if self.shared_config {
+            FileSettings::load
+        } else {
+            FileSettings::load_job
+        }
+    }
+
+    // The directory the job writes to. A lone job uses the output directory
+    // itself, unless it names a subdirectory.
+    pub fn output_dir(&self, output_dir: &Path, alone: bool) -> Result<PathBuf> {
+        if let Some(output) = &self.output {
+            return Ok(output_dir.join(output));
+        }
+        if alone {
+            return Ok(output_dir.to_path_buf());
+        }
+        let url = Url::parse(&self.url).with_context(|| format!("Invalid start URL {}", self.url))?;
+        let mut dir = url.host_str().unwrap_or("unknown").to_ascii_lowercase();
+        if let Some(port) = url.port() {
+            dir.push_str(&format!("_{}", port));
+        }
+        Ok(output_dir.join(dir))
+    }
+}
+
+// Make sure no two jobs write to the same directory, since each keeps its
+// own manifest and state there
+pub fn check_output_dirs(jobs: &[JobSpec], output_dir: &Path) -> Result<()> {
+    let mut seen: HashMap<PathBuf, &str> = HashMap::new();
+    for job in jobs {
+        let dir = job.output_dir(output_dir, jobs.len() == 1)?;
+        if let Some(other) = seen.insert(dir.clone(), job.name()) {
+            bail!(
+                "{} and {} would both be saved to {}; give one of them its own `output` in a jobs file",
+                other,
+                job.name(),
+                dir.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobsFile {
+    job: Vec<JobSpec>,
+}
+
+// Read a `--jobs` file listing `[[job]]` tables. Relative config paths are
+// taken relative to the jobs file; output paths, relative to `--output`.
+pub fn load_jobs(path: &Path) -> Result<Vec<JobSpec>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read jobs file {}", path.display()))?;
+    let file: JobsFile =
+        toml::from_str(&text).with_context(|| format!("Failed to parse jobs file {}", path.display()))?;
+    if file.job.is_empty() {
+        bail!("Jobs file {} doesn't define any [[job]]", path.display());
+    }
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    Ok(file
+        .job
+        .into_iter()
+        .map(|mut job| {
+            job.config = job.config.map(|config| dir.join(config));
+            job
+        })
+        .collect())
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Entry {
+    // About to write `path` (via `path.part`) with the image from `url`
+    Intent { url: String, path: PathBuf },
+    // `path` now holds `size` bytes of the image from `url`
+    Complete { url: String, path: PathBuf, size: u64 },
+}
+
+// Append-only record of download intents and completions. Every entry is
+// synced before the step it describes, so after an unclean shutdown the
+// journal rather than whatever happens to be on disk says what is complete.
+pub struct Journal {
+    file: Mutex<File>,
+}
+
+// The file a download is streamed into before being renamed into place
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+impl Journal {
+    // Open the journal in `dir`, first cleaning up after any downloads an
+    // earlier run started but never finished
+    pub async fn open(dir: &Path) -> Result<Journal> {
+        fs::create_dir_all(dir).await?;
+        let path = dir.join("journal.jsonl");
+        reconcile(&path).await?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Failed to open journal {}", path.display()))?;
+        Ok(Journal { file: Mutex::new(file) })
+    }
+
+    async fn append(&self, entry: &Entry) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+
+    pub async fn intent(&self, url: &str, path: &Path) -> std::io::Result<()> {
+        self.append(&Entry::Intent { url: url.to_string(), path: path.to_path_buf() }).await
+    }
+
+    pub async fn complete(&self, url: &str, path: &Path, size: u64) -> std::io::Result<()> {
+        self.append(&Entry::Complete { url: url.to_string(), path: path.to_path_buf(), size }).await
+    }
+
+    // After a clean finish nothing needs reconciling, so start the next run
+    // with an empty journal
+    pub async fn clear(&self) -> Result<()> {
+        let file = self.file.lock().await;
+        file.set_len(0).await?;
+        file.sync_all().await?;
+        Ok(())
+    }
+}
+
+// Remove partial files left by interrupted downloads and drop completed
+// files that don't match the size that was journaled
+async fn reconcile(path: &Path) -> Result<()> {
+    let text = match fs::read_to_string(path).await {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Latest entry per target path
+    let mut latest: HashMap<PathBuf, Entry> = HashMap::new();
+    for line in text.lines() {
+        // A torn final line is exactly what a crash mid-append looks like
+        let Ok(entry) = serde_json::from_str::<Entry>(line) else { continue };
+        let target = match &entry {
+            Entry::Intent { path, .. } | Entry::Complete { path, .. } => path.clone(),
+        };
+        latest.insert(target, entry);
+    }
+
+    let mut interrupted = 0;
+    let mut damaged = 0;
+    for (target, entry) in &latest {
+        match entry {
+            Entry::Intent { url, .. } => {
+                interrupted += 1;
+                let part = partial_path(target);
+                if fs::try_exists(&part).await.unwrap_or(false) {
+                    fs::remove_file(&part).await?;
+                }
+                println!("Interrupted download discarded: {} ({})", target.display(), url);
+            }
+            Entry::Complete { url, size, .. } => {
+                let on_disk = fs::metadata(target).await.map(|m| m.len()).ok();
+                if on_disk.is_some_and(|len| len != *size) {
+                    damaged += 1;
+                    fs::remove_file(target).await?;
+                    println!("Damaged download removed: {} ({})", target.display(), url);
+                }
+            }
+        }
+    }
+
+    if interrupted + damaged > 0 {
+        println!(
+            "Journal recovery: {} interrupted and {} damaged downloads cleaned up",
+            interrupted, damaged
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,2488 @@
+use anyhow::{anyhow, bail, Context, Result};
+use image::ImageFormat;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, Mutex, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+use futures::future::join_all;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("enable the native-tls or rustls feature to get a TLS stack");
+
+mod archive;
+mod bandwidth;
+mod blacklist;
+mod breaker;
+mod checkpoint;
+mod cluster;
+mod config;
+mod cookies;
+mod css;
+mod dates;
+#[cfg(feature = "console")]
+mod diagnostics;
+mod dispatch;
+mod discovery;
+mod disposition;
+mod download;
+#[cfg(feature = "distributed")]
+mod distributed;
+mod error;
+mod export;
+mod extract;
+mod hook;
+mod inflight;
+mod job;
+mod inventory;
+mod journal;
+mod login;
+mod maintenance;
+mod manifest;
+mod merge;
+mod network;
+mod originals;
+mod pause;
+mod photo;
+mod plan;
+mod politeness;
+mod pool;
+mod preset;
+mod probe;
+mod quarantine;
+mod reload;
+mod retry;
+mod robots;
+mod schedule;
+mod scope;
+mod shards;
+mod signing;
+mod skips;
+mod stats;
+mod status;
+mod urls;
+mod verbosity;
+
+use archive::{Archive, Capture};
+use bandwidth::Bandwidth;
+use blacklist::Blacklist;
+use hook::Hook;
+use quarantine::{Quarantine, Reason};
+use retry::{Failure, Retries, Retry};
+use skips::{Skip, Skips};
+use shards::{KeyLocks, ShardedMap, ShardedSet};
+use breaker::CircuitBreaker;
+use cluster::Fingerprint;
+use config::{Dedupe, DownloadOrder, FileSettings, HttpsPolicy, ImageType, MixedContentPolicy, Srcset, TlsBackend};
+use dispatch::Handler;
+use download::{Download, Downloads};
+use error::CrawlError;
+use job::JobSpec;
+use journal::Journal;
+use manifest::{ErrorEntry, ImageEntry, InventoryEntry, Manifest, PageEntry, Timing};
+use sha2::{Digest, Sha256};
+use network::NetworkMonitor;
+use pause::PauseGate;
+use originals::Originals;
+use politeness::Politeness;
+use inflight::InFlight;
+use reload::{LiveSettings, Tunables};
+use robots::Robots;
+use scope::Scope;
+use signing::Signer;
+use verbosity::{detail, progress};
+
+pub use config::{Cli, CliCommand, Command, Config, CrawlScope, Layout, RedirectScope};
+pub use status::{CrawlReport, JobProgress, JobReport, Outcome};
+pub use verbosity::{set_verbosity, Verbosity};
+
+// What the subcommands other than the crawling ones run
+pub mod commands {
+    pub use crate::cluster::cluster;
+    pub use crate::config::print_completions;
+    pub use crate::export::export_parquet;
+    pub use crate::maintenance::{gc, prune, verify};
+    pub use crate::manifest::{print_diff, print_query};
+    pub use crate::merge::merge;
+    pub use crate::plan::plan;
+    pub use crate::stats::print_trends;
+}
+
+#[cfg(feature = "console")]
+pub use diagnostics::init as init_console;
+
+// Size ranges in bytes
+const SMALL_SIZE: u64 = 100 * 1024;    // 100KB
+const MEDIUM_SIZE: u64 = 1024 * 1024;  // 1MB
+
+// Downloads that come back shorter than their Content-Length are retried
+// up to this many times in total
+const MAX_DOWNLOAD_ATTEMPTS: u32 = 3;
+
+// Pages at least this big are parsed on the blocking pool, at most
+// LARGE_PAGE_PARSES at once across all jobs. Their DOM takes several times
+// the HTML's size, and building it would hold up the other tasks on the
+// worker thread meanwhile.
+const LARGE_PAGE: usize = 1 << 20;
+const LARGE_PAGE_PARSES: usize = 2;
+
+// `--max-error-rate` isn't applied until a job has tried this many pages
+// and images, so one early failure can't abort it
+const MIN_ATTEMPTS_FOR_ERROR_RATE: usize = 20;
+
+// Filename -> (size, path relative to the output directory) of the largest
+// version saved so far
+type ImageSizes = ShardedMap<String, (u64, PathBuf)>;
+
+// Everything the crawl tasks of one job share
+struct CrawlState {
+    name: String,
+    output_dir: PathBuf,
+    settings: Arc<LiveSettings>,
+    client: reqwest::Client,
+    scope: Arc<Scope>,
+    pause: Arc<PauseGate>,
+    network: Arc<NetworkMonitor>,
+    politeness: Arc<Politeness>,
+    breaker: Arc<CircuitBreaker>,
+    blacklist: Arc<Blacklist>,
+    retry: Retries,
+    archive: Option<Arc<Archive>>,
+    quarantine: Option<Arc<Quarantine>>,
+    robots: Option<Arc<Robots>>,
+    bandwidth: Option<Arc<Bandwidth>>,
+    signer: Option<Arc<Signer>>,
+    hook: Option<Arc<Hook>>,
+    large_page_parses: Arc<Semaphore>,
+    // Pages and images left alone, by reason
+    skips: Skips,
+    journal: Journal,
+    downloads: Downloads,
+    // Cancelled to stop the crawl early, keeping what it found so far
+    cancel: CancellationToken,
+    in_flight: InFlight,
+    visited_urls: ShardedSet<String>,
+    // Pages discovered but not finished yet, i.e. the frontier
+    pending_urls: Mutex<HashMap<String, PageOrigin>>,
+    downloaded_images: ShardedSet<String>,
+    // Crawl the pages a page links to, rather than only the seed, down to
+    // `max_depth` links away from it
+    follow_links: bool,
+    max_depth: Option<usize>,
+    // Collect images instead of downloading them as they're found
+    discover_only: bool,
+    // Catalogue images in `inventory` instead of saving them
+    metadata_only: bool,
+    inventory: Mutex<HashMap<String, InventoryEntry>>,
+    layout: Layout,
+    // Ask for full-size originals instead of resized copies
+    originals: Option<Originals>,
+    // Download collected images by size rather than letting the user pick
+    download_order: Option<DownloadOrder>,
+    // Budget for saved image bytes, and how much of it is used
+    max_bytes: Option<u64>,
+    bytes_saved: AtomicU64,
+    discovered: Mutex<Vec<Url>>,
+    discovered_embedded: Mutex<Vec<FetchedImage>>,
+    // Look for images in the stylesheets pages link to
+    follow_stylesheets: bool,
+    // Fetch AMP variants as part of the page that declares them
+    follow_amp: bool,
+    // Leave the images of pages marked `noimageindex` alone
+    honor_noimageindex: bool,
+    // The name robots `<meta>` tags and `X-Robots-Tag` headers address us by
+    robots_agent: String,
+    // Crawl embedded documents like linked pages
+    follow_iframes: bool,
+    // Language ranges whose hreflang alternates are crawled like links
+    hreflang: Vec<String>,
+    min_data_uri_size: usize,
+    max_page_size: u64,
+    srcset: Srcset,
+    external_images: bool,
+    image_attrs: Vec<String>,
+    site_image_attrs: BTreeMap<String, Vec<String>>,
+    formats: Vec<ImageType>,
+    // 0 where there's no minimum
+    min_width: u32,
+    min_height: u32,
+    // Stylesheets and web app manifests already scanned for images
+    fetched_resources: ShardedSet<String>,
+    image_sizes: ImageSizes,
+    // (page, image) pairs where an https page references an http image
+    mixed_content: Mutex<Vec<(String, String)>>,
+    // Manifest records: pages by URL, images by the file they were saved to
+    page_records: Mutex<HashMap<String, PageEntry>>,
+    image_records: Mutex<HashMap<PathBuf, ImageEntry>>,
+    // Where each content hash was saved, so the same bytes under another URL
+    // aren't saved twice
+    saved_hashes: ShardedMap<String, PathBuf>,
+    // Taken for an image's hash and file name while it's being saved
+    saving: KeyLocks,
+    hardlink_duplicates: bool,
+    // With `--dedupe perceptual`, what each saved image looks like. A
+    // near-duplicate can be any image, so those checks go one at a time.
+    dedupe: Dedupe,
+    fingerprints: Mutex<Vec<(Fingerprint, PathBuf)>>,
+    perceptual: Mutex<()>,
+    // Pages and images the crawl gave up on, out of `attempts` tried
+    errors: Mutex<Vec<ErrorEntry>>,
+    attempts: AtomicUsize,
+    max_error_rate: Option<f64>,
+    // Failures passed `max_error_rate` and the job was stopped
+    error_limit_hit: AtomicBool,
+    // Images by URL from the manifest a previous run left in the output directory
+    previous_images: HashMap<String, ImageEntry>,
+    finished: AtomicBool,
+    // With `--daemon`, where pages posted to `/crawl` are sent, ahead of
+    // the frontier; set while the crawl runs
+    daemon: bool,
+    posted: std::sync::Mutex<Option<Frontier>>,
+}
+
+impl CrawlState {
+    // What remains of the `--max-bytes` budget, if there is one
+    fn bytes_left(&self) -> Option<u64> {
+        let max_bytes = self.max_bytes?;
+        Some(max_bytes.saturating_sub(self.bytes_saved.load(Ordering::Relaxed)))
+    }
+
+    fn budget_spent(&self) -> bool {
+        self.bytes_left() == Some(0)
+    }
+
+    // The dimensions of an image `--min-width` or `--min-height` rules out,
+    // judged from its first bytes
+    fn too_small(&self, head: &[u8]) -> Option<(u32, u32)> {
+        if self.min_width == 0 && self.min_height == 0 {
+            return None;
+        }
+        let (width, height) = probe::dimensions(head, image::guess_format(head).ok()?)?;
+        (width < self.min_width || height < self.min_height).then_some((width, height))
+    }
+
+    // New images are only downloaded whole once their first bytes pass the
+    // size filters. The quarantine and the archive keep responses as they
+    // came, so they're read in full as before.
+    fn probes_images(&self) -> bool {
+        (self.min_width > 0 || self.min_height > 0) && self.quarantine.is_none() && self.archive.is_none()
+    }
+
+    // Queue a page posted to `/crawl`, whether or not it was crawled
+    // before. False if it's outside the scope or the job takes no posts.
+    async fn post(&self, mut url: Url) -> bool {
+        if !self.scope.canonicalize(&mut url) {
+            return false;
+        }
+        let Some(posted) = self.posted.lock().unwrap().clone() else { return false };
+        urls::strip_session_params(&mut url, &self.settings.get().strip_params);
+        self.visited_urls.remove(url.as_str());
+        self.pending_urls.lock().await.insert(url.to_string(), PageOrigin::seed());
+        progress!("Posted: {}", url);
+        posted.send((url, PageOrigin::seed())).is_ok()
+    }
+
+    // False for URLs on a blacklisted host
+    fn host_allowed(&self, url: &Url, page: Option<&Url>) -> bool {
+        let allowed = self.blacklist.allows(url.host_str().unwrap_or_default());
+        if !allowed {
+            detail!("Blacklisted host: {}", url);
+            self.skips.record(Skip::Blacklisted, url, page);
+        }
+        allowed
+    }
+
+    // Whether robots.txt lets us fetch `url`, found on `page`, counting the
+    // URLs it doesn't
+    async fn robots_allow(&self, url: &Url, page: Option<&Url>) -> bool {
+        let Some(robots) = &self.robots else { return true };
+        let allowed = robots.allows(url).await;
+        if !allowed {
+            progress!("Disallowed by robots.txt: {}", url);
+            self.skips.record(Skip::Robots, url, page);
+        }
+        allowed
+    }
+
+    // Where a redirect took a page request is checked again once it's known:
+    // robots.txt and the blacklist only see the URL asked for, and with
+    // `--redirect-scope any` nothing but an image may end up off the site
+    async fn landing_allowed(&self, url: &Url, landed: &Url, handler: Handler) -> bool {
+        progress!("Redirected: {} -> {}", url, landed);
+        if !matches!(handler, Handler::Image | Handler::Binary) && !self.scope.allows_redirect(landed) {
+            progress!("Not crawled: {} (redirected out of scope to {})", url, landed);
+            self.skips.record(Skip::OutOfScope, landed, Some(url));
+            return false;
+        }
+        self.host_allowed(landed, Some(url)) && self.robots_allow(landed, Some(url)).await
+    }
+
+    // The pause before a page request: the configured delay, or longer if
+    // the host asks for it in robots.txt or has needed more room
+    async fn page_delay(&self, url: &Url, configured: Duration) -> Duration {
+        let mut configured = configured;
+        if let Some(robots) = &self.robots {
+            configured = configured.max(robots.crawl_delay(url).await.unwrap_or_default());
+        }
+        self.politeness.delay(url.host_str().unwrap_or_default(), configured)
+    }
+}
+
+// State shared by every job in the process
+struct Shared {
+    client: reqwest::Client,
+    // Every job's client keeps its cookies here
+    cookies: Arc<reqwest::cookie::Jar>,
+    pause: Arc<PauseGate>,
+    network: Arc<NetworkMonitor>,
+    politeness: Arc<Politeness>,
+    breaker: Arc<CircuitBreaker>,
+    blacklist: Arc<Blacklist>,
+    archive: Option<Arc<Archive>>,
+    quarantine: Option<Arc<Quarantine>>,
+    robots: Option<Arc<Robots>>,
+    bandwidth: Option<Arc<Bandwidth>>,
+    signer: Option<Arc<Signer>>,
+    hook: Option<Arc<Hook>>,
+    large_page_parses: Arc<Semaphore>,
+    cancel: CancellationToken,
+}
+
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+// In-flight requests listed per job in the SIGUSR1 dump
+#[cfg(unix)]
+const SLOWEST_SHOWN: usize = 5;
+
+// A crawl of one site or a jobs file's worth of them, as the `crawl`,
+// `fetch` and `worker` commands run it:
+//
+//     let report = Crawler::builder().start_url("https://example.com").max_depth(2).build()?.run().await?;
+pub struct Crawler {
+    config: Config,
+    cancel: CancellationToken,
+}
+
+impl Crawler {
+    pub fn builder() -> CrawlerBuilder {
+        CrawlerBuilder { config: Config::default() }
+    }
+
+    pub fn new(config: Config) -> Crawler {
+        Crawler { config, cancel: CancellationToken::new() }
+    }
+
+    // Stop the crawl the way Ctrl-C does: nothing new starts and `run`
+    // returns with what it found. A stopped crawler stays stopped.
+    pub fn stop(&self) {
+        self.cancel.cancel();
+    }
+
+    // Crawl every job to the end and report how each went. Failed jobs are
+    // reported rather than returned as errors, which are kept for problems
+    // that stop the crawl from starting or finishing at all.
+    pub async fn run(&self) -> Result<CrawlReport> {
+        let config = &self.config;
+        let jobs = match &config.jobs_file {
+            Some(path) => job::load_jobs(path)?,
+            None => config
+                .start_urls
+                .iter()
+                .map(|url| JobSpec {
+                    url: url.clone(),
+                    name: None,
+                    config: config.config_file.clone(),
+                    shared_config: true,
+                    output: None,
+                })
+                .collect(),
+        };
+        if jobs.is_empty() {
+            bail!("Nothing to crawl: no start URL or jobs file given");
+        }
+        job::check_output_dirs(&jobs, &config.output_dir)?;
+
+        if (config.estimate || config.download_order.is_some()) && matches!(config.command, Command::Worker { .. }) {
+            bail!("--estimate and --download-order can't be used with worker");
+        }
+        if config.interactive && (jobs.len() > 1 || matches!(config.command, Command::Worker { .. })) {
+            bail!("--interactive needs a single job crawled by this process");
+        }
+
+        progress!("Images will be saved to the '{}' directory", config.output_dir.display());
+        if config.handle_signals {
+            progress!("Send SIGUSR2 or type 'p' + Enter to pause/resume, SIGUSR1 for stats, Ctrl-C to stop early");
+        }
+
+        // Create base downloads directory
+        fs::create_dir_all(&config.output_dir).await?;
+
+        let cookies = cookies::jar(config)?;
+        let client = build_client(config, None, cookies.clone())?;
+
+        // The network monitor probes the first seed to tell when we're back online
+        let probe_url = Url::parse(&jobs[0].url).context("Failed to parse URL")?;
+        let network = Arc::new(NetworkMonitor::new(
+            client.clone(),
+            probe_url,
+            config.network_failure_threshold,
+            config.network_probe_interval,
+        ));
+
+        let pause = Arc::new(PauseGate::new());
+        if config.handle_signals {
+            // Interactive selection reads stdin itself
+            pause::listen_for_controls(pause.clone(), !config.interactive);
+        }
+        if let Some(hours) = config.active_hours {
+            schedule::enforce(hours, pause.clone());
+        }
+
+        let cancel = self.cancel.clone();
+        if config.handle_signals {
+            tokio::spawn(shutdown_on_signal(cancel.clone()));
+        }
+
+        let robots = config.honor_robots.then(|| Arc::new(Robots::new(client.clone(), config.robots_agent())));
+        let shared = Shared {
+            client,
+            cookies,
+            pause,
+            network,
+            politeness: Arc::new(Politeness::load(&config.output_dir, config.adaptive_delay)),
+            breaker: Arc::new(CircuitBreaker::new(config.breaker_threshold, config.breaker_cooldown)),
+            blacklist: Arc::new(Blacklist::load(&config.output_dir, config.blacklist.as_deref(), config.learn_blacklist)?),
+            archive: if config.warc || config.wacz {
+                Some(Arc::new(Archive::create(&config.output_dir, config.warc, config.wacz).await?))
+            } else {
+                None
+            },
+            quarantine: if config.quarantine {
+                Some(Arc::new(Quarantine::open(&config.output_dir).await?))
+            } else {
+                None
+            },
+            robots,
+            bandwidth: config.max_bandwidth.map(|rate| Arc::new(Bandwidth::new(rate))),
+            signer: config.sign_key.as_deref().map(Signer::load).transpose()?.map(Arc::new),
+            hook: config
+                .exec_per_image
+                .clone()
+                .map(|command| Arc::new(Hook::new(command, config.exec_concurrency))),
+            large_page_parses: Arc::new(Semaphore::new(LARGE_PAGE_PARSES)),
+            cancel,
+        };
+
+        if let Some(settings) = &config.login {
+            login::login(&shared.client, settings).await?;
+        }
+
+        if let Command::Worker { redis_url } = &config.command {
+            return run_worker(&jobs[0], config, &shared, redis_url).await;
+        }
+
+        let mut states = Vec::new();
+        let mut crawls = Vec::new();
+        for spec in &jobs {
+            let (base_url, state) = prepare_job(spec, config, &shared, jobs.len() == 1)
+                .await
+                .with_context(|| format!("Failed to start job {}", spec.name()))?;
+            progress!("Starting crawler for {}", base_url);
+            states.push(state.clone());
+            if let Some(interval) = config.checkpoint_interval {
+                checkpoint::spawn_checkpoints(state.clone(), interval, config.checkpoint_keep);
+            }
+            checkpoint::spawn_resume_points(state.clone());
+            let checkpoint_keep = config.checkpoint_interval.map(|_| config.checkpoint_keep);
+            let estimate = config.estimate;
+            crawls.push(async move {
+                let started = Instant::now();
+                let started_at = dates::unix_now();
+                let mut result = crawl(state.clone()).await;
+                if state.error_limit_hit.load(Ordering::Relaxed) {
+                    let max_rate = state.max_error_rate.unwrap_or_default();
+                    result = Err(anyhow!("More than {}% of requests failed", max_rate * 100.0));
+                }
+                // A stopped crawl still reports what it found, but isn't picked from
+                let completed = result.is_ok() && !state.cancel.is_cancelled();
+                if completed && estimate {
+                    discovery::print_estimate(&state).await;
+                } else if let (true, Some(order)) = (completed, state.download_order) {
+                    discovery::download_in_order(&state, order).await;
+                } else if completed && state.discover_only {
+                    result = discovery::select_and_download(&state).await;
+                }
+                state.finished.store(true, Ordering::Relaxed);
+                // An estimate downloads nothing, so it isn't a run worth tracking
+                if !estimate {
+                    if let Err(e) = record_stats(&state, started_at, started.elapsed(), result.is_err()).await {
+                        eprintln!("Failed to record stats for {}: {:#}", state.name, e);
+                    }
+                }
+                if result.is_ok() {
+                    if let Err(e) = state.journal.clear().await {
+                        eprintln!("Failed to clear journal for {}: {:#}", state.name, e);
+                    }
+                }
+                // Only a crawl that didn't finish leaves something to resume
+                let resume_point = if completed {
+                    checkpoint::clear_resume_point(&state).await
+                } else {
+                    checkpoint::write_resume_point(&state).await.map(|_| ())
+                };
+                if let Err(e) = resume_point {
+                    eprintln!("Failed to update the resume point for {}: {:#}", state.name, e);
+                }
+                // A last snapshot records where the job ended, even on failure
+                if let Some(keep) = checkpoint_keep {
+                    if let Err(e) = checkpoint::write_snapshot(&state, keep).await {
+                        eprintln!("Final checkpoint for {} failed: {:#}", state.name, e);
+                    }
+                }
+                result
+            });
+        }
+
+        if let Some(addr) = config.status_addr {
+            status::serve(addr, states.clone()).await?;
+        }
+        if states.len() > 1 {
+            tokio::spawn(report_status(states.clone()));
+        }
+        #[cfg(unix)]
+        if config.handle_signals {
+            tokio::spawn(dump_on_signal(states.clone()));
+        }
+
+        // Run every job to completion, even if one of them fails
+        let results = join_all(crawls).await;
+        save_host_profiles(&shared).await;
+        finish_archive(&shared, &jobs[0].url).await;
+        finish_hooks(&shared).await;
+
+        let mut failures = Vec::new();
+        for (state, result) in states.iter().zip(results) {
+            let failure = result.err().map(|e| {
+                match e.downcast_ref::<CrawlError>() {
+                    Some(cause) => eprintln!("\nJob {} failed ({}): {:#}", state.name, cause.kind(), e),
+                    None => eprintln!("\nJob {} failed: {:#}", state.name, e),
+                }
+                format!("{:#}", e)
+            });
+            failures.push(failure);
+            if !config.estimate {
+                print_summary(state, states.len() > 1).await;
+            }
+        }
+
+        // Leave the manifest of the last real crawl in place
+        if !config.estimate {
+            for state in &states {
+                write_manifest(state).await?;
+            }
+        }
+
+        let report = status::report(&states, failures, config.container).await;
+        if let Some(path) = &config.status_file {
+            if let Err(e) = report.write(path).await {
+                eprintln!("{:#}", e);
+            }
+        }
+        Ok(report)
+    }
+}
+
+// Settings for a `Crawler`, starting from the command line's defaults
+pub struct CrawlerBuilder {
+    config: Config,
+}
+
+impl CrawlerBuilder {
+    pub fn start_url(mut self, url: impl Into<String>) -> Self {
+        self.config.start_urls = vec![url.into()];
+        self
+    }
+
+    // Where images, the manifest and crawl state go (default `downloads`)
+    pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.output_dir = dir.into();
+        self
+    }
+
+    // Pages crawled at the same time (default 8)
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.config.concurrency = Some(concurrency.max(1));
+        self
+    }
+
+    // Don't follow links from pages this many links away from the seed
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.config.max_depth = Some(depth);
+        self
+    }
+
+    // Pause before each page request
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.config.delay = Some(delay);
+        self
+    }
+
+    // Stop downloading once this many image bytes are saved
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.config.max_bytes = Some(bytes);
+        self
+    }
+
+    // Give up on a job once more than this fraction (0 to 1) of requests fail
+    pub fn max_error_rate(mut self, rate: f64) -> Self {
+        self.config.max_error_rate = Some(rate);
+        self
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.config.layout = layout;
+        self
+    }
+
+    pub fn scope(mut self, scope: CrawlScope) -> Self {
+        self.config.scope = scope;
+        self
+    }
+
+    pub fn external_images(mut self, external: bool) -> Self {
+        self.config.external_images = external;
+        self
+    }
+
+    pub fn redirect_scope(mut self, scope: RedirectScope) -> Self {
+        self.config.redirect_scope = scope;
+        self
+    }
+
+    // Record responses in <output>/crawl.warc, and bundle them as crawl.wacz
+    pub fn warc(mut self, warc: bool) -> Self {
+        self.config.warc = warc;
+        self
+    }
+
+    pub fn wacz(mut self, wacz: bool) -> Self {
+        self.config.wacz = wacz;
+        self
+    }
+
+    // Stop on Ctrl-C and SIGTERM and take the pause and stats signals, as
+    // the command line does. Off by default, leaving signals to the caller.
+    pub fn handle_signals(mut self, handle: bool) -> Self {
+        self.config.handle_signals = handle;
+        self
+    }
+
+    pub fn build(self) -> Result<Crawler> {
+        if self.config.start_urls.is_empty() {
+            bail!("A crawler needs a start URL");
+        }
+        for url in &self.config.start_urls {
+            Url::parse(url).with_context(|| format!("Invalid start URL {}", url))?;
+        }
+        Ok(Crawler::new(self.config))
+    }
+}
+
+#[cfg(feature = "distributed")]
+async fn run_worker(spec: &JobSpec, config: &Config, shared: &Shared, redis_url: &str) -> Result<CrawlReport> {
+    // The shared frontier holds bare URLs, not how far they are from the seed
+    if config.max_depth.is_some() {
+        bail!("--max-depth isn't supported by worker");
+    }
+    // Nothing is lost when a worker dies; the others carry on with the frontier
+    if config.resume {
+        bail!("--resume isn't supported by worker, whose frontier lives on in Redis");
+    }
+    if config.metadata_only {
+        bail!("--metadata-only isn't supported by worker");
+    }
+    if config.daemon {
+        bail!("--daemon isn't supported by worker");
+    }
+    let (base_url, state) = prepare_job(spec, config, shared, true).await?;
+    let states = std::slice::from_ref(&state);
+    if let Some(addr) = config.status_addr {
+        status::serve(addr, states.to_vec()).await?;
+    }
+    progress!("Starting worker for {}", base_url);
+    let result = distributed::run_worker(base_url, state.clone(), redis_url).await;
+    save_host_profiles(shared).await;
+    finish_archive(shared, &spec.url).await;
+    finish_hooks(shared).await;
+    let result = match result {
+        Ok(()) => {
+            print_summary(&state, false).await;
+            write_manifest(&state).await?;
+            if state.error_limit_hit.load(Ordering::Relaxed) {
+                Err(anyhow!("More than {}% of requests failed", state.max_error_rate.unwrap_or_default() * 100.0))
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => Err(e),
+    };
+
+    let failure = result.as_ref().err().map(|e| format!("{:#}", e));
+    let report = status::report(states, vec![failure], config.container).await;
+    if let Some(path) = &config.status_file {
+        if let Err(e) = report.write(path).await {
+            eprintln!("{:#}", e);
+        }
+    }
+    result.map(|()| report)
+}
+
+#[cfg(not(feature = "distributed"))]
+async fn run_worker(_spec: &JobSpec, _config: &Config, _shared: &Shared, redis_url: &str) -> Result<CrawlReport> {
+    anyhow::bail!(
+        "Can't use {}: this build doesn't include distributed mode (rebuild with --features distributed)",
+        redis_url
+    )
+}
+
+async fn save_host_profiles(shared: &Shared) {
+    if let Err(e) = shared.politeness.save().await {
+        eprintln!("Failed to save host profiles: {:#}", e);
+    }
+    if let Err(e) = shared.blacklist.save().await {
+        eprintln!("Failed to save the host blacklist: {:#}", e);
+    }
+}
+
+async fn finish_hooks(shared: &Shared) {
+    if let Some(hook) = &shared.hook {
+        hook.finish().await;
+    }
+}
+
+async fn finish_archive(shared: &Shared, main_page: &str) {
+    if let Some(archive) = &shared.archive {
+        if let Err(e) = archive.finish(main_page).await {
+            eprintln!("Failed to finish the web archive: {:#}", e);
+        }
+    }
+}
+
+// Apply `--prefer-https` or `--https-only` to a seed, and start a scope
+// around it
+async fn seed_scope(client: &reqwest::Client, config: &Config, seed: &mut Url) -> Scope {
+    match config.https {
+        HttpsPolicy::Keep => {}
+        HttpsPolicy::Prefer => {
+            if seed.scheme() == "http" && urls::supports_https(client, seed).await {
+                let _ = seed.set_scheme("https");
+            }
+        }
+        HttpsPolicy::Strict => {
+            let _ = seed.set_scheme("https");
+        }
+    }
+    let mut scope = Scope::new(seed, config.scope, config.unify_hosts);
+    if config.https != HttpsPolicy::Keep && seed.scheme() == "https" {
+        scope.upgrade_http_links();
+    }
+    scope
+}
+
+// Work out the seed URL and scope for a job and build its state. `alone` is
+// false when it runs beside other jobs.
+async fn prepare_job(spec: &JobSpec, config: &Config, shared: &Shared, alone: bool) -> Result<(Url, Arc<CrawlState>)> {
+    let load = spec.config_loader();
+    let file_settings = match &spec.config {
+        Some(path) => load(path)?,
+        None => FileSettings::default(),
+    };
+    let settings = Arc::new(LiveSettings::new(config, &file_settings));
+    if let Some(path) = &spec.config {
+        reload::watch(settings.clone(), path.clone(), file_settings, load);
+    }
+
+    let mut base_url = Url::parse(&spec.url).context("Failed to parse URL")?;
+    urls::strip_session_params(&mut base_url, &settings.get().strip_params);
+
+    let client = &shared.client;
+    let mut scope = seed_scope(client, config, &mut base_url).await;
+
+    // Find out whether the seed redirects to its www/apex twin up front, so
+    // the whole crawl uses the host the site itself prefers
+    if config.unify_hosts {
+        if let Ok(response) = client.get(base_url.as_str()).send().await {
+            scope.adopt_seed_redirect(response.url());
+            scope.canonicalize(&mut base_url);
+        }
+    }
+
+    if let Some(profile) = shared.politeness.profile(scope.host()) {
+        let configured = settings.get().delay;
+        let delay = shared.politeness.delay(scope.host(), configured);
+        if delay > configured {
+            progress!(
+                "Starting {} at {}ms between pages, as learned from earlier runs ({} of {} requests throttled)",
+                scope.host(),
+                delay.as_millis(),
+                profile.errors,
+                profile.requests
+            );
+        }
+    }
+
+    // Redirects are checked against this job's scope, so it gets a client of its own
+    let scope = Arc::new(scope);
+    let client = match config.redirect_scope {
+        RedirectScope::Same => build_client(config, Some(scope.clone()), shared.cookies.clone())?,
+        RedirectScope::Any => client.clone(),
+    };
+
+    // Each job has a directory of its own when several run
+    let output_dir = spec.output_dir(&config.output_dir, alone)?;
+    fs::create_dir_all(&output_dir).await?;
+    let state_dir = checkpoint::state_dir(&output_dir, spec.name());
+    let journal = Journal::open(&state_dir).await?;
+    let downloads = Downloads::create(state_dir.join("downloads"), shared.bandwidth.clone()).await?;
+
+    // `--resume` picks up the frontier and records where the last run stopped
+    let resumed = match config.resume {
+        true => checkpoint::load_resume_point(&state_dir).await?,
+        false => None,
+    };
+    let mut pending = HashMap::from([(base_url.to_string(), PageOrigin::seed())]);
+    let mut visited = HashSet::new();
+    let mut downloaded_images = HashSet::new();
+    let mut page_records = HashMap::new();
+    let mut image_records = HashMap::new();
+    let mut inventory = HashMap::new();
+    let mut errors = Vec::new();
+    let mut bytes_saved = 0;
+    match resumed {
+        Some(snapshot) => {
+            println!(
+                "Resuming {} from {}: {} pages visited, {} queued, {} images downloaded",
+                spec.name(),
+                dates::iso8601(snapshot.taken_at),
+                snapshot.pages_visited,
+                snapshot.frontier.len(),
+                snapshot.images_downloaded
+            );
+            // Pages that were in progress go round again
+            pending = snapshot
+                .frontier
+                .iter()
+                .map(|url| (url.clone(), snapshot.origins.get(url).cloned().unwrap_or_else(PageOrigin::seed)))
+                .collect();
+            visited = snapshot.visited.into_iter().filter(|url| !pending.contains_key(url)).collect();
+            downloaded_images = snapshot.downloaded_images.into_iter().collect();
+            page_records = snapshot.pages.into_iter().map(|page| (page.url.clone(), page)).collect();
+            image_records = snapshot.images.into_iter().map(|image| (image.path.clone(), image)).collect();
+            inventory = snapshot.inventory.into_iter().map(|entry| (entry.url.clone(), entry)).collect();
+            errors = snapshot.errors;
+            bytes_saved = snapshot.bytes_saved;
+        }
+        None if config.resume => println!("Nothing to resume for {}; starting from the seed", spec.name()),
+        None if checkpoint::has_resume_point(&state_dir) => {
+            println!("{} was interrupted last time; --resume carries on from where it stopped", spec.name())
+        }
+        None => {}
+    }
+
+    // Images saved before the resume count as near-duplicates too
+    let mut fingerprints = Vec::new();
+    if config.dedupe == Dedupe::Perceptual {
+        for image in image_records.values() {
+            if let Some(fingerprint) = fingerprint_file(&output_dir.join(&image.path)).await {
+                fingerprints.push((fingerprint, image.path.clone()));
+            }
+        }
+    }
+
+    // A re-crawl into the same directory only re-downloads images that changed
+    let previous_images = Manifest::load(&output_dir)
+        .map(|manifest| manifest.images.into_iter().map(|image| (image.url.clone(), image)).collect())
+        .unwrap_or_default();
+
+    let skips = Skips::open(&output_dir, config.skip_log)?;
+    // Shared state for tracking visited URLs, downloaded images, and image sizes
+    let state = Arc::new(CrawlState {
+        name: spec.name().to_string(),
+        output_dir,
+        settings,
+        client,
+        scope,
+        pause: shared.pause.clone(),
+        network: shared.network.clone(),
+        politeness: shared.politeness.clone(),
+        breaker: shared.breaker.clone(),
+        blacklist: shared.blacklist.clone(),
+        retry: config.retry_policy(),
+        archive: shared.archive.clone(),
+        quarantine: shared.quarantine.clone(),
+        robots: shared.robots.clone(),
+        bandwidth: shared.bandwidth.clone(),
+        signer: shared.signer.clone(),
+        hook: shared.hook.clone(),
+        large_page_parses: shared.large_page_parses.clone(),
+        skips,
+        journal,
+        downloads,
+        cancel: shared.cancel.child_token(),
+        in_flight: InFlight::default(),
+        visited_urls: visited.into_iter().collect(),
+        pending_urls: Mutex::new(pending),
+        downloaded_images: downloaded_images.into_iter().collect(),
+        follow_links: !matches!(config.command, Command::Fetch),
+        max_depth: config.max_depth,
+        discover_only: config.interactive || config.estimate || config.download_order.is_some(),
+        metadata_only: config.metadata_only,
+        inventory: Mutex::new(inventory),
+        layout: config.layout,
+        originals: config.original_images.then(|| Originals::new(config.keep_image_params.clone())),
+        download_order: config.download_order,
+        max_bytes: config.max_bytes,
+        bytes_saved: AtomicU64::new(bytes_saved),
+        discovered: Mutex::new(Vec::new()),
+        discovered_embedded: Mutex::new(Vec::new()),
+        follow_stylesheets: config.follow_stylesheets,
+        follow_amp: config.follow_amp,
+        honor_noimageindex: config.honor_robots && !config.ignore_noimageindex,
+        robots_agent: config.robots_agent(),
+        follow_iframes: config.follow_iframes,
+        hreflang: match (&config.accept_language, config.follow_hreflang) {
+            (Some(languages), true) => extract::accepted_languages(languages),
+            _ => Vec::new(),
+        },
+        min_data_uri_size: config.min_data_uri_size,
+        max_page_size: config.max_page_size,
+        srcset: config.srcset,
+        external_images: config.external_images,
+        image_attrs: config.image_attrs.clone(),
+        site_image_attrs: config.site_image_attrs.clone(),
+        formats: config.formats.clone(),
+        min_width: config.min_width.unwrap_or(0),
+        min_height: config.min_height.unwrap_or(0),
+        fetched_resources: ShardedSet::new(),
+        image_sizes: ShardedMap::new(),
+        mixed_content: Mutex::new(Vec::new()),
+        page_records: Mutex::new(page_records),
+        saved_hashes: image_records.values().map(|image| (image.sha256.clone(), image.path.clone())).collect(),
+        saving: KeyLocks::new(),
+        image_records: Mutex::new(image_records),
+        hardlink_duplicates: config.hardlink_duplicates,
+        dedupe: config.dedupe,
+        fingerprints: Mutex::new(fingerprints),
+        perceptual: Mutex::new(()),
+        errors: Mutex::new(errors),
+        attempts: AtomicUsize::new(0),
+        max_error_rate: config.max_error_rate,
+        error_limit_hit: AtomicBool::new(false),
+        previous_images,
+        finished: AtomicBool::new(false),
+        daemon: config.daemon,
+        posted: std::sync::Mutex::new(None),
+    });
+
+    Ok((base_url, state))
+}
+
+// Add this run to the cross-run stats DB
+async fn record_stats(state: &CrawlState, started_at: u64, elapsed: Duration, failed: bool) -> Result<()> {
+    let images: HashSet<String> = state.downloaded_images.to_vec().into_iter().collect();
+    let record = stats::RunRecord {
+        site: state.scope.host().to_string(),
+        job: state.name.clone(),
+        started_at,
+        duration_secs: elapsed.as_secs(),
+        pages_visited: state.visited_urls.len(),
+        images_downloaded: images.len(),
+        new_images: stats::count_new_images(&state.output_dir, &state.name, &images).await?,
+        failed,
+        cancelled: state.cancel.is_cancelled() && !failed,
+    };
+    stats::record_run(&state.output_dir, &record).await
+}
+
+// Each job keeps its own manifest, in its own output directory
+async fn write_manifest(state: &CrawlState) -> Result<()> {
+    let mut manifest = Manifest {
+        generated_at: dates::unix_now(),
+        pages: state.page_records.lock().await.values().cloned().collect(),
+        images: state.image_records.lock().await.values().cloned().collect(),
+        inventory: state.inventory.lock().await.values().cloned().collect(),
+        errors: state.errors.lock().await.clone(),
+    };
+    manifest.inventory.sort_by(|a, b| a.url.cmp(&b.url));
+    manifest.pages.sort_by(|a, b| a.url.cmp(&b.url));
+    manifest::group_alternates(&mut manifest.pages);
+    manifest.images.sort_by(|a, b| a.path.cmp(&b.path));
+    manifest.errors.sort_by(|a, b| a.url.cmp(&b.url));
+
+    let path = manifest.write(&state.output_dir).await?;
+    state.skips.flush()?;
+    println!("Manifest written to {}", path.display());
+    if let Some(signer) = &state.signer {
+        let signature = signer.sign(&state.output_dir).await.context("Failed to sign the manifest")?;
+        println!("Manifest signed in {}", signature.display());
+    }
+    Ok(())
+}
+
+// Combined status view when several jobs run side by side
+async fn report_status(states: Vec<Arc<CrawlState>>) {
+    loop {
+        sleep(STATUS_INTERVAL).await;
+        let mut lines = Vec::new();
+        for state in &states {
+            let pages = state.visited_urls.len();
+            let images = state.downloaded_images.len();
+            let status = if state.finished.load(Ordering::Relaxed) { "done" } else { "running" };
+            lines.push(format!("  {}: {} pages, {} images ({})", state.name, pages, images, status));
+        }
+        println!("[status]\n{}", lines.join("\n"));
+    }
+}
+
+// Print where every job stands on SIGUSR1, for diagnosing a crawl that
+// seems stuck without stopping it
+#[cfg(unix)]
+async fn dump_on_signal(states: Vec<Arc<CrawlState>>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let Ok(mut signals) = signal(SignalKind::user_defined1()) else { return };
+    while signals.recv().await.is_some() {
+        let mut lines = Vec::new();
+        for state in &states {
+            let pages = state.visited_urls.len();
+            let queued = state.pending_urls.lock().await.len();
+            let images = state.downloaded_images.len();
+            lines.push(format!(
+                "  {}: {} pages, {} queued, {} images, {} requests in flight",
+                state.name,
+                pages,
+                queued,
+                images,
+                state.in_flight.len()
+            ));
+            for (url, elapsed) in state.in_flight.slowest(SLOWEST_SHOWN) {
+                lines.push(format!("    {:>7.1}s {}", elapsed.as_secs_f64(), url));
+            }
+        }
+        println!("[stats]\n{}", lines.join("\n"));
+    }
+}
+
+// The first Ctrl-C (or SIGTERM) stops the crawl gracefully: nothing new is
+// started, and the summary, stats and manifest still cover everything done
+// so far. A second one exits straight away.
+async fn shutdown_on_signal(cancel: CancellationToken) {
+    if stop_signal().await.is_err() {
+        return;
+    }
+    println!("\nStopping once in-flight requests finish; press Ctrl-C again to quit now");
+    cancel.cancel();
+    if stop_signal().await.is_ok() {
+        std::process::exit(130);
+    }
+}
+
+// Ctrl-C, or the SIGTERM container runtimes send to stop a job
+async fn stop_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+async fn print_summary(state: &CrawlState, show_name: bool) {
+    let (visited, downloaded) = (state.visited_urls.len(), state.downloaded_images.len());
+    let outcome = if state.cancel.is_cancelled() { "stopped early" } else { "completed" };
+    if show_name {
+        println!("\nCrawling {} for {}!", outcome, state.name);
+        println!("Saved to: {}", state.output_dir.display());
+    } else {
+        println!("\nCrawling {}!", outcome);
+    }
+    println!("Pages visited: {}", visited);
+    let action = if state.metadata_only { "catalogued" } else { "downloaded" };
+    println!("Images {}: {}", action, downloaded);
+    if let (Some(max_bytes), true) = (state.max_bytes, state.budget_spent()) {
+        println!("Byte budget of {} used up", discovery::format_size(max_bytes));
+    }
+    if let Some(skipped) = state.skips.summary() {
+        let listed = if state.skips.logged() { format!(", listed in {}", skips::SKIP_LOG_FILE) } else { String::new() };
+        println!("Skipped: {}{}", skipped, listed);
+    }
+
+    let errors = state.errors.lock().await;
+    if !errors.is_empty() {
+        let mut kinds: BTreeMap<&str, usize> = BTreeMap::new();
+        for error in errors.iter() {
+            *kinds.entry(error.kind.as_str()).or_default() += 1;
+        }
+        let kinds: Vec<String> = kinds.iter().map(|(kind, count)| format!("{} {}", count, kind)).collect();
+        println!("Failed: {} ({}), listed in the manifest", errors.len(), kinds.join(", "));
+    }
+    let timed_out: Vec<&ErrorEntry> = errors.iter().filter(|error| error.kind == "timeout").collect();
+    if !timed_out.is_empty() {
+        println!("\nTimed out ({}):", timed_out.len());
+        for error in timed_out {
+            println!("  {}", error.url);
+        }
+    }
+
+    let mixed_content = state.mixed_content.lock().await;
+    if !mixed_content.is_empty() {
+        println!("\nMixed content ({} http images on https pages):", mixed_content.len());
+        for (page, image) in mixed_content.iter() {
+            println!("  {} <- {}", image, page);
+        }
+    }
+}
+
+fn build_client(
+    config: &Config,
+    scope: Option<Arc<Scope>>,
+    cookies: Arc<reqwest::cookie::Jar>,
+) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+
+    match config.tls {
+        #[cfg(feature = "native-tls")]
+        Some(TlsBackend::Native) => builder = builder.use_native_tls(),
+        #[cfg(feature = "rustls")]
+        Some(TlsBackend::Rustls) => builder = builder.use_rustls_tls(),
+        // Config only accepts backends that were compiled in
+        _ => {}
+    }
+
+    // reqwest follows redirects on its own, so the https and scope policies
+    // have to be applied to each hop here. With a scope, a redirect is also
+    // evidence that its target is the same site, as for the seed. A request
+    // that started off the site (an `--external-images` image) can't leave it.
+    let https_only = config.https == HttpsPolicy::Strict;
+    let external_images = config.external_images;
+    let max_redirects = config.max_redirects;
+    builder = builder.redirect(reqwest::redirect::Policy::custom(move |attempt| {
+        if https_only && attempt.url().scheme() != "https" {
+            return attempt.error("refusing plaintext redirect in --https-only mode");
+        }
+        if attempt.previous().len() > max_redirects {
+            return attempt.error(format!("more than {} redirects", max_redirects));
+        }
+        if let Some(from) = attempt.previous().last() {
+            detail!("Redirect {} of at most {}: {} -> {}", attempt.previous().len(), max_redirects, from, attempt.url());
+        }
+        if let Some(scope) = &scope {
+            if let Some(from) = attempt.previous().last() {
+                scope.note_same_site(from, attempt.url());
+            }
+            let external = external_images && attempt.previous().first().is_some_and(|first| !scope.allows_redirect(first));
+            if !external && !scope.allows_redirect(attempt.url()) {
+                let message = format!("redirect to {} leaves the crawl scope", attempt.url());
+                return attempt.error(message);
+            }
+        }
+        attempt.follow()
+    }));
+
+    builder = builder.connect_timeout(config.connect_timeout);
+    if let Some(timeout) = config.timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    builder = builder.user_agent(config.user_agent());
+
+    // Without --proxy, reqwest reads HTTP_PROXY and HTTPS_PROXY itself
+    if let Some(proxy) = &config.proxy {
+        let proxy = reqwest::Proxy::all(proxy.as_str()).context("Invalid --proxy")?;
+        builder = builder.proxy(proxy.no_proxy(reqwest::NoProxy::from_env()));
+    }
+
+    let mut headers = header::HeaderMap::new();
+    if let Some(from) = &config.from {
+        let value = header::HeaderValue::from_str(from).with_context(|| format!("Invalid --from '{}'", from))?;
+        headers.insert(header::FROM, value);
+    }
+    if let Some(languages) = &config.accept_language {
+        let value = header::HeaderValue::from_str(languages)
+            .with_context(|| format!("Invalid --accept-language '{}'", languages))?;
+        headers.insert(header::ACCEPT_LANGUAGE, value);
+    }
+    for (name, value) in &config.headers {
+        let name = header::HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Invalid header name '{}'", name))?;
+        let value = header::HeaderValue::from_str(value)
+            .with_context(|| format!("Invalid value for header {}", name))?;
+        headers.insert(name, value);
+    }
+    builder = builder.default_headers(headers);
+
+    // reqwest leaves the jar out of requests that already carry a Cookie header
+    builder = builder.cookie_provider(cookies);
+
+    Ok(builder.build()?)
+}
+
+// Crawl from the pages in the job's frontier (the seed, or wherever the crawl
+// being resumed stopped) with at most the live `concurrency` setting's pages
+// in progress at once. Links join the frontier queue as they're found and go to the next
+// free worker; the crawl is over once the queue is empty and no page that
+// could add to it is still in progress.
+async fn crawl(state: Arc<CrawlState>) -> Result<()> {
+    let (frontier, mut queue) = mpsc::unbounded_channel();
+    for (url, origin) in state.pending_urls.lock().await.iter() {
+        if let Ok(url) = Url::parse(url) {
+            let _ = frontier.send((url, origin.clone()));
+        }
+    }
+    let (posted, mut posts) = mpsc::unbounded_channel();
+    if state.daemon {
+        *state.posted.lock().unwrap() = Some(posted);
+    }
+    let mut concurrency = state.settings.concurrency();
+    let mut workers = JoinSet::new();
+    loop {
+        while workers.len() < *concurrency.borrow() {
+            let Ok((url, origin)) = posts.try_recv().or_else(|_| queue.try_recv()) else { break };
+            workers.spawn(crawl_page(url, origin, state.clone(), frontier.clone()));
+        }
+        let joined = tokio::select! {
+            joined = workers.join_next() => joined,
+            // Raised by a config reload: start more pages straight away.
+            // Lowering it lets pages in progress finish first.
+            Ok(()) = concurrency.changed() => continue,
+        };
+        match joined {
+            Some(Ok(())) => {}
+            Some(Err(e)) => bail!("Crawl worker failed: {}", e),
+            // Out of pages: a daemon records what it has and waits for more
+            None if state.daemon && !state.cancel.is_cancelled() => {
+                write_manifest(&state).await?;
+                progress!("Waiting for pages posted to /crawl");
+                tokio::select! {
+                    Some((url, origin)) = posts.recv() => {
+                        workers.spawn(crawl_page(url, origin, state.clone(), frontier.clone()));
+                    }
+                    _ = state.cancel.cancelled() => return Ok(()),
+                }
+            }
+            None => return Ok(()),
+        }
+    }
+}
+
+async fn crawl_page(url: Url, origin: PageOrigin, state: Arc<CrawlState>, frontier: Frontier) {
+    // A stopped crawl leaves the page in the frontier, unvisited. Once the
+    // byte budget is spent there's nothing left to crawl for.
+    if state.cancel.is_cancelled() || state.budget_spent() {
+        return;
+    }
+
+    // Skip if we've already visited this URL
+    if !state.visited_urls.insert(url.to_string()) {
+        return;
+    }
+
+    state.attempts.fetch_add(1, Ordering::Relaxed);
+    let page = match process_page(&url, Some(&origin), &state).await {
+        Ok(page) => page,
+        Err(e) => {
+            record_error(&state, &url, None, e).await;
+            state.pending_urls.lock().await.remove(url.as_str());
+            return;
+        }
+    };
+
+    // Save images embedded in the page itself, or the URL's own image
+    for image in page.fetched {
+        if state.cancel.is_cancelled() {
+            break;
+        }
+        let is_new = state.downloaded_images.insert(image.url.to_string());
+        if state.metadata_only {
+            inventory::record_fetched(&state, image, &url).await;
+        } else if is_new && state.discover_only {
+            state.discovered_embedded.lock().await.push(image);
+        } else if is_new {
+            save_or_record(&state, image).await;
+        }
+    }
+
+    // Download images
+    for img_url in page.images {
+        if state.cancel.is_cancelled() {
+            break;
+        }
+        let is_new = state.downloaded_images.insert(img_url.to_string());
+        if state.metadata_only {
+            inventory::record(&state, img_url, &url).await;
+        } else if is_new && state.discover_only {
+            state.discovered.lock().await.push(img_url);
+        } else if is_new {
+            download_or_record(&state, img_url, Some(&url)).await;
+        }
+    }
+
+    let too_deep = state.max_depth.is_some_and(|max_depth| origin.depth >= max_depth);
+    if too_deep && !page.links.is_empty() {
+        detail!("Not following {} links from {}: --max-depth reached", page.links.len(), url);
+    }
+    if !state.follow_links || too_deep {
+        state.pending_urls.lock().await.remove(url.as_str());
+        return;
+    }
+
+    // Links not seen before join the frontier until their crawl finishes. A
+    // page is marked visited before it leaves the frontier, so with the
+    // frontier locked a link can't slip between the two.
+    let mut pending = state.pending_urls.lock().await;
+    for link_url in page.links {
+        if !state.visited_urls.contains(link_url.as_str()) && !pending.contains_key(link_url.as_str()) {
+            let child = origin.child(&url);
+            pending.insert(link_url.to_string(), child.clone());
+            let _ = frontier.send((link_url, child));
+        }
+    }
+    pending.remove(url.as_str());
+}
+
+// Note a page or image the crawl couldn't handle and carry on without it,
+// unless failures have become common enough that the job should stop
+async fn record_error(state: &CrawlState, url: &Url, page: Option<&Url>, error: CrawlError) {
+    eprintln!("Failed: {} ({}): {}", url, error.kind(), error);
+    let failures = {
+        let mut errors = state.errors.lock().await;
+        errors.push(ErrorEntry {
+            url: url.to_string(),
+            page: page.map(Url::to_string),
+            kind: error.kind().to_string(),
+            message: error.to_string(),
+        });
+        errors.len()
+    };
+
+    let Some(max_rate) = state.max_error_rate else { return };
+    let attempts = state.attempts.load(Ordering::Relaxed);
+    if attempts >= MIN_ATTEMPTS_FOR_ERROR_RATE
+        && failures as f64 > max_rate * attempts as f64
+        && !state.error_limit_hit.swap(true, Ordering::Relaxed)
+    {
+        eprintln!("{} of {} requests failed; stopping {}", failures, attempts, state.name);
+        state.cancel.cancel();
+    }
+}
+
+// Download an image found on `page`, recording a failure instead of returning it
+async fn download_or_record(state: &CrawlState, url: Url, page: Option<&Url>) {
+    if state.budget_spent() {
+        state.downloaded_images.remove(url.as_str());
+        return;
+    }
+    if !state.host_allowed(&url, page) {
+        return;
+    }
+    state.attempts.fetch_add(1, Ordering::Relaxed);
+    if let Err(e) = download_image(state, url.clone(), page).await {
+        record_error(state, &url, page, e).await;
+    }
+}
+
+// Like `download_or_record`, for an image that's already in memory
+async fn save_or_record(state: &CrawlState, image: FetchedImage) {
+    if state.budget_spent() {
+        state.downloaded_images.remove(image.url.as_str());
+        return;
+    }
+    state.attempts.fetch_add(1, Ordering::Relaxed);
+    let (url, page) = (image.url.clone(), image.embedded_in.clone());
+    if let Err(e) = save_image(state, image).await {
+        record_error(state, &url, page.as_ref(), e).await;
+    }
+}
+
+// Pages waiting for a worker, with how they were reached
+type Frontier = mpsc::UnboundedSender<(Url, PageOrigin)>;
+
+// How the crawl reached a page
+#[derive(Clone, Serialize, Deserialize)]
+struct PageOrigin {
+    // Links followed from the seed
+    depth: usize,
+    parent: Option<Url>,
+}
+
+impl PageOrigin {
+    fn seed() -> PageOrigin {
+        PageOrigin { depth: 0, parent: None }
+    }
+
+    // A page linked from `page`, which was reached this way
+    fn child(&self, page: &Url) -> PageOrigin {
+        PageOrigin { depth: self.depth + 1, parent: Some(page.clone()) }
+    }
+}
+
+// In-scope images and links found on a page
+#[derive(Default)]
+struct PageResult {
+    images: Vec<Url>,
+    // Images already in hand: decoded `data:` images, and the URL itself
+    // when it turned out to be an image
+    fetched: Vec<FetchedImage>,
+    links: Vec<Url>,
+}
+
+// Fetch a page and work out which of its images and links belong to the
+// crawl. `origin` is None when the page came from a shared frontier.
+async fn process_page(url: &Url, origin: Option<&PageOrigin>, state: &CrawlState) -> Result<PageResult, CrawlError> {
+    progress!("Crawling: {}", url);
+
+    let settings = state.settings.get();
+
+    // Links are checked against robots.txt as they're found; the seed, and
+    // anything a shared frontier hands out, here
+    let parent = origin.and_then(|origin| origin.parent.as_ref());
+    if parent.is_none() && !state.robots_allow(url, None).await {
+        return Ok(PageResult::default());
+    }
+    if !state.host_allowed(url, parent) {
+        return Ok(PageResult::default());
+    }
+
+    // Space page requests to a host out to be respectful to the server,
+    // more for hosts that have shown they need it
+    let delay = state.page_delay(url, settings.delay).await;
+    state.politeness.wait_turn(url.host_str().unwrap_or_default(), delay).await;
+
+    // Fetch the page content
+    let (response, ttfb) = send_timed(state, || state.client.get(url.as_str())).await?;
+    state.scope.note_same_site(url, response.url());
+
+    let content_type = header_value(&response, header::CONTENT_TYPE);
+    let handler = Handler::for_content_type(content_type.as_deref());
+    if response.url() != url && !state.landing_allowed(url, response.url(), handler).await {
+        return Ok(PageResult::default());
+    }
+    match handler {
+        Handler::Html => html_page(url, origin, state, response, &settings).await,
+        Handler::Image => linked_image(url, origin, state, response, ttfb, false).await,
+        Handler::Binary => linked_image(url, origin, state, response, ttfb, true).await,
+        Handler::Json => {
+            let json = read_text(state, response, false).await?;
+            let found = extract::json_images(url, &json, &settings.strip_params);
+            Ok(scoped_images(state, url, found, &settings).await)
+        }
+        Handler::Css => linked_stylesheet(url, state, response, &settings).await,
+        Handler::Ignore => {
+            progress!("Not crawled: {} ({})", url, content_type.unwrap_or_default());
+            state.skips.record(Skip::NotAPage, url, parent);
+            Ok(PageResult::default())
+        }
+    }
+}
+
+// A link that led straight to an image, saved as if the page it was found
+// on had shown it. A `generic` binary is only taken for one if its first
+// bytes say so, so archives and installers aren't downloaded whole.
+async fn linked_image(
+    url: &Url,
+    origin: Option<&PageOrigin>,
+    state: &CrawlState,
+    mut response: reqwest::Response,
+    ttfb: Duration,
+    generic: bool,
+) -> Result<PageResult, CrawlError> {
+    if state.downloaded_images.contains(url.as_str()) {
+        return Ok(PageResult::default());
+    }
+    let page = origin.and_then(|origin| origin.parent.as_ref());
+    let mut start = Vec::new();
+    if generic {
+        while start.len() < dispatch::BINARY_SNIFF_BYTES {
+            let Some(chunk) = response.chunk().await? else { break };
+            if let Some(bandwidth) = &state.bandwidth {
+                bandwidth.consume(chunk.len()).await;
+            }
+            start.extend_from_slice(&chunk);
+        }
+        if image::guess_format(&start).is_err() {
+            progress!("Not crawled: {} (application/octet-stream, and not an image)", url);
+            state.skips.record(Skip::NotAPage, url, page);
+            return Ok(PageResult::default());
+        }
+    }
+    let fetched = read_image(state, url.clone(), page, start, response, ttfb).await?;
+    Ok(PageResult { fetched: fetched.into_iter().collect(), ..PageResult::default() })
+}
+
+// A link to a stylesheet, scanned as if a page had used it
+async fn linked_stylesheet(
+    url: &Url,
+    state: &CrawlState,
+    response: reqwest::Response,
+    settings: &Tunables,
+) -> Result<PageResult, CrawlError> {
+    state.fetched_resources.insert(url.to_string());
+    let css = read_text(state, response, false).await?;
+    let refs = css::extract(&css);
+    let resolve = |href: &String| urls::resolve(url, href, &settings.strip_params);
+    let mut found: Vec<Url> = refs.images.iter().filter_map(resolve).collect();
+    let imports = refs.imports.iter().filter_map(resolve).collect();
+    found.extend(stylesheet_images(state, imports, &settings.strip_params).await?);
+    Ok(scoped_images(state, url, found, settings).await)
+}
+
+async fn html_page(
+    url: &Url,
+    origin: Option<&PageOrigin>,
+    state: &CrawlState,
+    response: reqwest::Response,
+    settings: &Tunables,
+) -> Result<PageResult, CrawlError> {
+    let status = response.status();
+    let content_length = response.content_length();
+    let declared = response.headers().contains_key(header::CONTENT_TYPE);
+    // Links are relative to where a redirect landed, not the URL asked for
+    let base = response.url().clone();
+    let header_noimageindex = response
+        .headers()
+        .get_all("x-robots-tag")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| robots::header_forbids_images(value, &state.robots_agent));
+    let html = read_text(state, response, true).await?;
+    if !declared && dispatch::looks_binary(html.as_bytes()) {
+        progress!("Not crawled: {} (no Content-Type, and the body isn't text)", url);
+        state.skips.record(Skip::NotAPage, url, origin.and_then(|origin| origin.parent.as_ref()));
+        return Ok(PageResult::default());
+    }
+    let (html, sha256) = pool::sha256(html).await;
+    let length = html.len() as u64;
+    let mut parsed = parse_page(state, &base, html, settings).await;
+    // The image count is filled in once the page's resources are scanned
+    state.page_records.lock().await.insert(
+        url.to_string(),
+        PageEntry {
+            url: url.to_string(),
+            sha256,
+            status: status.as_u16(),
+            title: parsed.title.take(),
+            content_length: content_length.unwrap_or(length),
+            depth: origin.map(|origin| origin.depth),
+            parent: origin.and_then(|origin| origin.parent.as_ref()).map(Url::to_string),
+            images: 0,
+            alternates: parsed.alternates.iter().map(|(language, url)| (language.clone(), url.to_string())).collect(),
+            alternate_group: None,
+        },
+    );
+
+    // A canonical link pointing at the www/apex twin means both hosts are one site
+    for canonical in &parsed.canonical {
+        state.scope.note_same_site(url, canonical);
+    }
+
+    // A page that asks for its images not to be indexed is still crawled for links
+    let (images, fetched) = if state.honor_noimageindex && (header_noimageindex || parsed.noimageindex) {
+        progress!("Not saving images from {} (noimageindex)", url);
+        state.skips.record(Skip::Noimageindex, url, None);
+        (Vec::new(), Vec::new())
+    } else {
+        let mut found_images = parsed.images;
+        if state.follow_stylesheets {
+            found_images.extend(stylesheet_images(state, parsed.stylesheets, &settings.strip_params).await?);
+        }
+        if state.follow_amp {
+            found_images.extend(amp_images(state, parsed.amp_variants, settings).await?);
+        }
+        found_images.extend(app_manifest_images(state, parsed.app_manifests, &settings.strip_params).await?);
+        let PageResult { images, fetched, .. } = scoped_images(state, url, found_images, settings).await;
+        (images, fetched)
+    };
+
+    let mut found_links = parsed.links;
+    if state.follow_iframes {
+        found_links.extend(parsed.frames);
+    }
+    for (language, alternate) in parsed.alternates {
+        if state.hreflang.iter().any(|wanted| extract::language_matches(&language, wanted)) {
+            found_links.push(alternate);
+        }
+    }
+
+    // Only follow links from the same site, and that robots.txt allows
+    let mut links = Vec::new();
+    for mut link_url in found_links {
+        if !state.scope.canonicalize(&mut link_url) {
+            detail!("Out of scope: {} (linked from {})", link_url, url);
+            state.skips.record(Skip::OutOfScope, &link_url, Some(url));
+        } else if state.robots_allow(&link_url, Some(url)).await {
+            links.push(link_url);
+        }
+    }
+
+    if let Some(record) = state.page_records.lock().await.get_mut(url.as_str()) {
+        record.images = images.len() + fetched.len();
+    }
+    Ok(PageResult { images, fetched, links })
+}
+
+// The images found on `page` that belong to the crawl, after the mixed
+// content policy and `--original-images` have had their say
+async fn scoped_images(state: &CrawlState, page: &Url, found_images: Vec<Url>, settings: &Tunables) -> PageResult {
+    let mut images = Vec::new();
+    let mut fetched = Vec::new();
+    for mut img_url in found_images {
+        if img_url.scheme() == "data" {
+            fetched.extend(embedded_image(state, page, &img_url));
+            continue;
+        }
+
+        if page.scheme() == "https" && img_url.scheme() == "http" {
+            state.mixed_content.lock().await.push((page.to_string(), img_url.to_string()));
+            match settings.mixed_content {
+                MixedContentPolicy::Fetch => {}
+                MixedContentPolicy::Upgrade => {
+                    let _ = img_url.set_scheme("https");
+                }
+                MixedContentPolicy::Skip => {
+                    detail!("Mixed content skipped: {} on {}", img_url, page);
+                    state.skips.record(Skip::MixedContent, &img_url, Some(page));
+                    continue;
+                }
+            }
+        }
+
+        if let Some(originals) = &state.originals {
+            originals.rewrite(&mut img_url);
+        }
+
+        // Only process images from the same site, unless `--external-images`
+        // lets them come from anywhere
+        let external = state.external_images && matches!(img_url.scheme(), "http" | "https");
+        if !state.scope.canonicalize(&mut img_url) && !external {
+            detail!("Out of scope: {} (image on {})", img_url, page);
+            state.skips.record(Skip::OutOfScope, &img_url, Some(page));
+        } else if state.robots_allow(&img_url, Some(page)).await {
+            images.push(img_url);
+        }
+    }
+    PageResult { images, fetched, links: Vec::new() }
+}
+
+// Decode a `data:` image found on `page`. It's recorded under a hash of its
+// bytes rather than the URI itself, which can run to megabytes.
+fn embedded_image(state: &CrawlState, page: &Url, uri: &Url) -> Option<FetchedImage> {
+    let bytes = urls::decode_data_image(uri.as_str())?;
+    let media_type = uri.path().split([';', ',']).next().unwrap_or_default();
+    let sha256 = format!("{:x}", Sha256::digest(&bytes));
+    let key = Url::parse(&format!("data:{};sha256,{}", media_type, sha256)).ok()?;
+    if bytes.len() < state.min_data_uri_size {
+        detail!("Embedded image too small: {} bytes on {}", bytes.len(), page);
+        state.skips.record(Skip::TooSmall, &key, Some(page));
+        return None;
+    }
+    let Some(extension) = kept_extension(state, image::guess_format(&bytes).ok()?) else {
+        state.skips.record(Skip::Format, &key, Some(page));
+        return None;
+    };
+    if let Some((width, height)) = state.too_small(&bytes) {
+        detail!("Embedded image too small: {}x{} on {}", width, height, page);
+        state.skips.record(Skip::TooSmall, &key, Some(page));
+        return None;
+    }
+
+    progress!("Embedded: {} bytes of {} on {}", bytes.len(), media_type, page);
+    Some(FetchedImage {
+        url: key,
+        size: bytes.len() as u64,
+        sha256,
+        body: ImageBody::Embedded(bytes.into()),
+        extension,
+        etag: None,
+        last_modified: None,
+        embedded_in: Some(page.clone()),
+        filename: None,
+        timing: None,
+    })
+}
+
+// Image references in the same-site stylesheets a page links to, following
+// `@import`s a few levels deep. Each stylesheet is fetched once per job.
+async fn stylesheet_images(state: &CrawlState, stylesheets: Vec<Url>, strip_params: &[String]) -> Result<Vec<Url>, CrawlError> {
+    const MAX_IMPORT_DEPTH: usize = 3;
+
+    let mut images = Vec::new();
+    let mut queue: Vec<(Url, usize)> = stylesheets.into_iter().map(|url| (url, 0)).collect();
+    while let Some((mut css_url, depth)) = queue.pop() {
+        if !state.scope.canonicalize(&mut css_url)
+            || !state.fetched_resources.insert(css_url.to_string())
+        {
+            continue;
+        }
+
+        progress!("Stylesheet: {}", css_url);
+        let css = read_text(state, fetch(state, &css_url).await?, false).await?;
+        let refs = css::extract(&css);
+
+        // url() values are relative to the stylesheet, not the page
+        images.extend(refs.images.iter().filter_map(|href| urls::resolve(&css_url, href, strip_params)));
+        if depth < MAX_IMPORT_DEPTH {
+            queue.extend(
+                refs.imports
+                    .iter()
+                    .filter_map(|href| urls::resolve(&css_url, href, strip_params))
+                    .map(|url| (url, depth + 1)),
+            );
+        }
+    }
+    Ok(images)
+}
+
+// Images on the AMP variants of a page. The variant is the same content as
+// the page that declared it, so it's marked visited and its images are
+// credited to that page rather than it being crawled as a page of its own.
+async fn amp_images(state: &CrawlState, variants: Vec<Url>, settings: &Tunables) -> Result<Vec<Url>, CrawlError> {
+    let mut images = Vec::new();
+    for mut amp_url in variants {
+        if !state.scope.canonicalize(&mut amp_url) || !state.visited_urls.insert(amp_url.to_string()) {
+            continue;
+        }
+        state.pending_urls.lock().await.remove(amp_url.as_str());
+
+        progress!("AMP variant: {}", amp_url);
+        let delay = state.page_delay(&amp_url, settings.delay).await;
+        state.politeness.wait_turn(amp_url.host_str().unwrap_or_default(), delay).await;
+        let response = fetch(state, &amp_url).await?;
+        let content_type = header_value(&response, header::CONTENT_TYPE);
+        if Handler::for_content_type(content_type.as_deref()) != Handler::Html {
+            detail!("AMP variant isn't a page: {} ({})", amp_url, content_type.unwrap_or_default());
+            continue;
+        }
+        let html = read_text(state, response, true).await?;
+        images.extend(parse_page(state, &amp_url, html, settings).await.images);
+    }
+    Ok(images)
+}
+
+// Parse a page, on the blocking pool if it's a large one. The HTML is
+// taken so that a large page's is freed along with its document.
+async fn parse_page(state: &CrawlState, url: &Url, html: String, settings: &Tunables) -> extract::ParsedPage {
+    let image_attrs = extract::image_attrs_for(url, &state.image_attrs, &state.site_image_attrs);
+    if html.len() < LARGE_PAGE {
+        return extract::parse_page(url, &html, &settings.strip_params, state.srcset, image_attrs, &state.robots_agent);
+    }
+    let slot = state.large_page_parses.clone().acquire_owned().await.expect("parse slots closed");
+    detail!("Parsing {} ({}) off the crawl's threads", url, discovery::format_size(html.len() as u64));
+    let (url, strip_params, srcset, image_attrs, robots_agent) =
+        (url.clone(), settings.strip_params.clone(), state.srcset, image_attrs.to_vec(), state.robots_agent.clone());
+    let parsed = tokio::task::spawn_blocking(move || {
+        let parsed = extract::parse_page(&url, &html, &strip_params, srcset, &image_attrs, &robots_agent);
+        drop(slot);
+        parsed
+    });
+    parsed.await.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+// Icons listed in the same-site web app manifests a page links to, each
+// manifest fetched once per job
+async fn app_manifest_images(state: &CrawlState, manifests: Vec<Url>, strip_params: &[String]) -> Result<Vec<Url>, CrawlError> {
+    let mut images = Vec::new();
+    for mut manifest_url in manifests {
+        if !state.scope.canonicalize(&mut manifest_url)
+            || !state.fetched_resources.insert(manifest_url.to_string())
+        {
+            continue;
+        }
+
+        progress!("App manifest: {}", manifest_url);
+        let json = read_text(state, fetch(state, &manifest_url).await?, false).await?;
+        images.extend(extract::app_manifest_icons(&manifest_url, &json, strip_params));
+    }
+    Ok(images)
+}
+
+// Read a text response in the charset it declares (UTF-8 otherwise),
+// recording it in the web archive if there is one. Bodies over
+// `--max-page-size` are abandoned as soon as that's clear.
+async fn read_text(state: &CrawlState, mut response: reqwest::Response, is_page: bool) -> Result<String, CrawlError> {
+    let url = response.url().clone();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let limit = state.max_page_size;
+    if response.content_length().is_some_and(|length| length > limit) {
+        return Err(CrawlError::TooLarge { limit });
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if let Some(bandwidth) = &state.bandwidth {
+            bandwidth.consume(chunk.len()).await;
+        }
+        if (body.len() + chunk.len()) as u64 > limit {
+            return Err(CrawlError::TooLarge { limit });
+        }
+        body.extend_from_slice(&chunk);
+    }
+    if let Some(archive) = &state.archive {
+        archive.record(Capture { url: &url, status, headers: &headers, body: &body, is_page }).await?;
+    }
+
+    let charset = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').find_map(|param| param.trim().strip_prefix("charset=")))
+        .and_then(|label| encoding_rs::Encoding::for_label(label.trim_matches('"').as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    // A body that's already whole, valid UTF-8 becomes the text as it is,
+    // rather than being copied for it
+    let (text, _, _) = charset.decode(&body);
+    let text = match text {
+        Cow::Borrowed(text) if text.len() == body.len() => None,
+        text => Some(text.into_owned()),
+    };
+    Ok(text.unwrap_or_else(|| String::from_utf8(body).expect("decoded as UTF-8 already")))
+}
+
+// Send a GET, holding it while the crawl is paused and retrying it if it
+// failed because our network went away
+async fn fetch(state: &CrawlState, url: &Url) -> reqwest::Result<reqwest::Response> {
+    send(state, || state.client.get(url.as_str())).await
+}
+
+// Like `fetch`, for any request. `request` is called again for each attempt.
+async fn send(
+    state: &CrawlState,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> reqwest::Result<reqwest::Response> {
+    send_timed(state, request).await.map(|(response, _)| response)
+}
+
+// Like `send`, also returning how long the successful attempt took to get
+// response headers back (time to first byte). A server error or a request
+// that got no answer is retried with `--retries`, or the `--retry` for that
+// kind of failure, backing off in between.
+async fn send_timed(
+    state: &CrawlState,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> reqwest::Result<(reqwest::Response, Duration)> {
+    let mut retries = 0;
+    loop {
+        let request = request().build()?;
+        let url = request.url().clone();
+        let host = url.host_str().unwrap_or_default().to_string();
+        // Hold the request while the crawl is paused or the host's circuit
+        // is open, but stopping shouldn't have to wait for either
+        tokio::select! {
+            _ = async {
+                state.pause.wait().await;
+                state.breaker.wait(&host).await;
+            } => {}
+            _ = state.cancel.cancelled() => {}
+        }
+        let configured_delay = state.settings.get().delay;
+        let _tracked = state.in_flight.track(request.url());
+        let started = Instant::now();
+        match state.client.execute(request).await {
+            Ok(response) => {
+                state.network.record_success();
+                state.politeness.record(&host, started.elapsed(), Some(response.status()), configured_delay);
+                if breaker::is_failure_status(response.status()) {
+                    state.breaker.record_failure(&host);
+                } else {
+                    state.breaker.record_success(&host);
+                }
+                state.blacklist.record(&host, response.status() == StatusCode::FORBIDDEN);
+                let elapsed = started.elapsed();
+                let retry = state.retry.get(Failure::Server);
+                if retry::is_transient_status(response.status()) && retries < retry.attempts {
+                    retries += 1;
+                    // The host said when to come back: all its requests wait
+                    // until then, this one included
+                    match retry::retry_after(&response) {
+                        Some(wait) if wait <= retry::MAX_RETRY_AFTER => {
+                            state.breaker.hold(&host, wait);
+                            continue;
+                        }
+                        Some(wait) => {
+                            progress!("Giving up on {}: {} asks to wait {}s", url, host, wait.as_secs());
+                        }
+                        None => {
+                            if back_off(state, &url, &response.status().to_string(), retry, retries).await {
+                                continue;
+                            }
+                        }
+                    }
+                }
+                return Ok((response, elapsed));
+            }
+            // Refusing to follow a redirect is our policy, not the host struggling
+            Err(e) if e.is_redirect() => return Err(e),
+            Err(e) => {
+                // Our own connection going down says nothing about the host
+                if network::is_connection_error(&e) && state.network.record_failure(&state.pause).await {
+                    continue;
+                }
+                state.politeness.record(&host, started.elapsed(), None, configured_delay);
+                state.breaker.record_failure(&host);
+                state.blacklist.record(&host, e.is_timeout());
+                if let Some(failure) = retry::failure(&e) {
+                    let retry = state.retry.get(failure);
+                    if retries < retry.attempts {
+                        retries += 1;
+                        if back_off(state, &url, &e.to_string(), retry, retries).await {
+                            continue;
+                        }
+                    }
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+// Wait before retry number `count` of a request to `url`. False if the crawl
+// was stopped meanwhile, and the failure should stand.
+async fn back_off(state: &CrawlState, url: &Url, problem: &str, retry: Retry, count: u32) -> bool {
+    let delay = retry.delay(count);
+    progress!("Retrying: {} in {}ms ({}, retry {} of {})", url, delay.as_millis(), problem, count, retry.attempts);
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => true,
+        _ = state.cancel.cancelled() => false,
+    }
+}
+
+fn header_value(response: &reqwest::Response, name: header::HeaderName) -> Option<String> {
+    response.headers().get(name)?.to_str().ok().map(str::to_string)
+}
+
+fn get_size_category(size: u64) -> &'static str {
+    if size < SMALL_SIZE {
+        "small"
+    } else if size < MEDIUM_SIZE {
+        "medium"
+    } else {
+        "large"
+    }
+}
+
+async fn download_image(state: &CrawlState, url: Url, page: Option<&Url>) -> Result<(), CrawlError> {
+    if let Some(image) = fetch_image(state, url, page).await? {
+        save_image(state, image).await?;
+    }
+    Ok(())
+}
+
+// An image that has been downloaded but not yet saved
+struct FetchedImage {
+    url: Url,
+    size: u64,
+    sha256: String,
+    body: ImageBody,
+    extension: &'static str,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    // The page a `data:` image came from
+    embedded_in: Option<Url>,
+    // Suggested by the server's Content-Disposition, already sanitized
+    filename: Option<String>,
+    timing: Option<Timing>,
+}
+
+enum ImageBody {
+    // A `data:` image, decoded from its page
+    Embedded(bytes::Bytes),
+    // Streamed to a temporary file as it arrived
+    Downloaded(Download),
+}
+
+impl ImageBody {
+    // Enough of the image to tell its format and EXIF date from
+    fn head(&self) -> &[u8] {
+        match self {
+            ImageBody::Embedded(bytes) => bytes,
+            ImageBody::Downloaded(download) => &download.head,
+        }
+    }
+}
+
+// Download an image found on `page`, returning None for formats we don't
+// keep and for images a previous run already saved that haven't changed since
+async fn fetch_image(state: &CrawlState, url: Url, page: Option<&Url>) -> Result<Option<FetchedImage>, CrawlError> {
+    progress!("Downloading: {}", url);
+
+    let previous = state
+        .previous_images
+        .get(url.as_str())
+        .filter(|entry| state.output_dir.join(&entry.path).exists());
+
+    let (response, ttfb) = match previous {
+        Some(entry) if entry.etag.is_some() || entry.last_modified.is_some() => {
+            let (response, ttfb) = send_timed(state, || {
+                let mut request = state.client.get(url.as_str());
+                if let Some(etag) = &entry.etag {
+                    request = request.header(header::IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &entry.last_modified {
+                    request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+                }
+                request
+            })
+            .await?;
+            if response.status() == StatusCode::NOT_MODIFIED {
+                keep_previous(state, entry).await;
+                return Ok(None);
+            }
+            (response, ttfb)
+        }
+        Some(entry) => {
+            // Without validators, an unchanged Content-Length is the best hint we have
+            let head = send(state, || state.client.head(url.as_str())).await?;
+            let length = header_value(&head, header::CONTENT_LENGTH).and_then(|len| len.parse::<u64>().ok());
+            if length == Some(entry.size) {
+                keep_previous(state, entry).await;
+                return Ok(None);
+            }
+            send_timed(state, || state.client.get(url.as_str())).await?
+        }
+        None if state.probes_images() => return probe_image(state, url, page).await,
+        None => send_timed(state, || state.client.get(url.as_str())).await?,
+    };
+    read_image(state, url, page, Vec::new(), response, ttfb).await
+}
+
+// Fetch the start of a new image, and the rest only if it's big enough. An
+// image whose dimensions aren't in its first bytes is judged once it's in.
+async fn probe_image(state: &CrawlState, url: Url, page: Option<&Url>) -> Result<Option<FetchedImage>, CrawlError> {
+    let probe = probe::probe(state, &url).await?;
+    if let Some((width, height)) = state.too_small(&probe.head) {
+        detail!("Too small: {} ({}x{})", url, width, height);
+        state.skips.record(Skip::TooSmall, &url, page);
+        return Ok(None);
+    }
+    if image::guess_format(&probe.head).is_ok_and(|format| kept_extension(state, format).is_none()) {
+        state.skips.record(Skip::Format, &url, page);
+        return Ok(None); // Skip formats left out of --formats
+    }
+
+    // Carry on from where the probe stopped: on the same response if the
+    // server ignored the range, otherwise with a range for the rest
+    if !probe.partial || probe.complete() {
+        return read_image(state, url, page, probe.head, probe.response, probe.ttfb).await;
+    }
+    let validator = header_value(&probe.response, header::ETAG).or_else(|| header_value(&probe.response, header::LAST_MODIFIED));
+    let rest = format!("bytes={}-", probe.head.len());
+    let (response, ttfb) = send_timed(state, || {
+        let request = state.client.get(url.as_str()).header(header::RANGE, rest.as_str());
+        match &validator {
+            Some(validator) => request.header(header::IF_RANGE, validator),
+            None => request,
+        }
+    })
+    .await?;
+    // The image changed in between, and came back whole
+    let start = match response.status() {
+        StatusCode::PARTIAL_CONTENT => probe.head,
+        _ => Vec::new(),
+    };
+    read_image(state, url, page, start, response, probe.ttfb + ttfb).await
+}
+
+// Read the image `response` holds, after the `start` of it that was read
+// already, asking again while the body arrives short. `ttfb` is how long
+// the response took to start.
+async fn read_image(
+    state: &CrawlState,
+    url: Url,
+    page: Option<&Url>,
+    mut start: Vec<u8>,
+    mut response: reqwest::Response,
+    mut ttfb: Duration,
+) -> Result<Option<FetchedImage>, CrawlError> {
+    // A transfer cut short must not be saved as if it were the whole image
+    let mut attempt = 1;
+    let (download, etag, last_modified, timing, filename) = loop {
+        let etag = header_value(&response, header::ETAG);
+        let filename = header_value(&response, header::CONTENT_DISPOSITION).and_then(|value| disposition::filename(&value));
+        let last_modified = header_value(&response, header::LAST_MODIFIED);
+        let declared = probe::full_length(&response);
+        let (final_url, status, headers) = (response.url().clone(), response.status(), response.headers().clone());
+        let body_started = Instant::now();
+        let problem = match state.downloads.stream(std::mem::take(&mut start), response).await {
+            Ok(download) if declared.is_none_or(|len| len == download.size) => {
+                let timing = Timing::new(ttfb, ttfb + body_started.elapsed());
+                // The archive record holds the whole body, so it's read back
+                if let Some(archive) = &state.archive {
+                    let body = fs::read(download.path()).await?;
+                    let capture = Capture { url: &final_url, status, headers: &headers, body: &body, is_page: false };
+                    archive.record(capture).await?;
+                }
+                break (download, etag, last_modified, timing, filename);
+            }
+            Ok(download) => {
+                let problem = format!("{} of {} bytes", download.size, declared.unwrap_or_default());
+                if attempt >= MAX_DOWNLOAD_ATTEMPTS {
+                    hold(state, &url, page, download, Reason::Truncated, &problem).await?;
+                }
+                problem
+            }
+            Err(CrawlError::Network(e)) if e.is_body() => format!("body cut off: {}", e),
+            Err(e) => return Err(e),
+        };
+
+        if attempt >= MAX_DOWNLOAD_ATTEMPTS {
+            return Err(CrawlError::Truncated { attempts: attempt, detail: problem });
+        }
+        progress!("Truncated: {} ({}), retrying", url, problem);
+        attempt += 1;
+        (response, ttfb) = send_timed(state, || state.client.get(url.as_str())).await?;
+    };
+    
+    // Try to determine image format from content
+    let format = match image::guess_format(&download.head) {
+        Ok(format) => format,
+        Err(e) => {
+            hold(state, &url, page, download, Reason::Unrecognized, &e.to_string()).await?;
+            return Err(e.into());
+        }
+    };
+    
+    let Some(extension) = kept_extension(state, format) else {
+        let detail = format!("{:?} isn't kept", format);
+        state.skips.record(Skip::Format, &url, page);
+        hold(state, &url, page, download, Reason::Format, &detail).await?;
+        return Ok(None); // Skip formats left out of --formats
+    };
+
+    if let Some((width, height)) = state.too_small(&download.head) {
+        let detail = format!("{}x{}", width, height);
+        detail!("Too small: {} ({})", url, detail);
+        state.skips.record(Skip::TooSmall, &url, page);
+        hold(state, &url, page, download, Reason::Small, &detail).await?;
+        return Ok(None);
+    }
+
+    Ok(Some(FetchedImage {
+        url,
+        size: download.size,
+        sha256: download.sha256.clone(),
+        body: ImageBody::Downloaded(download),
+        extension,
+        etag,
+        last_modified,
+        embedded_in: None,
+        filename,
+        timing: Some(timing),
+    }))
+}
+
+// Set a download aside with `--quarantine`, or let it go without
+async fn hold(
+    state: &CrawlState,
+    url: &Url,
+    page: Option<&Url>,
+    download: Download,
+    reason: Reason,
+    detail: &str,
+) -> Result<(), CrawlError> {
+    if let Some(quarantine) = &state.quarantine {
+        quarantine.hold(url, page, download, reason, detail).await?;
+    }
+    Ok(())
+}
+
+// The extension images of this format are saved with, if we keep them at all
+fn kept_extension(state: &CrawlState, format: ImageFormat) -> Option<&'static str> {
+    let kind = match format {
+        ImageFormat::Jpeg => ImageType::Jpg,
+        ImageFormat::Gif => ImageType::Gif,
+        ImageFormat::Png => ImageType::Png,
+        ImageFormat::WebP => ImageType::Webp,
+        ImageFormat::Avif => ImageType::Avif,
+        ImageFormat::Bmp => ImageType::Bmp,
+        ImageFormat::Tiff => ImageType::Tiff,
+        _ => return None,
+    };
+    state.formats.contains(&kind).then(|| kind.extension())
+}
+
+// Carry an unchanged image from the previous run into this run's manifest
+async fn keep_previous(state: &CrawlState, entry: &ImageEntry) {
+    progress!("Unchanged: {}", entry.url);
+    if let Some(filename) = entry.path.file_name() {
+        let filename = filename.to_string_lossy().into_owned();
+        state.image_sizes.insert(filename, (entry.size, entry.path.clone()));
+    }
+    state.saved_hashes.insert(entry.sha256.clone(), entry.path.clone());
+    if state.dedupe == Dedupe::Perceptual {
+        if let Some(fingerprint) = fingerprint_file(&state.output_dir.join(&entry.path)).await {
+            state.fingerprints.lock().await.push((fingerprint, entry.path.clone()));
+        }
+    }
+    state.image_records.lock().await.insert(entry.path.clone(), entry.clone());
+}
+
+// What an image looks like, for `--dedupe perceptual`. None if it can't be
+// decoded, which leaves it to exact deduplication.
+async fn fingerprint(bytes: bytes::Bytes) -> Option<Fingerprint> {
+    pool::run(move || Fingerprint::of(&bytes).ok()).await
+}
+
+async fn fingerprint_file(path: &Path) -> Option<Fingerprint> {
+    fingerprint(fs::read(path).await.ok()?.into()).await
+}
+
+// Remove saved images that `--dedupe perceptual` found a larger copy of,
+// returning their URLs
+async fn drop_resembling(state: &CrawlState, paths: &[PathBuf]) -> Result<Vec<String>, CrawlError> {
+    let mut urls = Vec::new();
+    for path in paths {
+        let Some(entry) = state.image_records.lock().await.remove(path) else { continue };
+        for file in std::iter::once(&entry.path).chain(&entry.links) {
+            fs::remove_file(state.output_dir.join(file)).await?;
+        }
+        state.saved_hashes.remove(&entry.sha256);
+        state.fingerprints.lock().await.retain(|(_, kept)| kept != path);
+        state.image_sizes.retain(|_, (_, kept)| kept != path);
+        progress!("Replaced: {} (a smaller copy)", path.display());
+        urls.push(entry.url);
+        urls.extend(entry.aliases.into_iter().chain(entry.similar));
+    }
+    Ok(urls)
+}
+
+// Record `url` as another address of the image already saved as `entry`,
+// hard-linking it at `relative_path` with `--hardlink-duplicates`
+async fn keep_duplicate(
+    state: &CrawlState,
+    entry: &mut ImageEntry,
+    url: &Url,
+    relative_path: PathBuf,
+) -> Result<(), CrawlError> {
+    if entry.url != url.as_str() && !entry.aliases.iter().any(|alias| alias == url.as_str()) {
+        entry.aliases.push(url.to_string());
+    }
+    if !state.hardlink_duplicates || relative_path == entry.path || entry.links.contains(&relative_path) {
+        progress!("Duplicate: {} (same content as {})", url, entry.path.display());
+        return Ok(());
+    }
+
+    let path = state.output_dir.join(&relative_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    match fs::hard_link(state.output_dir.join(&entry.path), &path).await {
+        Ok(()) => {
+            progress!("Linked: {} -> {} (same content)", path.display(), entry.path.display());
+            entry.links.push(relative_path);
+        }
+        // Another image already has that name; it's listed as an alias either way
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            progress!("Duplicate: {} (same content as {})", url, entry.path.display());
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+// Move the previous version of a changed image aside under a dated name
+async fn archive_previous(state: &CrawlState, entry: &ImageEntry) -> Result<PathBuf, CrawlError> {
+    let stem = entry.path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = entry.path.extension().unwrap_or_default().to_string_lossy();
+    let archived = entry
+        .path
+        .with_file_name(format!("{}.{}.{}", stem, dates::file_label(dates::unix_now()), extension));
+    fs::rename(state.output_dir.join(&entry.path), state.output_dir.join(&archived)).await?;
+    progress!("Changed: {} (previous version kept as {})", entry.url, archived.display());
+    Ok(archived)
+}
+
+async fn save_image(state: &CrawlState, image: FetchedImage) -> Result<(), CrawlError> {
+    let FetchedImage { url, size: file_size, sha256, body, extension, etag, last_modified, embedded_in, filename, timing } =
+        image;
+
+    let previous = state
+        .previous_images
+        .get(url.as_str())
+        .filter(|entry| state.output_dir.join(&entry.path).exists());
+
+    // The server re-sent identical bytes; keep the existing file
+    if let Some(entry) = previous.filter(|entry| entry.sha256 == sha256) {
+        let entry = ImageEntry { etag, last_modified, ..entry.clone() };
+        keep_previous(state, &entry).await;
+        return Ok(());
+    }
+
+    // Prefer the name the server gave, since image endpoints are often
+    // opaque IDs, then the URL; embedded images have no path to go by. The
+    // extension always comes from the content.
+    let filename = match (&embedded_in, filename) {
+        (Some(_), _) => format!("data-{}", &sha256[..16]),
+        (None, Some(name)) => Path::new(&name)
+            .file_stem()
+            .map_or(name.clone(), |stem| stem.to_string_lossy().into_owned()),
+        (None, None) => url.path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or("image")
+            .to_string(),
+    };
+    
+    let full_filename = format!("{}.{}", filename, extension);
+    
+    // Held until the image is in place, so two copies can't both be saved.
+    // Images with other contents and names are saved alongside.
+    let saving = state.saving.lock(&[&sha256, &full_filename]).await;
+
+    // Create organized directory structure
+    let domain = embedded_in.as_ref().unwrap_or(&url).domain().unwrap_or("unknown");
+    let size_category = get_size_category(file_size);
+    let format_dir = extension.to_string();
+    
+    let relative_path = match state.layout {
+        Layout::Standard => Path::new(&format_dir)
+            .join(domain)              // Format first (jpg, png, ...), then domain
+            .join(size_category)       // Then size
+            .join(&full_filename),
+        // Photographers archiving their galleries look for photos by date
+        Layout::Date => match photo::taken_month(body.head(), last_modified.as_deref()) {
+            Some((year, month)) => Path::new(&format!("{:04}", year)).join(format!("{:02}", month)),
+            None => PathBuf::from("undated"),
+        }
+        .join(&full_filename),
+    };
+    let path = state.output_dir.join(&relative_path);
+
+    // The same bytes under another URL (a CDN variant, another query string)
+    let original = state.saved_hashes.get(&sha256);
+    if let Some(original) = original {
+        if let Some(entry) = state.image_records.lock().await.get_mut(&original) {
+            state.skips.record(Skip::Duplicate, &url, embedded_in.as_ref());
+            return keep_duplicate(state, entry, &url, relative_path).await;
+        }
+    }
+
+    // A resized or recompressed copy of an image already saved: the largest
+    // one is kept, and lists the others as `similar`
+    let fingerprint = match (state.dedupe, &body) {
+        (Dedupe::Exact, _) => None,
+        (Dedupe::Perceptual, ImageBody::Embedded(bytes)) => fingerprint(bytes.clone()).await,
+        (Dedupe::Perceptual, ImageBody::Downloaded(download)) => fingerprint_file(download.path()).await,
+    };
+    let perceptual = match fingerprint {
+        Some(_) => Some(state.perceptual.lock().await),
+        None => None,
+    };
+    let mut resembling = Vec::new();
+    if let Some(fingerprint) = &fingerprint {
+        let fingerprints = state.fingerprints.lock().await;
+        let near = fingerprints.iter().filter(|(other, _)| fingerprint.resembles(other));
+        if let Some((_, larger)) = near.clone().find(|(other, _)| other.pixels >= fingerprint.pixels) {
+            if let Some(entry) = state.image_records.lock().await.get_mut(larger) {
+                if entry.url != url.as_str() && !entry.similar.iter().any(|similar| similar == url.as_str()) {
+                    entry.similar.push(url.to_string());
+                }
+            }
+            progress!("Near-duplicate: {} (a smaller copy of {})", url, larger.display());
+            state.skips.record(Skip::Duplicate, &url, embedded_in.as_ref());
+            return Ok(());
+        }
+        resembling = near.map(|(_, path)| path.clone()).collect();
+    }
+
+    // Check if we have a larger version of this image
+    let smaller = match state.image_sizes.get(&full_filename) {
+        // Skip if this version is smaller
+        Some((existing_size, _)) if file_size <= existing_size => {
+            state.skips.record(Skip::Duplicate, &url, embedded_in.as_ref());
+            return Ok(());
+        }
+        Some((_, existing_path)) => Some(existing_path),
+        None => None,
+    };
+    
+    // Create all necessary directories
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    
+    let mut previous_versions = Vec::new();
+    if let Some(entry) = previous {
+        previous_versions = entry.previous_versions.clone();
+        previous_versions.push(archive_previous(state, entry).await?);
+    }
+
+    // Move the image into place, with the journal recording the attempt so
+    // a crash can be cleaned up after. Embedded images are written next to
+    // their final name first.
+    state.journal.intent(url.as_str(), &path).await?;
+    match body {
+        ImageBody::Embedded(bytes) => {
+            let part = journal::partial_path(&path);
+            let mut file = fs::File::create(&part).await?;
+            file.write_all(&bytes).await?;
+            file.sync_data().await?;
+            drop(file);
+            fs::rename(&part, &path).await?;
+        }
+        ImageBody::Downloaded(download) => download.persist(&path).await?,
+    }
+    state.journal.complete(url.as_str(), &path, file_size).await?;
+    state.bytes_saved.fetch_add(file_size, Ordering::Relaxed);
+
+    // The smaller version saved earlier is replaced, wherever it went
+    state.image_sizes.insert(full_filename, (file_size, relative_path.clone()));
+    if let Some(smaller) = smaller.filter(|smaller| *smaller != relative_path) {
+        if state.image_records.lock().await.remove(&smaller).is_some() {
+            fs::remove_file(state.output_dir.join(&smaller)).await?;
+            state.fingerprints.lock().await.retain(|(_, kept)| *kept != smaller);
+            progress!("Replaced: {} (smaller version)", smaller.display());
+        }
+    }
+    let similar = drop_resembling(state, &resembling).await?;
+    if let Some(fingerprint) = fingerprint {
+        state.fingerprints.lock().await.push((fingerprint, relative_path.clone()));
+    }
+
+    state.saved_hashes.insert(sha256.clone(), relative_path.clone());
+    state.image_records.lock().await.insert(
+        relative_path.clone(),
+        ImageEntry {
+            url: url.to_string(),
+            path: relative_path,
+            size: file_size,
+            sha256: sha256.clone(),
+            etag,
+            last_modified,
+            embedded_in: embedded_in.map(String::from),
+            timing,
+            previous_versions,
+            aliases: Vec::new(),
+            links: Vec::new(),
+            similar,
+        },
+    );
+    
+    progress!("Saved: {} ({})", path.display(), size_category);
+    drop((saving, perceptual));
+    if let Some(hook) = &state.hook {
+        hook.run(&path, &url, &sha256).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use crate::verbosity::progress;
+use anyhow::{bail, Context, Result};
+use scraper::{Html, Selector};
+use serde::Deserialize;
+use url::Url;
+
+// The config file's `[login]` table: a form to sign in with before the
+// crawl starts. The session cookies it sets stay in the run's cookie jar.
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LoginSettings {
+    // The page with the login form
+    pub url: Url,
+    pub username: String,
+    // The password itself, or the environment variable holding it, so it
+    // needn't be written into the file
+    pub password: Option<String>,
+    pub password_env: Option<String>,
+    // The form's field names
+    #[serde(default = "default_username_field")]
+    pub username_field: String,
+    #[serde(default = "default_password_field")]
+    pub password_field: String,
+    // An element holding a CSRF token the form doesn't already carry in a
+    // hidden input, e.g. `meta[name="csrf-token"]`, and the field to send
+    // it in (by default the element's own name)
+    pub csrf: Option<String>,
+    pub csrf_field: Option<String>,
+}
+
+fn default_username_field() -> String {
+    "username".to_string()
+}
+
+fn default_password_field() -> String {
+    "password".to_string()
+}
+
+impl LoginSettings {
+    // Checked when the config is read, so a missing password stops the
+    // crawl before anything is fetched
+    pub fn check(&self) -> Result<()> {
+        match (&self.password, &self.password_env) {
+            (Some(_), Some(_)) => bail!("login takes password or password_env, not both"),
+            (None, None) => bail!("login needs a password or password_env"),
+            _ => {}
+        }
+        if let Some(csrf) = &self.csrf {
+            Selector::parse(csrf).map_err(|_| anyhow::anyhow!("login csrf '{}' isn't a CSS selector", csrf))?;
+        }
+        Ok(())
+    }
+
+    fn password(&self) -> Result<String> {
+        match (&self.password, &self.password_env) {
+            (Some(password), _) => Ok(password.clone()),
+            (None, Some(name)) => std::env::var(name).with_context(|| format!("login password_env {} isn't set", name)),
+            (None, None) => bail!("login needs a password or password_env"),
+        }
+    }
+}
+
+// The form to submit, as found on the login page
+struct Form {
+    action: Url,
+    fields: Vec<(String, String)>,
+    // Whether the page asked for the password field at all
+    has_password: bool,
+}
+
+// Fetch the login page, fill in its form and submit it with `client`,
+// whose cookie jar keeps the session for the crawl
+pub async fn login(client: &reqwest::Client, settings: &LoginSettings) -> Result<()> {
+    progress!("Logging in at {}", settings.url);
+    let response = client
+        .get(settings.url.as_str())
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to fetch the login page {}", settings.url))?;
+    let page = response.url().clone();
+    let html = response.text().await.with_context(|| format!("Failed to read the login page {}", page))?;
+
+    let mut form = find_form(&page, &html, settings)?;
+    form.fields.retain(|(name, _)| *name != settings.username_field && *name != settings.password_field);
+    form.fields.push((settings.username_field.clone(), settings.username.clone()));
+    form.fields.push((settings.password_field.clone(), settings.password()?));
+
+    let response = client
+        .post(form.action.as_str())
+        .form(&form.fields)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Login at {} failed", form.action))?;
+    let landed = response.url().clone();
+    let html = response.text().await.with_context(|| format!("Failed to read the login response from {}", landed))?;
+    // A form that's still asking for the password turned the credentials down
+    if find_form(&landed, &html, settings).is_ok_and(|form| form.has_password) {
+        bail!("Login at {} failed: the page it led to, {}, still has a {} field", form.action, landed, settings.password_field);
+    }
+    progress!("Logged in as {}", settings.username);
+    Ok(())
+}
+
+// The form with the password field (or else the page's first form), with
+// its hidden inputs and the CSRF token if one is named. Kept synchronous so
+// the parsed document never lives across an await.
+fn find_form(page: &Url, html: &str, settings: &LoginSettings) -> Result<Form> {
+    let document = Html::parse_document(html);
+    let form_selector = Selector::parse("form").unwrap();
+    let password_selector = Selector::parse(&format!(r#"input[name="{}"]"#, settings.password_field.replace('"', "\\\"")))
+        .map_err(|_| anyhow::anyhow!("login password_field '{}' isn't a field name", settings.password_field))?;
+    let hidden_selector = Selector::parse(r#"input[type="hidden"][name]"#).unwrap();
+
+    let forms: Vec<_> = document.select(&form_selector).collect();
+    let with_password = forms.iter().find(|form| form.select(&password_selector).next().is_some());
+    let Some(form) = with_password.or(forms.first()) else {
+        bail!("No form on the login page {}", page);
+    };
+
+    let action = match form.value().attr("action").map(str::trim) {
+        Some(action) if !action.is_empty() => {
+            page.join(action).with_context(|| format!("The login form's action {} isn't a URL", action))?
+        }
+        _ => page.clone(),
+    };
+    let mut fields: Vec<(String, String)> = form
+        .select(&hidden_selector)
+        .filter_map(|input| {
+            let element = input.value();
+            Some((element.attr("name")?.to_string(), element.attr("value").unwrap_or_default().to_string()))
+        })
+        .collect();
+
+    if let Some(csrf) = &settings.csrf {
+        let selector = Selector::parse(csrf).map_err(|_| anyhow::anyhow!("login csrf '{}' isn't a CSS selector", csrf))?;
+        let Some(element) = document.select(&selector).next() else {
+            bail!("No {} on the login page {}", csrf, page);
+        };
+        let element = element.value();
+        let Some(token) = element.attr("value").or(element.attr("content")) else {
+            bail!("{} on the login page has no value or content", csrf);
+        };
+        let Some(name) = settings.csrf_field.as_deref().or(element.attr("name")) else {
+            bail!("{} on the login page has no name; set csrf_field", csrf);
+        };
+        fields.retain(|(field, _)| field != name);
+        fields.push((name.to_string(), token.to_string()));
+    }
+
+    Ok(Form { action, fields, has_password: with_password.is_some() })
+}
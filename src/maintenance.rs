@@ -0,0 +1,400 @@
+use crate::archive::{WACZ_FILE, WARC_FILE};
+use crate::blacklist::BLACKLIST_FILE;
+use crate::dates::{date, unix_now, DAY};
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::politeness::{self, HostProfile};
+use crate::quarantine::QUARANTINE_DIR;
+use crate::signing::{self, SIGNATURE_FILE};
+use crate::skips::SKIP_LOG_FILE;
+use crate::stats::{self, RunRecord};
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// A host profile nobody has crawled with for this long says little about
+// how the host copes today, so `gc` lets the next crawl learn it afresh
+const STALE_PROFILE: u64 = 90 * DAY;
+
+// Image files under the output directory, relative to it. Crawl state,
+// the manifest, web archives, the skip log and blacklist, quarantined
+// downloads and other dotfiles are not images and are left alone.
+pub fn image_files(output_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![output_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let not_images = [MANIFEST_FILE, SIGNATURE_FILE, WARC_FILE, WACZ_FILE, SKIP_LOG_FILE, BLACKLIST_FILE, QUARANTINE_DIR];
+            if name.starts_with('.') || (dir == output_dir && not_images.contains(&name.as_ref())) {
+                continue;
+            }
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if let Ok(relative) = path.strip_prefix(output_dir) {
+                files.push(relative.to_path_buf());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Remove directories left empty, deepest first
+fn remove_empty_dirs(dir: &Path, root: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+            remove_empty_dirs(&entry.path(), root)?;
+        }
+    }
+    if dir != root && std::fs::read_dir(dir)?.next().is_none() {
+        std::fs::remove_dir(dir)?;
+    }
+    Ok(())
+}
+
+// Every file the manifest accounts for: the images, their earlier versions
+// and hard links to them
+fn referenced(manifest: &Manifest) -> HashSet<&Path> {
+    let mut referenced: HashSet<&Path> = HashSet::new();
+    for image in &manifest.images {
+        referenced.insert(&image.path);
+        referenced.extend(image.previous_versions.iter().map(PathBuf::as_path));
+        referenced.extend(image.links.iter().map(PathBuf::as_path));
+    }
+    referenced
+}
+
+// Image files under the output directory that no manifest record accounts for
+fn orphan_images(output_dir: &Path, manifest: &Manifest) -> Result<Vec<PathBuf>> {
+    let referenced = referenced(manifest);
+    Ok(image_files(output_dir)?.into_iter().filter(|path| !referenced.contains(path.as_path())).collect())
+}
+
+// `prune` subcommand: delete images the latest crawl no longer references,
+// keeping a mirrored archive in sync with the live site
+pub fn prune(output_dir: &Path, dry_run: bool) -> Result<()> {
+    let manifest = Manifest::load(output_dir)?;
+    let orphans = orphan_images(output_dir, &manifest)?;
+
+    let verb = if dry_run { "Would remove" } else { "Removing" };
+    let mut bytes = 0;
+    for orphan in &orphans {
+        let path = output_dir.join(orphan);
+        bytes += std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        println!("{}: {}", verb, orphan.display());
+        if !dry_run {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    if !dry_run {
+        remove_empty_dirs(output_dir, output_dir)?;
+    }
+
+    println!(
+        "{} {} orphaned images ({} bytes); {} referenced by the latest crawl",
+        if dry_run { "Would prune" } else { "Pruned" },
+        orphans.len(),
+        bytes,
+        referenced(&manifest).len()
+    );
+    Ok(())
+}
+
+// Leftovers of interrupted writes: `.part` downloads and `.tmp` manifests
+// and snapshots that never got renamed into place
+fn stray_temp_files(output_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut strays = Vec::new();
+    let mut dirs = vec![output_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.ends_with(".part") || (name.starts_with('.') && name.ends_with(".tmp")) {
+                    strays.push(path);
+                }
+            }
+        }
+    }
+    strays.sort();
+    Ok(strays)
+}
+
+// Write `text` under a temporary name and rename it over `path`
+fn replace(path: &Path, text: &[u8]) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+    std::fs::write(&tmp, text)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// Drop the lines of a JSON-lines file that no longer parse, such as the
+// half-written last line of a crawl that was killed. Returns how many.
+fn drop_unreadable_lines(path: &Path, parses: impl Fn(&str) -> bool, verb: &str, dry_run: bool) -> Result<usize> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let (kept, unreadable): (Vec<&str>, Vec<&str>) =
+        text.lines().filter(|line| !line.trim().is_empty()).partition(|line| parses(line));
+    if unreadable.is_empty() {
+        return Ok(0);
+    }
+    println!("{} {} unreadable lines from {}", verb, unreadable.len(), path.display());
+    if !dry_run {
+        let mut rest = kept.join("\n");
+        if !rest.is_empty() {
+            rest.push('\n');
+        }
+        replace(path, rest.as_bytes())?;
+    }
+    Ok(unreadable.len())
+}
+
+// Drop host profiles that haven't been updated in STALE_PROFILE, or the whole
+// file if it can't be read (crawls ignore it then anyway). Returns how many.
+fn drop_stale_profiles(output_dir: &Path, verb: &str, dry_run: bool) -> Result<usize> {
+    let path = politeness::profiles_path(output_dir);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut profiles: BTreeMap<String, HostProfile> = match serde_json::from_slice(&bytes) {
+        Ok(profiles) => profiles,
+        Err(_) => {
+            println!("{} unreadable host profiles: {}", verb, path.display());
+            if !dry_run {
+                std::fs::remove_file(&path)?;
+            }
+            return Ok(1);
+        }
+    };
+    let cutoff = unix_now().saturating_sub(STALE_PROFILE);
+    let before = profiles.len();
+    profiles.retain(|host, profile| {
+        let keep = profile.updated_at >= cutoff;
+        if !keep {
+            println!("{} host profile: {} (last crawled {})", verb, host, date(profile.updated_at));
+        }
+        keep
+    });
+    let dropped = before - profiles.len();
+    if dropped > 0 && !dry_run {
+        replace(&path, &serde_json::to_vec_pretty(&profiles)?)?;
+    }
+    Ok(dropped)
+}
+
+// `gc` subcommand: bring metadata and files back in line in a long-lived
+// output directory, in both directions. Manifest records whose files are
+// gone are dropped and image files no record accounts for are deleted, as
+// are temporary files from interrupted writes, half-written lines in the
+// skip log and run history, and host profiles gone stale.
+pub async fn gc(output_dir: &Path, dry_run: bool) -> Result<()> {
+    let verb = if dry_run { "Would remove" } else { "Removing" };
+
+    let strays = stray_temp_files(output_dir)?;
+    for stray in &strays {
+        println!("{} temporary file: {}", verb, stray.display());
+        if !dry_run {
+            std::fs::remove_file(stray)?;
+        }
+    }
+
+    let mut dropped_records = 0;
+    let mut orphans = Vec::new();
+    if output_dir.join(MANIFEST_FILE).exists() {
+        let mut manifest = Manifest::load(output_dir)?;
+        let exists = |path: &Path| output_dir.join(path).exists();
+
+        let before = manifest.images.len();
+        manifest.images.retain(|image| {
+            let keep = exists(&image.path);
+            if !keep {
+                println!("{} manifest record: {} ({})", verb, image.path.display(), image.url);
+            }
+            keep
+        });
+        dropped_records += before - manifest.images.len();
+
+        for image in &mut manifest.images {
+            image.previous_versions.retain(|version| {
+                let keep = exists(version);
+                if !keep {
+                    println!("{} previous version record: {}", verb, version.display());
+                    dropped_records += 1;
+                }
+                keep
+            });
+            image.links.retain(|link| {
+                let keep = exists(link);
+                if !keep {
+                    println!("{} hard link record: {}", verb, link.display());
+                    dropped_records += 1;
+                }
+                keep
+            });
+        }
+
+        if dropped_records > 0 && !dry_run {
+            manifest.write(output_dir).await?;
+        }
+
+        // Without a manifest every image would look orphaned, so this only
+        // runs against one
+        orphans = orphan_images(output_dir, &manifest)?;
+        orphans.retain(|orphan| !strays.contains(&output_dir.join(orphan)));
+        for orphan in &orphans {
+            println!("{} image with no manifest record: {}", verb, orphan.display());
+            if !dry_run {
+                std::fs::remove_file(output_dir.join(orphan))?;
+            }
+        }
+        if !orphans.is_empty() && !dry_run {
+            remove_empty_dirs(output_dir, output_dir)?;
+        }
+    }
+
+    let parses_json = |line: &str| serde_json::from_str::<serde_json::Value>(line).is_ok();
+    let parses_run = |line: &str| serde_json::from_str::<RunRecord>(line).is_ok();
+    let unreadable_lines = drop_unreadable_lines(&output_dir.join(SKIP_LOG_FILE), parses_json, verb, dry_run)?
+        + drop_unreadable_lines(&stats::db_path(output_dir), parses_run, verb, dry_run)?;
+    let stale_profiles = drop_stale_profiles(output_dir, verb, dry_run)?;
+
+    println!(
+        "{}: {} stale manifest records, {} orphaned images, {} temporary files, {} unreadable log lines, {} stale host profiles",
+        if dry_run { "Would clean up" } else { "Cleaned up" },
+        dropped_records,
+        orphans.len(),
+        strays.len(),
+        unreadable_lines,
+        stale_profiles
+    );
+    Ok(())
+}
+
+// `verify` subcommand: check every image in the manifest is on disk with the
+// size and content it was saved with, and the manifest against its signature
+// if it was signed
+pub fn verify(output_dir: &Path) -> Result<()> {
+    let manifest = Manifest::load(output_dir)?;
+    let mut problems = 0;
+    for image in &manifest.images {
+        let problem = match std::fs::read(output_dir.join(&image.path)) {
+            Err(e) => Some(format!("unreadable ({})", e)),
+            Ok(bytes) if bytes.len() as u64 != image.size => {
+                Some(format!("{} bytes, expected {}", bytes.len(), image.size))
+            }
+            Ok(bytes) if format!("{:x}", Sha256::digest(&bytes)) != image.sha256 => Some("content changed".to_string()),
+            Ok(_) => None,
+        };
+        if let Some(problem) = problem {
+            println!("{}: {}", image.path.display(), problem);
+            problems += 1;
+        }
+        // Hard links share the file's content, so it's enough that they're
+        // still there and still the same size
+        for link in &image.links {
+            let problem = match std::fs::metadata(output_dir.join(link)) {
+                Err(e) => Some(format!("unreadable ({})", e)),
+                Ok(metadata) if metadata.len() != image.size => Some(format!("no longer a link to {}", image.path.display())),
+                Ok(_) => None,
+            };
+            if let Some(problem) = problem {
+                println!("{}: {}", link.display(), problem);
+                problems += 1;
+            }
+        }
+    }
+
+    if problems > 0 {
+        bail!("{} of {} images failed verification", problems, manifest.images.len());
+    }
+    println!("All {} images match the manifest", manifest.images.len());
+    if let Some(public_key) = signing::check(output_dir)? {
+        println!("Manifest signature is valid (ed25519 public key {})", public_key);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh output directory holding `files` and a manifest listing `records`
+    fn output_dir(name: &str, files: &[&str], records: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rusty_spider_gc_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"image").unwrap();
+        }
+        let images: Vec<serde_json::Value> = records
+            .iter()
+            .map(|path| serde_json::json!({ "url": format!("https://example.com/{}", path), "path": path, "size": 5, "sha256": "" }))
+            .collect();
+        let manifest = serde_json::json!({ "generated_at": 0, "pages": [], "images": images });
+        std::fs::write(dir.join(MANIFEST_FILE), manifest.to_string()).unwrap();
+        dir
+    }
+
+    fn recorded(dir: &Path) -> Vec<PathBuf> {
+        Manifest::load(dir).unwrap().images.into_iter().map(|image| image.path).collect()
+    }
+
+    #[tokio::test]
+    async fn drops_records_whose_files_are_gone() {
+        let dir = output_dir("records", &["kept.jpg"], &["kept.jpg", "deleted.jpg"]);
+        gc(&dir, false).await.unwrap();
+        assert_eq!(recorded(&dir), vec![PathBuf::from("kept.jpg")]);
+        assert!(dir.join("kept.jpg").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn deletes_images_no_record_accounts_for() {
+        let dir = output_dir("orphans", &["kept.jpg", "orphan.jpg", "old/orphan.png", SKIP_LOG_FILE], &["kept.jpg"]);
+        gc(&dir, false).await.unwrap();
+        assert!(dir.join("kept.jpg").exists());
+        assert!(!dir.join("orphan.jpg").exists());
+        assert!(!dir.join("old").exists());
+        assert!(dir.join(SKIP_LOG_FILE).exists());
+        assert_eq!(recorded(&dir), vec![PathBuf::from("kept.jpg")]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn dry_run_changes_nothing() {
+        let dir = output_dir("dry_run", &["orphan.jpg"], &["deleted.jpg"]);
+        gc(&dir, true).await.unwrap();
+        assert!(dir.join("orphan.jpg").exists());
+        assert_eq!(recorded(&dir), vec![PathBuf::from("deleted.jpg")]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn drops_half_written_log_lines() {
+        let dir = output_dir("logs", &[], &[]);
+        let log = dir.join(SKIP_LOG_FILE);
+        std::fs::write(&log, "{\"url\":\"https://example.com/a.gif\",\"reason\":\"format\"}\n{\"url\":\"https://exa").unwrap();
+        gc(&dir, false).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "{\"url\":\"https://example.com/a.gif\",\"reason\":\"format\"}\n"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,324 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PageEntry {
+    pub url: String,
+    pub sha256: String,
+    // Manifests from before these were recorded leave them at their defaults
+    #[serde(default)]
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    // Body size in bytes
+    #[serde(default)]
+    pub content_length: u64,
+    // Links followed from the seed, and the page the link was on. Unknown
+    // for pages crawled by `worker`, whose shared frontier holds bare URLs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    // In-scope images the page references, embedded ones included
+    #[serde(default)]
+    pub images: usize,
+    // The page's `<link rel="alternate" hreflang>` declarations, language to URL
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub alternates: BTreeMap<String, String>,
+    // Shared by pages that are translations of one another, so the same
+    // article across locales can be found together: the x-default URL when
+    // one is declared, and otherwise the first of their URLs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alternate_group: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ImageEntry {
+    pub url: String,
+    // Relative to the output directory
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+    // Validators from the response, used for conditional re-downloads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    // The page a `data:` image was embedded in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedded_in: Option<String>,
+    // How the download that produced the file performed; unset for `data:` images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Timing>,
+    // Earlier versions of the image, kept under dated names when it changed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous_versions: Vec<PathBuf>,
+    // Other URLs that served exactly these bytes, which weren't saved again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    // With `--hardlink-duplicates`, hard links to this file where the
+    // aliases would have been saved
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<PathBuf>,
+    // With `--dedupe perceptual`, URLs of smaller copies of the image that
+    // were dropped in its favour
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub similar: Vec<String>,
+}
+
+// An image `--metadata-only` found, described from its first bytes
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct InventoryEntry {
+    pub url: String,
+    // The extension it would have been saved with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    // Bytes, when the server said
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    // Every page it was found on
+    pub referrers: Vec<String>,
+}
+
+// Request timings in milliseconds. reqwest doesn't expose DNS or connect
+// times, so these are measured around the request as a whole: `ttfb_ms`
+// until the response headers arrived, `total_ms` until the body was read.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Timing {
+    pub ttfb_ms: u64,
+    pub total_ms: u64,
+}
+
+impl Timing {
+    pub fn new(ttfb: Duration, total: Duration) -> Timing {
+        Timing { ttfb_ms: ttfb.as_millis() as u64, total_ms: total.as_millis() as u64 }
+    }
+}
+
+// A page or image the crawl gave up on
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ErrorEntry {
+    pub url: String,
+    // For images, the page that referenced them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<String>,
+    // `CrawlError::kind`
+    pub kind: String,
+    pub message: String,
+}
+
+// What a run left in its output directory
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    pub generated_at: u64,
+    pub pages: Vec<PageEntry>,
+    pub images: Vec<ImageEntry>,
+    // Images catalogued but not saved, by `--metadata-only`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inventory: Vec<InventoryEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<ErrorEntry>,
+}
+
+impl Manifest {
+    pub fn load(dir: &Path) -> Result<Manifest> {
+        let path = dir.join(MANIFEST_FILE);
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read manifest {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("Failed to parse manifest {}", path.display()))
+    }
+
+    // Written under a temporary name first so readers never see half a manifest
+    pub async fn write(&self, dir: &Path) -> Result<PathBuf> {
+        let path = dir.join(MANIFEST_FILE);
+        let tmp = dir.join(format!(".{}.tmp", MANIFEST_FILE));
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(self)?).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(path)
+    }
+}
+
+// Fill in `alternate_group` for pages linked by hreflang alternates. Sites
+// don't always declare them both ways, so a link in either direction joins
+// two pages, as does a chain of them through pages that weren't crawled.
+pub fn group_alternates(pages: &mut [PageEntry]) {
+    let mut linked: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for page in pages.iter() {
+        for alternate in page.alternates.values() {
+            linked.entry(&page.url).or_default().insert(alternate);
+            linked.entry(alternate).or_default().insert(&page.url);
+        }
+    }
+    let x_defaults: BTreeSet<&str> =
+        pages.iter().filter_map(|page| page.alternates.get("x-default")).map(String::as_str).collect();
+
+    let mut groups: BTreeMap<String, String> = BTreeMap::new();
+    for start in linked.keys() {
+        if groups.contains_key(*start) {
+            continue;
+        }
+        let mut members = BTreeSet::from([*start]);
+        let mut pending = vec![*start];
+        while let Some(url) = pending.pop() {
+            for next in &linked[url] {
+                if members.insert(next) {
+                    pending.push(next);
+                }
+            }
+        }
+        // BTreeSets iterate in order, so this is the first URL when no
+        // x-default is declared
+        let group = members.iter().find(|url| x_defaults.contains(*url)).unwrap_or(members.first().unwrap());
+        for url in &members {
+            groups.insert(url.to_string(), group.to_string());
+        }
+    }
+
+    for page in pages.iter_mut() {
+        page.alternate_group = groups.remove(&page.url);
+    }
+}
+
+// URL -> content hash for one side of a diff
+fn by_url<'a>(entries: impl Iterator<Item = (&'a str, &'a str)>) -> BTreeMap<&'a str, &'a str> {
+    entries.collect()
+}
+
+fn print_changes(kind: &str, old: BTreeMap<&str, &str>, new: BTreeMap<&str, &str>) {
+    let added: Vec<&str> = new.keys().filter(|url| !old.contains_key(*url)).copied().collect();
+    let removed: Vec<&str> = old.keys().filter(|url| !new.contains_key(*url)).copied().collect();
+    let changed: Vec<&str> = new
+        .iter()
+        .filter(|(url, hash)| old.get(*url).is_some_and(|old_hash| old_hash != *hash))
+        .map(|(url, _)| *url)
+        .collect();
+
+    println!(
+        "{}: {} added, {} removed, {} changed ({} unchanged)",
+        kind,
+        added.len(),
+        removed.len(),
+        changed.len(),
+        new.len() - added.len() - changed.len()
+    );
+    for url in added {
+        println!("  + {}", url);
+    }
+    for url in removed {
+        println!("  - {}", url);
+    }
+    for url in changed {
+        println!("  ~ {}", url);
+    }
+}
+
+// `diff` subcommand: what changed on the site between two crawls
+pub fn print_diff(old_dir: &Path, new_dir: &Path) -> Result<()> {
+    let old = Manifest::load(old_dir)?;
+    let new = Manifest::load(new_dir)?;
+
+    print_changes(
+        "Pages",
+        by_url(old.pages.iter().map(|page| (page.url.as_str(), page.sha256.as_str()))),
+        by_url(new.pages.iter().map(|page| (page.url.as_str(), page.sha256.as_str()))),
+    );
+    print_changes(
+        "Images",
+        by_url(old.images.iter().map(|image| (image.url.as_str(), image.sha256.as_str()))),
+        by_url(new.images.iter().map(|image| (image.url.as_str(), image.sha256.as_str()))),
+    );
+    Ok(())
+}
+
+// `query` subcommand: list manifest entries whose URL or path contains
+// `pattern`, images by default and pages with `pages`
+pub fn print_query(output_dir: &Path, pattern: &str, pages: bool) -> Result<()> {
+    let manifest = Manifest::load(output_dir)?;
+    let pattern = pattern.to_lowercase();
+    let matches = |text: &str| text.to_lowercase().contains(&pattern);
+
+    let mut found = 0;
+    if pages {
+        for page in manifest.pages.iter().filter(|page| matches(&page.url)) {
+            println!("{}  {:>3}  {}", &page.sha256[..12], page.status, page.url);
+            found += 1;
+        }
+    } else {
+        for image in &manifest.images {
+            if matches(&image.url) || matches(&image.path.to_string_lossy()) {
+                println!("{:>10}  {}  {}", image.size, image.path.display(), image.url);
+                found += 1;
+            }
+        }
+    }
+    println!("{} {} matched", found, if pages { "pages" } else { "images" });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(url: &str, alternates: &[(&str, &str)]) -> PageEntry {
+        PageEntry {
+            url: url.to_string(),
+            sha256: String::new(),
+            status: 200,
+            title: None,
+            content_length: 0,
+            depth: None,
+            parent: None,
+            images: 0,
+            alternates: alternates.iter().map(|(language, url)| (language.to_string(), url.to_string())).collect(),
+            alternate_group: None,
+        }
+    }
+
+    fn groups(pages: &[PageEntry]) -> Vec<Option<&str>> {
+        pages.iter().map(|page| page.alternate_group.as_deref()).collect()
+    }
+
+    #[test]
+    fn a_link_either_way_joins_a_group_under_its_first_url() {
+        let mut pages = vec![
+            page("https://example.com/fr/", &[]),
+            page("https://example.com/en/", &[("fr", "https://example.com/fr/")]),
+            page("https://example.com/about", &[]),
+        ];
+        group_alternates(&mut pages);
+        assert_eq!(groups(&pages), [Some("https://example.com/en/"), Some("https://example.com/en/"), None]);
+    }
+
+    #[test]
+    fn x_default_names_the_group() {
+        let mut pages = vec![
+            page("https://example.com/de/", &[("x-default", "https://example.com/"), ("en", "https://example.com/en/")]),
+            page("https://example.com/en/", &[("de", "https://example.com/de/")]),
+        ];
+        group_alternates(&mut pages);
+        assert_eq!(groups(&pages), [Some("https://example.com/"), Some("https://example.com/")]);
+    }
+
+    #[test]
+    fn groups_chain_through_pages_not_crawled() {
+        let mut pages = vec![
+            page("https://example.com/a/en", &[("fr", "https://example.com/a/fr")]),
+            page("https://example.com/a/de", &[("fr", "https://example.com/a/fr")]),
+            page("https://example.com/b/en", &[("fr", "https://example.com/b/fr")]),
+        ];
+        group_alternates(&mut pages);
+        assert_eq!(
+            groups(&pages),
+            [Some("https://example.com/a/de"), Some("https://example.com/a/de"), Some("https://example.com/b/en")]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use crate::dates;
+use crate::manifest::{self, ErrorEntry, ImageEntry, InventoryEntry, Manifest, PageEntry, MANIFEST_FILE};
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// `merge` subcommand: combine crawls of one site made from different
+// machines into a single output directory. Pages are taken from the newest
+// crawl that has them; images are kept once per content hash, the other
+// URLs becoming aliases, and the files are hard-linked rather than copied
+// where the filesystem allows.
+pub async fn merge(sources: &[PathBuf], target: &Path) -> Result<()> {
+    if target.join(MANIFEST_FILE).exists() {
+        bail!("{} already holds a crawl; merge into a new directory", target.display());
+    }
+    let mut crawls = Vec::new();
+    for source in sources {
+        crawls.push((source.as_path(), Manifest::load(source)?));
+    }
+    crawls.sort_by_key(|(_, manifest)| manifest.generated_at);
+    std::fs::create_dir_all(target).with_context(|| format!("Failed to create {}", target.display()))?;
+
+    let mut pages: BTreeMap<String, PageEntry> = BTreeMap::new();
+    let mut merged = Merged { target, images: BTreeMap::new(), by_hash: HashMap::new(), duplicates: 0 };
+    let mut inventory: BTreeMap<String, InventoryEntry> = BTreeMap::new();
+    let mut errors = Vec::new();
+    for (source, manifest) in crawls {
+        pages.extend(manifest.pages.into_iter().map(|page| (page.url.clone(), page)));
+        for image in manifest.images {
+            merged.add(source, image)?;
+        }
+        // `--metadata-only` crawls: the newest description, every referrer
+        for mut entry in manifest.inventory {
+            if let Some(earlier) = inventory.remove(&entry.url) {
+                let referrers = earlier.referrers.into_iter().filter(|page| !entry.referrers.contains(page));
+                entry.referrers.extend(referrers.collect::<Vec<_>>());
+            }
+            inventory.insert(entry.url.clone(), entry);
+        }
+        errors.extend(manifest.errors);
+    }
+
+    // A failure in one crawl doesn't count if another crawl got the URL
+    let fetched: HashSet<&str> = pages
+        .keys()
+        .map(String::as_str)
+        .chain(merged.images.values().flat_map(|image| std::iter::once(&image.url).chain(&image.aliases)).map(String::as_str))
+        .chain(inventory.keys().map(String::as_str))
+        .collect();
+    let mut seen = HashSet::new();
+    let errors: Vec<ErrorEntry> = errors
+        .into_iter()
+        .filter(|error| !fetched.contains(error.url.as_str()) && seen.insert((error.url.clone(), error.kind.clone())))
+        .collect();
+
+    let mut manifest = Manifest {
+        generated_at: dates::unix_now(),
+        pages: pages.into_values().collect(),
+        images: merged.images.into_values().collect(),
+        inventory: inventory.into_values().collect(),
+        errors,
+    };
+    manifest::group_alternates(&mut manifest.pages);
+    manifest.errors.sort_by(|a, b| a.url.cmp(&b.url));
+    manifest.write(target).await?;
+
+    println!(
+        "Merged {} crawls into {}: {} pages, {} images ({} duplicates folded in)",
+        sources.len(),
+        target.display(),
+        manifest.pages.len(),
+        manifest.images.len(),
+        merged.duplicates
+    );
+    Ok(())
+}
+
+struct Merged<'a> {
+    target: &'a Path,
+    // By path in the merged directory
+    images: BTreeMap<PathBuf, ImageEntry>,
+    // Content hash -> where it was placed
+    by_hash: HashMap<String, PathBuf>,
+    duplicates: usize,
+}
+
+impl Merged<'_> {
+    fn add(&mut self, source: &Path, mut image: ImageEntry) -> Result<()> {
+        // Seen in an earlier crawl: only the URLs and file locations are new
+        if let Some(path) = self.by_hash.get(&image.sha256) {
+            let entry = self.images.get_mut(path).unwrap();
+            for url in std::iter::once(image.url).chain(image.aliases) {
+                if entry.url != url && !entry.aliases.contains(&url) {
+                    entry.aliases.push(url);
+                }
+            }
+            for url in image.similar {
+                if !entry.similar.contains(&url) {
+                    entry.similar.push(url);
+                }
+            }
+            // Where the other crawl kept the file is re-linked to this copy
+            for location in std::iter::once(image.path).chain(image.links) {
+                if location != entry.path
+                    && !entry.links.contains(&location)
+                    && !self.target.join(&location).exists()
+                    && place(&self.target.join(&entry.path), &self.target.join(&location))?
+                {
+                    entry.links.push(location);
+                }
+            }
+            self.duplicates += 1;
+            return Ok(());
+        }
+
+        // Different images can share a name across crawls; the later one
+        // gets its hash added to tell them apart
+        let original = source.join(&image.path);
+        if self.target.join(&image.path).exists() {
+            let stem = image.path.file_stem().unwrap_or_default().to_string_lossy();
+            let extension = image.path.extension().unwrap_or_default().to_string_lossy();
+            image.path = image.path.with_file_name(format!("{}.{}.{}", stem, &image.sha256[..12], extension));
+        }
+        if !place(&original, &self.target.join(&image.path))? {
+            println!("Skipping {}: {} is missing", image.url, original.display());
+            return Ok(());
+        }
+        let links = std::mem::take(&mut image.links);
+        for link in links {
+            if !self.target.join(&link).exists() && place(&original, &self.target.join(&link))? {
+                image.links.push(link);
+            }
+        }
+        let versions = std::mem::take(&mut image.previous_versions);
+        for version in versions {
+            if !self.target.join(&version).exists() && place(&source.join(&version), &self.target.join(&version))? {
+                image.previous_versions.push(version);
+            }
+        }
+
+        self.by_hash.insert(image.sha256.clone(), image.path.clone());
+        self.images.insert(image.path.clone(), image);
+        Ok(())
+    }
+}
+
+// Put a copy of `from` at `to`: a hard link, or a real copy when they're
+// on different filesystems. False if `from` doesn't exist.
+fn place(from: &Path, to: &Path) -> Result<bool> {
+    if !from.exists() {
+        return Ok(false);
+    }
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::hard_link(from, to).is_err() {
+        std::fs::copy(from, to).with_context(|| format!("Failed to copy {} to {}", from.display(), to.display()))?;
+    }
+    Ok(true)
+}
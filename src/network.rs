@@ -0,0 +1,82 @@
+use crate::pause::{PauseGate, PauseReason};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use url::Url;
+
+// The check made before pausing, so a host that's down can't stall it long
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Watches for runs of connection failures that mean our own network is gone
+// (rather than one host being down), pauses the crawl, and probes until the
+// network comes back.
+pub struct NetworkMonitor {
+    consecutive_failures: AtomicU32,
+    threshold: u32,
+    probe_interval: Duration,
+    probe_url: Url,
+    client: reqwest::Client,
+}
+
+impl NetworkMonitor {
+    pub fn new(client: reqwest::Client, probe_url: Url, threshold: u32, probe_interval: Duration) -> NetworkMonitor {
+        NetworkMonitor {
+            consecutive_failures: AtomicU32::new(0),
+            threshold,
+            probe_interval,
+            probe_url,
+            client,
+        }
+    }
+
+    pub fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    // Record a failure to connect. Returns true if the network is now
+    // considered down, in which case the request is worth retrying once the
+    // gate opens again. A run of failures is only put down to our network
+    // if the probe URL can't be reached either; otherwise it's the hosts
+    // that are down, and their requests fail as usual.
+    pub async fn record_failure(self: &Arc<Self>, gate: &Arc<PauseGate>) -> bool {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures < self.threshold {
+            return false;
+        }
+        if gate.held_by(PauseReason::NetworkDown) {
+            return true;
+        }
+        if self.client.head(self.probe_url.as_str()).timeout(PROBE_TIMEOUT).send().await.is_ok() {
+            self.record_success();
+            return false;
+        }
+
+        if gate.pause(PauseReason::NetworkDown) {
+            println!(
+                "Network appears to be down ({} consecutive connection failures), pausing crawl",
+                failures
+            );
+            tokio::spawn(self.clone().probe_until_online(gate.clone()));
+        }
+        true
+    }
+
+    async fn probe_until_online(self: Arc<Self>, gate: Arc<PauseGate>) {
+        loop {
+            sleep(self.probe_interval).await;
+            if self.client.head(self.probe_url.as_str()).send().await.is_ok() {
+                println!("Network is back, resuming crawl");
+                self.record_success();
+                gate.resume(PauseReason::NetworkDown);
+                return;
+            }
+        }
+    }
+}
+
+// Errors that say nothing about the URL itself, only that we couldn't reach
+// it. A timeout is left out: that's a host being slow, not a lost network.
+pub fn is_connection_error(error: &reqwest::Error) -> bool {
+    error.is_connect() && !error.is_timeout()
+}
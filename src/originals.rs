@@ -0,0 +1,201 @@
+use url::Url;
+
+// Query parameters image services and CMSes use to serve a resized or
+// recompressed copy; without them the same URL returns the original
+const RESIZE_PARAMS: &[&str] = &[
+    "w", "h", "width", "height", "resize", "size", "fit", "crop", "rect", "ar", "dpr", "q", "quality", "auto", "fm",
+    "format",
+];
+
+// imgix signs the full query, so a signature is meaningless once the
+// parameters it covered are gone
+const IMGIX_SIGNATURE: &str = "s";
+
+// Shopify's older named sizes, as in `shirt_grande.jpg`
+const SHOPIFY_NAMED_SIZES: &[&str] =
+    &["pico", "icon", "thumb", "small", "compact", "medium", "large", "grande", "1024x1024", "2048x2048", "master"];
+
+// Rewrites image URLs that ask for a resized copy into URLs for the
+// full-size original, for `--original-images`. Besides the query
+// parameters, image CDNs that put the transform in the path are recognized,
+// so every variant of an image dedups to its one source file.
+pub struct Originals {
+    // Hosts whose image URLs are left alone, lowercased
+    keep_hosts: Vec<String>,
+}
+
+impl Originals {
+    pub fn new(keep_hosts: Vec<String>) -> Originals {
+        Originals { keep_hosts }
+    }
+
+    pub fn rewrite(&self, url: &mut Url) {
+        let Some(host) = url.host_str() else { return };
+        if self.keep_hosts.iter().any(|keep| keep == host) {
+            return;
+        }
+        let host = host.to_string();
+        if let Some(path) = cloudinary_original(url.path())
+            .or_else(|| wordpress_original(url.path()))
+            .or_else(|| shopify_original(&host, url.path()))
+        {
+            url.set_path(&path);
+        }
+        strip_resize_params(url, &host);
+    }
+}
+
+// Drop RESIZE_PARAMS from the query, leaving other queries untouched
+fn strip_resize_params(url: &mut Url, host: &str) {
+    if url.query().is_none() {
+        return;
+    }
+    let is_resize = |key: &str| RESIZE_PARAMS.contains(&key.to_ascii_lowercase().as_str());
+    if !url.query_pairs().any(|(key, _)| is_resize(&key)) {
+        return;
+    }
+    let imgix = host.ends_with(".imgix.net");
+    let kept: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !(is_resize(key) || imgix && key == IMGIX_SIGNATURE))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+
+    if kept.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(kept);
+    }
+}
+
+// Cloudinary puts transforms between the delivery type and the asset:
+// `/demo/image/upload/w_300,h_200,c_fill/v1612/sample.jpg` is a resized
+// `/demo/image/upload/v1612/sample.jpg`. Chained transforms take one path
+// segment each.
+fn cloudinary_original(path: &str) -> Option<String> {
+    let (prefix, rest) = ["/image/upload/", "/image/private/", "/image/authenticated/"]
+        .iter()
+        .find_map(|marker| path.find(marker).map(|pos| path.split_at(pos + marker.len())))?;
+    let segments: Vec<&str> = rest.split('/').collect();
+    let transforms = segments.iter().take_while(|segment| is_cloudinary_transform(segment)).count();
+    // The last segment is the asset itself, whatever it looks like
+    if transforms == 0 || transforms >= segments.len() {
+        return None;
+    }
+    Some(format!("{}{}", prefix, segments[transforms..].join("/")))
+}
+
+// `w_300,h_200,c_fill` or `q_auto`: comma-separated parameters, each a short
+// lowercase key, an underscore and a value
+fn is_cloudinary_transform(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment.split(',').all(|param| {
+            param.split_once('_').is_some_and(|(key, value)| {
+                (1..=3).contains(&key.len()) && key.bytes().all(|b| b.is_ascii_lowercase()) && !value.is_empty()
+            })
+        })
+}
+
+// WordPress saves resized uploads next to the original with the size in the
+// name: `/wp-content/uploads/2024/05/photo-300x200.jpg` for `photo.jpg`
+fn wordpress_original(path: &str) -> Option<String> {
+    if !path.contains("/wp-content/uploads/") {
+        return None;
+    }
+    strip_size_suffix(path, '-')
+}
+
+// Shopify's CDN serves sizes as `shirt_600x600.jpg` (or `_600x`, `_x600`),
+// or by name as `shirt_grande.jpg`
+fn shopify_original(host: &str, path: &str) -> Option<String> {
+    if host != "cdn.shopify.com" && !path.starts_with("/cdn/shop/") {
+        return None;
+    }
+    strip_size_suffix(path, '_').or_else(|| {
+        let dot = path.rfind('.').filter(|&dot| dot > path.rfind('/').unwrap_or(0))?;
+        let (stem, extension) = path.split_at(dot);
+        let (name, size) = stem.rsplit_once('_')?;
+        (SHOPIFY_NAMED_SIZES.contains(&size) && !name.ends_with('/')).then(|| format!("{}{}", name, extension))
+    })
+}
+
+// Remove `<separator><width>x<height>` just before the file extension. Either
+// dimension may be missing, but not both.
+fn strip_size_suffix(path: &str, separator: char) -> Option<String> {
+    let dot = path.rfind('.').filter(|&dot| dot > path.rfind('/').unwrap_or(0))?;
+    let (stem, extension) = path.split_at(dot);
+    let start = stem.rfind(separator)?;
+    let (width, height) = stem[start + 1..].split_once('x')?;
+    let is_number = |text: &str| text.bytes().all(|b| b.is_ascii_digit());
+    if !is_number(width) || !is_number(height) || (width.is_empty() && height.is_empty()) {
+        return None;
+    }
+    Some(format!("{}{}", &stem[..start], extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn original(url: &str) -> String {
+        let mut url = Url::parse(url).unwrap();
+        Originals::new(Vec::new()).rewrite(&mut url);
+        url.into()
+    }
+
+    #[test]
+    fn cloudinary_transform_chains() {
+        assert_eq!(
+            original("https://res.cloudinary.com/demo/image/upload/w_300,h_200,c_fill/e_sepia/v1612/sample.jpg"),
+            "https://res.cloudinary.com/demo/image/upload/v1612/sample.jpg"
+        );
+        // A version segment isn't a transform
+        assert_eq!(cloudinary_original("/demo/image/upload/v123/sample.jpg"), None);
+        // Nor is the asset, even when its name looks like one
+        assert_eq!(cloudinary_original("/demo/image/upload/w_300"), None);
+    }
+
+    #[test]
+    fn wordpress_sizes() {
+        assert_eq!(
+            original("https://example.com/wp-content/uploads/2024/05/photo-300x200.jpg"),
+            "https://example.com/wp-content/uploads/2024/05/photo.jpg"
+        );
+        assert_eq!(
+            original("https://example.com/wp-content/uploads/2024/05/photo-300x200.jpg?w=300&id=4"),
+            "https://example.com/wp-content/uploads/2024/05/photo.jpg?id=4"
+        );
+    }
+
+    #[test]
+    fn shopify_sizes() {
+        assert_eq!(
+            original("https://cdn.shopify.com/s/files/1/products/shirt_400x.jpg"),
+            "https://cdn.shopify.com/s/files/1/products/shirt.jpg"
+        );
+        assert_eq!(
+            original("https://cdn.shopify.com/s/files/1/products/shirt_grande.jpg?v=16"),
+            "https://cdn.shopify.com/s/files/1/products/shirt.jpg?v=16"
+        );
+        assert_eq!(
+            original("https://shop.example.com/cdn/shop/products/shirt_x600.png"),
+            "https://shop.example.com/cdn/shop/products/shirt.png"
+        );
+    }
+
+    #[test]
+    fn lookalike_names_are_left_alone() {
+        for url in [
+            "https://example.com/wp-content/uploads/2024/05/photo-300.jpg",
+            "https://example.com/wp-content/uploads/2024/05/box-axb.jpg",
+            "https://example.com/wp-content/uploads/2024/05/1920x1080/photo.jpg",
+            "https://example.com/images/photo-300x200.jpg",
+            "https://example.com/images/shirt_grande.jpg",
+            "https://cdn.shopify.com/s/files/1/products/shirt_x.jpg",
+            "https://cdn.shopify.com/s/files/1/products/shirt_grandest.jpg",
+            "https://cdn.shopify.com/s/files/1/products/_large.jpg",
+        ] {
+            assert_eq!(original(url), url);
+        }
+    }
+}
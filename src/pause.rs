@@ -0,0 +1,101 @@
+use std::io::{BufRead, IsTerminal};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+// Why the crawl is currently held. Several can be active at once; requests
+// only proceed once every reason has cleared.
+#[derive(Clone, Copy)]
+pub enum PauseReason {
+    NetworkDown = 1,
+    Manual = 2,
+    OutsideActiveHours = 4,
+}
+
+// A gate every request passes through before it is sent
+pub struct PauseGate {
+    reasons: watch::Sender<u8>,
+}
+
+impl PauseGate {
+    pub fn new() -> PauseGate {
+        PauseGate {
+            reasons: watch::Sender::new(0),
+        }
+    }
+
+    // Returns true if this reason wasn't already holding the crawl
+    pub fn pause(&self, reason: PauseReason) -> bool {
+        self.reasons.send_if_modified(|reasons| {
+            let was_set = *reasons & reason as u8 != 0;
+            *reasons |= reason as u8;
+            !was_set
+        })
+    }
+
+    pub fn resume(&self, reason: PauseReason) {
+        self.reasons.send_if_modified(|reasons| {
+            let was_set = *reasons & reason as u8 != 0;
+            *reasons &= !(reason as u8);
+            was_set
+        });
+    }
+
+    // Flip a reason on or off; returns true if the crawl is now held by it
+    pub fn toggle(&self, reason: PauseReason) -> bool {
+        let mut paused = false;
+        self.reasons.send_modify(|reasons| {
+            *reasons ^= reason as u8;
+            paused = *reasons & reason as u8 != 0;
+        });
+        paused
+    }
+
+    pub fn held_by(&self, reason: PauseReason) -> bool {
+        *self.reasons.borrow() & reason as u8 != 0
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.reasons.borrow() != 0
+    }
+
+    pub async fn wait(&self) {
+        let mut rx = self.reasons.subscribe();
+        let _ = rx.wait_for(|reasons| *reasons == 0).await;
+    }
+}
+
+fn toggle_manual(gate: &PauseGate, source: &str) {
+    if gate.toggle(PauseReason::Manual) {
+        println!("Crawl paused ({}); in-flight requests will finish", source);
+    } else {
+        println!("Crawl resumed ({})", source);
+    }
+}
+
+// Let the user pause and resume a running crawl with SIGUSR2 or, when
+// attached to a terminal and `keyboard` is set, by typing `p` and Enter.
+pub fn listen_for_controls(gate: Arc<PauseGate>, keyboard: bool) {
+    #[cfg(unix)]
+    {
+        let gate = gate.clone();
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            let Ok(mut signals) = signal(SignalKind::user_defined2()) else { return };
+            while signals.recv().await.is_some() {
+                toggle_manual(&gate, "SIGUSR2");
+            }
+        });
+    }
+
+    // A plain thread rather than tokio's stdin, whose blocking read would
+    // keep the runtime from shutting down until Enter is pressed
+    if keyboard && std::io::stdin().is_terminal() {
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+                if line.trim().eq_ignore_ascii_case("p") {
+                    toggle_manual(&gate, "keyboard");
+                }
+            }
+        });
+    }
+}
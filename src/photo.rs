@@ -0,0 +1,21 @@
+use chrono::{DateTime, Datelike};
+use exif::{In, Reader, Tag, Value};
+use std::io::Cursor;
+
+// The year and month a photo was taken according to its EXIF
+// DateTimeOriginal, falling back to the server's Last-Modified date
+pub fn taken_month(bytes: &[u8], last_modified: Option<&str>) -> Option<(u16, u8)> {
+    exif_month(bytes).or_else(|| {
+        let modified = DateTime::parse_from_rfc2822(last_modified?).ok()?;
+        Some((u16::try_from(modified.year()).ok()?, modified.month() as u8))
+    })
+}
+
+fn exif_month(bytes: &[u8]) -> Option<(u16, u8)> {
+    let exif = Reader::new().read_from_container(&mut Cursor::new(bytes)).ok()?;
+    let field = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)?;
+    let Value::Ascii(values) = &field.value else { return None };
+    let taken = exif::DateTime::from_ascii(values.first()?).ok()?;
+    // Cameras with an unset clock write zeros
+    (taken.year > 0 && (1..=12).contains(&taken.month)).then_some((taken.year, taken.month))
+}
@@ -0,0 +1,386 @@
+use crate::config::{Config, CrawlScope, FileSettings, ImageType, RedirectScope};
+use crate::cookies;
+use crate::discovery::format_size;
+use crate::extract;
+use crate::politeness::Politeness;
+use crate::retry::{Failure, Retry};
+use crate::reload::{LiveSettings, Tunables};
+use crate::robots::Robots;
+use crate::scope::Scope;
+use crate::{build_client, seed_scope, urls};
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use reqwest::header;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Read;
+use url::Url;
+
+// Sitemaps read at most, nested indexes included
+const MAX_SITEMAPS: usize = 20;
+// The sitemap protocol's limit on one file, uncompressed
+const MAX_SITEMAP_SIZE: u64 = 50 << 20;
+// Sections listed, the largest first
+const MAX_SECTIONS: usize = 15;
+
+// `plan` subcommand: fetch only the seed, its robots.txt and its sitemaps,
+// and print what a crawl with the same options would do, so a config can be
+// checked before a single page is crawled
+pub async fn plan(config: &Config) -> Result<()> {
+    let file = match &config.config_file {
+        Some(path) => FileSettings::load(path)?,
+        None => FileSettings::default(),
+    };
+    let settings = LiveSettings::new(config, &file).get();
+    let client = build_client(config, None, cookies::jar(config)?)?;
+
+    let mut seed = Url::parse(&config.start_urls[0]).context("Failed to parse URL")?;
+    urls::strip_session_params(&mut seed, &settings.strip_params);
+    let mut scope = seed_scope(&client, config, &mut seed).await;
+    let robots = Robots::new(client.clone(), config.robots_agent());
+
+    println!("Plan for {}", seed);
+    if config.honor_robots && !robots.allows(&seed).await {
+        println!("Seed: disallowed by robots.txt; the crawl would stop there");
+    } else {
+        match client.get(seed.as_str()).send().await {
+            Ok(response) => {
+                let requested = seed.clone();
+                scope.adopt_seed_redirect(response.url());
+                scope.canonicalize(&mut seed);
+                describe_seed(config, &settings.strip_params, &scope, &robots, &requested, response).await;
+            }
+            Err(e) => println!("Seed: request failed: {}", e),
+        }
+    }
+
+    describe_config(config, &settings, &scope, &robots, &seed).await;
+    describe_sitemaps(&client, &scope, &robots, &seed, config.honor_robots).await;
+    Ok(())
+}
+
+async fn describe_seed(
+    config: &Config,
+    strip_params: &[String],
+    scope: &Scope,
+    robots: &Robots,
+    requested: &Url,
+    response: reqwest::Response,
+) {
+    let status = response.status();
+    let url = response.url().clone();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("no Content-Type")
+        .to_string();
+    let body = match response.bytes().await {
+        Ok(body) => body,
+        Err(e) => {
+            println!("Seed: {}, but its body couldn't be read: {}", status, e);
+            return;
+        }
+    };
+    println!("Seed: {}, {}, {}", status, content_type, format_size(body.len() as u64));
+    if url != *requested {
+        println!("  Ends up at {}", url);
+    }
+    if !status.is_success() || !content_type.contains("html") {
+        return;
+    }
+
+    let html = String::from_utf8_lossy(&body[..body.len().min(config.max_page_size as usize)]);
+    let image_attrs = extract::image_attrs_for(&url, &config.image_attrs, &config.site_image_attrs);
+    let parsed = extract::parse_page(&url, &html, strip_params, config.srcset, image_attrs, &config.robots_agent());
+    if let Some(title) = &parsed.title {
+        println!("  Title: {}", title);
+    }
+    let mut in_scope = 0;
+    let mut disallowed = 0;
+    for mut link in parsed.links.iter().cloned() {
+        if !scope.canonicalize(&mut link) {
+            continue;
+        }
+        in_scope += 1;
+        if config.honor_robots && !robots.allows(&link).await {
+            disallowed += 1;
+        }
+    }
+    println!(
+        "  {} images, {} links ({} within scope{})",
+        parsed.images.len(),
+        parsed.links.len(),
+        in_scope,
+        if disallowed > 0 { format!(", {} disallowed by robots.txt", disallowed) } else { String::new() }
+    );
+    if parsed.noimageindex && config.honor_robots && !config.ignore_noimageindex {
+        println!("  Marked noimageindex: its links are followed but its images left alone");
+    }
+}
+
+async fn describe_config(config: &Config, settings: &Tunables, scope: &Scope, robots: &Robots, seed: &Url) {
+    let depth = match config.max_depth {
+        Some(depth) => format!("down to depth {}", depth),
+        None => "at any depth".to_string(),
+    };
+    let twins = match (config.scope, config.unify_hosts) {
+        (CrawlScope::Domain, _) => " and its subdomains",
+        (CrawlScope::Host, true) => " (any scheme or port) and its www twin",
+        (CrawlScope::Host, false) => " (any scheme or port)",
+        (CrawlScope::Origin, true) => " and its www twin",
+        (CrawlScope::Origin, false) => "",
+    };
+    let redirects = match config.redirect_scope {
+        RedirectScope::Same => "within the site",
+        RedirectScope::Any => "anywhere",
+    };
+    println!(
+        "Scope: pages on {}{}, {}; redirects followed {}, at most {}",
+        scope.host(),
+        twins,
+        depth,
+        redirects,
+        config.max_redirects
+    );
+
+    // What the first page request would wait: the configured delay, or
+    // longer if robots.txt or an earlier run asks for it
+    let mut delay = Politeness::load(&config.output_dir, config.adaptive_delay).delay(scope.host(), settings.delay);
+    println!("User-Agent: {}", config.user_agent());
+    if let Some(proxy) = &config.proxy {
+        let mut proxy = proxy.clone();
+        let _ = proxy.set_password(None);
+        println!("Proxy: {}", proxy);
+    }
+    if let Some(login) = &config.login {
+        println!("Login: as {} at {}, before crawling (the seed above was fetched without it)", login.username, login.url);
+    }
+    if config.honor_robots {
+        let crawl_delay = robots.crawl_delay(seed).await;
+        println!(
+            "Robots: honored{}",
+            crawl_delay.map(|delay| format!(", Crawl-delay {:?}", delay)).unwrap_or_default()
+        );
+        delay = delay.max(crawl_delay.unwrap_or_default());
+    } else {
+        println!("Robots: ignored");
+    }
+    println!(
+        "Pacing: {} requests at once, {}ms between pages per host{}; {} retries from {}ms{}; {}, {}s to connect",
+        settings.concurrency,
+        delay.as_millis(),
+        if config.adaptive_delay { " (more for hosts that slow down)" } else { "" },
+        config.retries,
+        config.retry_backoff.as_millis(),
+        describe_retry_rules(config),
+        config
+            .timeout
+            .map(|timeout| format!("{}s per request", timeout.as_secs()))
+            .unwrap_or_else(|| "no time limit on requests".to_string()),
+        config.connect_timeout.as_secs()
+    );
+
+    let mut budgets = Vec::new();
+    if let Some(max_bytes) = config.max_bytes {
+        budgets.push(format!("{} of images", format_size(max_bytes)));
+    }
+    if let Some(rate) = config.max_bandwidth {
+        budgets.push(format!("{}/s", format_size(rate)));
+    }
+    if let Some(rate) = config.max_error_rate {
+        budgets.push(format!("stop past {}% failed requests", rate * 100.0));
+    }
+    budgets.push(format!("pages up to {}", format_size(config.max_page_size)));
+    println!("Budgets: {}", budgets.join(", "));
+
+    let formats = if config.formats.len() == ImageType::ALL.len() {
+        "all formats".to_string()
+    } else {
+        config.formats.iter().map(|format| format.extension()).collect::<Vec<_>>().join(", ")
+    };
+    let mut filters = vec![formats];
+    match (config.min_width, config.min_height) {
+        (Some(width), Some(height)) => filters.push(format!("at least {}x{}", width, height)),
+        (Some(width), None) => filters.push(format!("at least {}px wide", width)),
+        (None, Some(height)) => filters.push(format!("at least {}px tall", height)),
+        (None, None) => {}
+    }
+    if config.external_images {
+        filters.push("from any host".to_string());
+    }
+    filters.push(format!("data: URIs from {}", format_size(config.min_data_uri_size as u64)));
+    filters.push(format!("read from {}", config.image_attrs.join(", ")));
+    for (site, attrs) in &config.site_image_attrs {
+        filters.push(format!("read from {} on {}", attrs.join(", "), site));
+    }
+    if config.original_images {
+        filters.push("resizing parameters dropped".to_string());
+    }
+    println!("Images: {}", filters.join(", "));
+
+    let mut output = vec![
+        format!("{}", config.output_dir.display()),
+        format!("{:?} layout", config.layout).to_lowercase(),
+        format!("{:?} dedupe", config.dedupe).to_lowercase(),
+    ];
+    if config.metadata_only {
+        output.push("metadata only".to_string());
+    }
+    if config.quarantine {
+        output.push("quarantine".to_string());
+    }
+    if config.warc {
+        output.push("WARC".to_string());
+    }
+    if config.wacz {
+        output.push("WACZ".to_string());
+    }
+    println!("Output: {}", output.join(", "));
+}
+
+// The kinds of failure `--retry` budgets differently, e.g.
+// " (timeout: 1 from 500ms, tls: none)"
+fn describe_retry_rules(config: &Config) -> String {
+    if config.retry_rules.is_empty() {
+        return String::new();
+    }
+    let retries = config.retry_policy();
+    let mut failures: Vec<Failure> = config.retry_rules.iter().map(|rule| rule.failure).collect();
+    failures.sort_by_key(|failure| *failure as u8);
+    failures.dedup();
+    let rules: Vec<String> = failures
+        .into_iter()
+        .map(|failure| match retries.get(failure) {
+            Retry { attempts: 0, .. } => format!("{}: none", failure.name()),
+            retry => format!("{}: {} from {}ms", failure.name(), retry.attempts, retry.backoff.as_millis()),
+        })
+        .collect();
+    format!(" ({})", rules.join(", "))
+}
+
+// Pages in one top-level directory of the site, as listed by its sitemaps
+#[derive(Default)]
+struct Section {
+    pages: usize,
+    disallowed: usize,
+}
+
+// Read the sitemaps robots.txt names, or `/sitemap.xml`, and count the
+// pages they list by their first path segment. The crawl itself follows
+// links, so this is a measure of the site rather than of the crawl.
+async fn describe_sitemaps(client: &reqwest::Client, scope: &Scope, robots: &Robots, seed: &Url, honor_robots: bool) {
+    let mut queue: VecDeque<Url> = robots.sitemaps(seed).await.into();
+    let guessed = queue.is_empty();
+    if guessed {
+        queue.extend(seed.join("/sitemap.xml"));
+    }
+
+    let mut read = 0;
+    let mut sections: BTreeMap<String, Section> = BTreeMap::new();
+    let mut other_sites = 0;
+    while let Some(sitemap) = queue.pop_front() {
+        if read == MAX_SITEMAPS {
+            println!("Sitemaps: stopped after {}, {} more not read", MAX_SITEMAPS, queue.len() + 1);
+            break;
+        }
+        let xml = match fetch_sitemap(client, &sitemap).await {
+            Ok(xml) => xml,
+            Err(_) if guessed => {
+                println!("Sitemaps: none named in robots.txt or at /sitemap.xml");
+                continue;
+            }
+            Err(e) => {
+                println!("Sitemap {}: {:#}", sitemap, e);
+                continue;
+            }
+        };
+        read += 1;
+        let (index, locations) = locations(&xml);
+        for location in locations {
+            let Ok(mut url) = Url::parse(&location) else { continue };
+            if index {
+                queue.push_back(url);
+                continue;
+            }
+            if !scope.canonicalize(&mut url) {
+                other_sites += 1;
+                continue;
+            }
+            let section = sections.entry(section_of(&url)).or_default();
+            section.pages += 1;
+            if honor_robots && !robots.allows(&url).await {
+                section.disallowed += 1;
+            }
+        }
+    }
+    if read == 0 {
+        return;
+    }
+
+    let pages: usize = sections.values().map(|section| section.pages).sum();
+    println!("Sitemaps: {} pages on the site in {} sitemaps", pages, read);
+    let mut sections: Vec<(String, Section)> = sections.into_iter().collect();
+    sections.sort_by(|(a, x), (b, y)| y.pages.cmp(&x.pages).then_with(|| a.cmp(b)));
+    for (name, section) in sections.iter().take(MAX_SECTIONS) {
+        let disallowed = if section.disallowed > 0 {
+            format!("  ({} disallowed by robots.txt)", section.disallowed)
+        } else {
+            String::new()
+        };
+        println!("  {:<24} {:>7}{}", name, section.pages, disallowed);
+    }
+    if sections.len() > MAX_SECTIONS {
+        let rest: usize = sections[MAX_SECTIONS..].iter().map(|(_, section)| section.pages).sum();
+        println!("  {:<24} {:>7}", format!("{} more sections", sections.len() - MAX_SECTIONS), rest);
+    }
+    if other_sites > 0 {
+        println!("  {:<24} {:>7}  (out of scope)", "other sites", other_sites);
+    }
+}
+
+// A sitemap's text, unzipped if it came gzipped
+async fn fetch_sitemap(client: &reqwest::Client, url: &Url) -> Result<String> {
+    let response = client.get(url.as_str()).send().await?.error_for_status()?;
+    let body = response.bytes().await?;
+    let mut text = String::new();
+    if body.starts_with(&[0x1f, 0x8b]) {
+        GzDecoder::new(&body[..]).take(MAX_SITEMAP_SIZE).read_to_string(&mut text).context("not valid gzip")?;
+    } else {
+        text = String::from_utf8_lossy(&body[..body.len().min(MAX_SITEMAP_SIZE as usize)]).into_owned();
+    }
+    Ok(text)
+}
+
+// The `<loc>`s of a sitemap, and whether it's an index of other sitemaps
+// rather than a list of pages
+fn locations(xml: &str) -> (bool, Vec<String>) {
+    let index = xml.contains("<sitemapindex");
+    let mut locations = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("<loc>") {
+        rest = &rest[start + "<loc>".len()..];
+        let Some(end) = rest.find("</loc>") else { break };
+        let text = rest[..end].trim();
+        let text = text.strip_prefix("<![CDATA[").and_then(|text| text.strip_suffix("]]>")).unwrap_or(text);
+        locations.push(unescape(text.trim()));
+        rest = &rest[end..];
+    }
+    (index, locations)
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// `/blog/` for anything under /blog/, `/` for pages at the top
+fn section_of(url: &Url) -> String {
+    let path = url.path().trim_start_matches('/');
+    match path.split_once('/') {
+        Some((first, _)) => format!("/{}/", first),
+        None => "/".to_string(),
+    }
+}
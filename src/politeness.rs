@@ -0,0 +1,156 @@
+use crate::dates::unix_now;
+use anyhow::Result;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::fs;
+use tokio::time::{sleep_until, Instant};
+
+// A host's delay at least doubles on each sign of overload, up to this
+const MAX_DELAY: Duration = Duration::from_secs(60);
+// The shortest delay backing off leaves a host at, even with `--delay 0`
+const MIN_BACKOFF: Duration = Duration::from_millis(250);
+// Successful requests in a row after which a learned delay is eased by a quarter
+const EASE_AFTER: u32 = 20;
+// With `--adaptive-delay`: a response this many times slower than the
+// host's usual, or a share of recent requests failing above ERROR_RATE,
+// adds half again to its delay
+const SLOW_FACTOR: u64 = 3;
+const ERROR_RATE: f64 = 0.2;
+// Requests a host's latency is learned over before it's judged slow
+const WARM_UP: u64 = 5;
+
+// What we know about how a host copes with being crawled
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct HostProfile {
+    // Smoothed time until response headers arrive
+    pub latency_ms: u64,
+    pub requests: u64,
+    // Requests answered with 429 or 503, or not answered at all
+    pub errors: u64,
+    pub last_error_at: Option<u64>,
+    // The delay the host was last crawled at without complaint
+    pub delay_ms: u64,
+    pub updated_at: u64,
+    #[serde(skip)]
+    successes_in_a_row: u32,
+    // Smoothed share of this run's requests that failed with a server
+    // error or no answer
+    #[serde(skip)]
+    error_rate: f64,
+}
+
+// Per-host profiles kept in the output directory, so a recurring crawl
+// starts each host at the pace it tolerated last time instead of
+// rediscovering it from the configured delay. Page requests to a host take
+// turns, each one waiting the delay after the one before.
+pub struct Politeness {
+    path: PathBuf,
+    profiles: Mutex<BTreeMap<String, HostProfile>>,
+    // Also back off when a host slows down or starts failing
+    adaptive: bool,
+    // When each host's next page request may go out
+    turns: Mutex<HashMap<String, Instant>>,
+}
+
+pub fn profiles_path(output_dir: &Path) -> PathBuf {
+    output_dir.join(".state").join("hosts.json")
+}
+
+impl Politeness {
+    pub fn load(output_dir: &Path, adaptive: bool) -> Politeness {
+        let path = profiles_path(output_dir);
+        let profiles = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                eprintln!("Ignoring unreadable host profiles in {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Politeness { path, profiles: Mutex::new(profiles), adaptive, turns: Mutex::new(HashMap::new()) }
+    }
+
+    // What this host's profile says about it, if it has one
+    pub fn profile(&self, host: &str) -> Option<HostProfile> {
+        self.profiles.lock().unwrap().get(host).cloned()
+    }
+
+    // The pause before the next page request to `host`: the configured
+    // delay, or longer if the host has needed more room
+    pub fn delay(&self, host: &str, configured: Duration) -> Duration {
+        let learned = self.profiles.lock().unwrap().get(host).map_or(0, |profile| profile.delay_ms);
+        configured.max(Duration::from_millis(learned))
+    }
+
+    // Wait for this page request's turn at `host`, `delay` after the last
+    // one's, however many crawl tasks are after the same host
+    pub async fn wait_turn(&self, host: &str, delay: Duration) {
+        let turn = {
+            let mut turns = self.turns.lock().unwrap();
+            let now = Instant::now();
+            let turn = turns.get(host).map_or(now, |next| (*next).max(now));
+            turns.insert(host.to_string(), turn + delay);
+            turn
+        };
+        sleep_until(turn).await;
+    }
+
+    // Fold one request into the host's profile. `status` is None when the
+    // request got no response at all; backing off starts from whichever of
+    // the learned and `configured` delays is in effect.
+    pub fn record(&self, host: &str, latency: Duration, status: Option<StatusCode>, configured: Duration) {
+        let mut profiles = self.profiles.lock().unwrap();
+        let profile = profiles.entry(host.to_string()).or_default();
+        let latency_ms = latency.as_millis() as u64;
+        let slow = profile.requests >= WARM_UP && latency_ms > profile.latency_ms.max(1) * SLOW_FACTOR;
+        profile.latency_ms = if profile.requests == 0 {
+            latency_ms
+        } else {
+            (profile.latency_ms * 7 + latency_ms) / 8
+        };
+        profile.requests += 1;
+        profile.updated_at = unix_now();
+        let failed = status.is_none_or(|status| status.is_server_error());
+        profile.error_rate = (profile.error_rate * 7.0 + f64::from(u8::from(failed))) / 8.0;
+
+        let overloaded = status.is_none_or(|status| {
+            status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+        });
+        let struggling = self.adaptive && (slow || failed && profile.error_rate > ERROR_RATE);
+        if struggling && !overloaded {
+            profile.successes_in_a_row = 0;
+            let delay = Duration::from_millis(profile.delay_ms).max(configured).max(MIN_BACKOFF) * 3 / 2;
+            profile.delay_ms = delay.min(MAX_DELAY).as_millis() as u64;
+            let why = if slow { format!("a {}ms response", latency_ms) } else { "failing requests".to_string() };
+            println!("Slowing down for {} after {}: {}ms between pages", host, why, profile.delay_ms);
+        } else if overloaded {
+            profile.errors += 1;
+            profile.last_error_at = Some(profile.updated_at);
+            profile.successes_in_a_row = 0;
+            let delay = Duration::from_millis(profile.delay_ms).max(configured).max(MIN_BACKOFF / 2) * 2;
+            profile.delay_ms = delay.min(MAX_DELAY).as_millis() as u64;
+            println!("Slowing down for {}: {}ms between pages", host, profile.delay_ms);
+        } else {
+            profile.successes_in_a_row += 1;
+            if profile.successes_in_a_row >= EASE_AFTER {
+                profile.successes_in_a_row = 0;
+                profile.delay_ms = profile.delay_ms * 3 / 4;
+            }
+        }
+    }
+
+    // Write the profiles under a temporary name and rename them into place
+    pub async fn save(&self) -> Result<()> {
+        let bytes = serde_json::to_vec_pretty(&*self.profiles.lock().unwrap())?;
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, bytes).await?;
+        fs::rename(&tmp, &self.path).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+// Run CPU-bound work (hashing, decoding images to fingerprint them) on
+// rayon's pool, a thread per core, rather than on the crawl's threads or
+// tokio's blocking pool, which is sized for waiting rather than computing.
+// A panic is passed on to the caller.
+pub async fn run<T, F>(work: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (done, result) = tokio::sync::oneshot::channel();
+    rayon::spawn(move || {
+        let _ = done.send(std::panic::catch_unwind(std::panic::AssertUnwindSafe(work)));
+    });
+    match result.await.expect("rayon dropped a task") {
+        Ok(value) => value,
+        Err(panic) => std::panic::resume_unwind(panic),
+    }
+}
+
+// The hex SHA-256 of `data`, handed back along with it
+pub async fn sha256<T: AsRef<[u8]> + Send + 'static>(data: T) -> (T, String) {
+    run(move || {
+        let sha256 = format!("{:x}", Sha256::digest(data.as_ref()));
+        (data, sha256)
+    })
+    .await
+}
+
+// The hex SHA-256 of the file at `path`, read through in blocks
+pub async fn sha256_file(path: PathBuf) -> std::io::Result<String> {
+    run(move || {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut std::fs::File::open(&path)?, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await
+}
@@ -0,0 +1,86 @@
+use crate::config::ImageType;
+use anyhow::{bail, Result};
+use std::str::FromStr;
+use std::time::Duration;
+
+// Named starting points for `--profile`. Each only fills in settings the
+// command line, environment and config file leave unset, so any of them can
+// still switch a preset's flag back off.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Preset {
+    // Slow and quick to back off, for sites we don't want to bother
+    Polite,
+    // No delay and a forgiving circuit breaker, for our own servers
+    Aggressive,
+    // Everything a page can reference, recorded as a replayable archive
+    Archive,
+    // Size up a site without downloading its images
+    Audit,
+}
+
+impl FromStr for Preset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "polite" => Ok(Preset::Polite),
+            "aggressive" => Ok(Preset::Aggressive),
+            "archive" => Ok(Preset::Archive),
+            "audit" => Ok(Preset::Audit),
+            other => bail!("Unknown profile '{}' (expected polite, aggressive, archive or audit)", other),
+        }
+    }
+}
+
+// What a preset sets. `None` leaves the built-in default alone.
+#[derive(Default)]
+pub struct PresetSettings {
+    pub delay: Option<Duration>,
+    pub concurrency: Option<usize>,
+    pub formats: Option<Vec<ImageType>>,
+    pub breaker_threshold: Option<u32>,
+    pub breaker_cooldown: Option<Duration>,
+    pub max_error_rate: Option<f64>,
+    pub checkpoint_interval: Option<Duration>,
+    pub follow_amp: Option<bool>,
+    pub follow_iframes: Option<bool>,
+    pub wacz: Option<bool>,
+    pub estimate: Option<bool>,
+}
+
+impl Preset {
+    pub fn settings(self) -> PresetSettings {
+        match self {
+            Preset::Polite => PresetSettings {
+                delay: Some(Duration::from_secs(2)),
+                concurrency: Some(2),
+                breaker_threshold: Some(3),
+                breaker_cooldown: Some(Duration::from_secs(300)),
+                max_error_rate: Some(0.2),
+                ..PresetSettings::default()
+            },
+            Preset::Aggressive => PresetSettings {
+                delay: Some(Duration::ZERO),
+                concurrency: Some(32),
+                breaker_threshold: Some(20),
+                breaker_cooldown: Some(Duration::from_secs(10)),
+                ..PresetSettings::default()
+            },
+            Preset::Archive => PresetSettings {
+                delay: Some(Duration::from_secs(1)),
+                checkpoint_interval: Some(Duration::from_secs(10 * 60)),
+                follow_amp: Some(true),
+                follow_iframes: Some(true),
+                wacz: Some(true),
+                ..PresetSettings::default()
+            },
+            Preset::Audit => PresetSettings {
+                delay: Some(Duration::from_millis(250)),
+                follow_amp: Some(true),
+                follow_iframes: Some(true),
+                estimate: Some(true),
+                ..PresetSettings::default()
+            },
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::error::CrawlError;
+use crate::{send_timed, CrawlState};
+use image::ImageFormat;
+use reqwest::{header, StatusCode};
+use std::io::Cursor;
+use std::time::Duration;
+use url::Url;
+
+// How much of an image is asked for to learn its format and dimensions.
+// Formats keep their dimensions in the header, though a JPEG with a large
+// EXIF block can push them past this.
+pub const PROBE_BYTES: usize = 64 * 1024;
+
+// The start of an image, from a ranged GET
+pub struct Probe {
+    pub head: Vec<u8>,
+    // The full size, when the server said
+    pub size: Option<u64>,
+    // A 206 holding only `head`; otherwise the server ignored the range and
+    // the rest of the image can still be read from `response`
+    pub partial: bool,
+    pub response: reqwest::Response,
+    pub ttfb: Duration,
+}
+
+impl Probe {
+    // The whole image is already in `head`
+    pub fn complete(&self) -> bool {
+        self.size.is_some_and(|size| self.head.len() as u64 >= size)
+    }
+}
+
+pub async fn probe(state: &CrawlState, url: &Url) -> Result<Probe, CrawlError> {
+    let range = format!("bytes=0-{}", PROBE_BYTES - 1);
+    let (mut response, ttfb) =
+        send_timed(state, || state.client.get(url.as_str()).header(header::RANGE, range.as_str())).await?;
+    let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+    let size = full_length(&response);
+    let mut head = Vec::new();
+    while head.len() < PROBE_BYTES {
+        let Some(chunk) = response.chunk().await? else { break };
+        if let Some(bandwidth) = &state.bandwidth {
+            bandwidth.consume(chunk.len()).await;
+        }
+        head.extend_from_slice(&chunk);
+    }
+    Ok(Probe { head, size, partial, response, ttfb })
+}
+
+// The size of the whole image a response is for: the total from a 206's
+// Content-Range, or the Content-Length of anything else. Read from the
+// headers, as `content_length()` shrinks while the body is read.
+pub fn full_length(response: &reqwest::Response) -> Option<u64> {
+    let value = |name| response.headers().get(name).and_then(|value| value.to_str().ok());
+    match response.status() {
+        StatusCode::PARTIAL_CONTENT => value(header::CONTENT_RANGE)?.rsplit('/').next()?.parse().ok(),
+        _ => value(header::CONTENT_LENGTH)?.parse().ok(),
+    }
+}
+
+// Width and height, if the header is all there in `head`
+pub fn dimensions(head: &[u8], format: ImageFormat) -> Option<(u32, u32)> {
+    image::io::Reader::with_format(Cursor::new(head), format).into_dimensions().ok()
+}
@@ -0,0 +1,117 @@
+use crate::dates::unix_now;
+use crate::download::Download;
+use crate::verbosity::progress;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use url::Url;
+
+pub const QUARANTINE_DIR: &str = "quarantine";
+const REASONS_FILE: &str = "reasons.jsonl";
+
+// Why a download was set aside instead of saved
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Reason {
+    // Not an image format we can recognize
+    Unrecognized,
+    // An image, but not in a format the crawl keeps
+    Format,
+    // Still short of its Content-Length after every attempt
+    Truncated,
+    // Narrower or shorter than `--min-width` or `--min-height`
+    Small,
+}
+
+impl Reason {
+    fn dir(self) -> &'static str {
+        match self {
+            Reason::Unrecognized => "unrecognized",
+            Reason::Format => "format",
+            Reason::Truncated => "truncated",
+            Reason::Small => "small",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Held<'a> {
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<&'a str>,
+    // Relative to the output directory
+    path: &'a Path,
+    reason: Reason,
+    detail: &'a str,
+    size: u64,
+    held_at: u64,
+}
+
+// Downloads the crawl would otherwise drop, kept under `quarantine/` in the
+// output directory with a line in `reasons.jsonl` saying why, so a filter
+// that was too strict can be reviewed and its decisions undone
+pub struct Quarantine {
+    output_dir: PathBuf,
+    reasons: Mutex<File>,
+}
+
+impl Quarantine {
+    pub async fn open(output_dir: &Path) -> Result<Quarantine> {
+        let dir = output_dir.join(QUARANTINE_DIR);
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join(REASONS_FILE);
+        let reasons = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Quarantine { output_dir: output_dir.to_path_buf(), reasons: Mutex::new(reasons) })
+    }
+
+    // Move a download to `quarantine/<reason>/<domain>/` and record why.
+    // The name starts with a hash of the content so same-named files from
+    // different URLs don't overwrite each other.
+    pub async fn hold(
+        &self,
+        url: &Url,
+        page: Option<&Url>,
+        download: Download,
+        reason: Reason,
+        detail: &str,
+    ) -> std::io::Result<PathBuf> {
+        let name = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|name| !name.is_empty())
+            .unwrap_or("download");
+        let relative = Path::new(QUARANTINE_DIR)
+            .join(reason.dir())
+            .join(url.domain().unwrap_or("unknown"))
+            .join(format!("{}-{}", &download.sha256[..12], name));
+        let path = self.output_dir.join(&relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let size = download.size;
+        download.persist(&path).await?;
+
+        let held = Held {
+            url: url.as_str(),
+            page: page.map(Url::as_str),
+            path: &relative,
+            reason,
+            detail,
+            size,
+            held_at: unix_now(),
+        };
+        let mut line = serde_json::to_vec(&held)?;
+        line.push(b'\n');
+        self.reasons.lock().await.write_all(&line).await?;
+        progress!("Quarantined: {} ({})", url, detail);
+        Ok(relative)
+    }
+}
@@ -0,0 +1,142 @@
+use crate::config::{Config, FileSettings, MixedContentPolicy};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// The settings a running crawl may pick up changes to
+#[derive(Clone)]
+pub struct Tunables {
+    pub delay: Duration,
+    pub strip_params: Vec<String>,
+    pub mixed_content: MixedContentPolicy,
+    pub concurrency: usize,
+}
+
+impl Tunables {
+    // Command line values first, then the file, then defaults (which a
+    // `--profile` may have set)
+    fn from_config(config: &Config, file: &FileSettings) -> Tunables {
+        let mut strip_params = config.strip_params.clone();
+        for param in &file.strip_params {
+            let param = param.to_ascii_lowercase();
+            if !strip_params.contains(&param) {
+                strip_params.push(param);
+            }
+        }
+
+        Tunables {
+            delay: config
+                .delay
+                .or(file.delay_ms.map(Duration::from_millis))
+                .unwrap_or(config.default_delay),
+            strip_params,
+            mixed_content: config
+                .mixed_content
+                .or(file.mixed_content)
+                .unwrap_or(MixedContentPolicy::Fetch),
+            concurrency: config.concurrency.or(file.concurrency).unwrap_or(config.default_concurrency),
+        }
+    }
+}
+
+pub struct LiveSettings {
+    current: RwLock<Arc<Tunables>>,
+    // What the settings would be without a config file
+    base: Tunables,
+    // The current concurrency again, for the crawl loop to wait on changes to
+    concurrency: watch::Sender<usize>,
+}
+
+impl LiveSettings {
+    pub fn new(config: &Config, file: &FileSettings) -> LiveSettings {
+        let current = Tunables::from_config(config, file);
+        LiveSettings {
+            concurrency: watch::Sender::new(current.concurrency),
+            current: RwLock::new(Arc::new(current)),
+            base: Tunables::from_config(config, &FileSettings::default()),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Tunables> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn concurrency(&self) -> watch::Receiver<usize> {
+        self.concurrency.subscribe()
+    }
+
+    // Apply only what changed in the file, so settings given on the command
+    // line hold until the file is deliberately edited to change them
+    fn apply(&self, old: &FileSettings, new: &FileSettings) {
+        let mut next = (*self.get()).clone();
+
+        if old.delay_ms != new.delay_ms {
+            next.delay = new.delay_ms.map(Duration::from_millis).unwrap_or(self.base.delay);
+            println!("Config reload: delay is now {}ms", next.delay.as_millis());
+        }
+        if old.strip_params != new.strip_params {
+            next.strip_params = self.base.strip_params.clone();
+            for param in &new.strip_params {
+                let param = param.to_ascii_lowercase();
+                if !next.strip_params.contains(&param) {
+                    next.strip_params.push(param);
+                }
+            }
+            println!("Config reload: stripping {}", next.strip_params.join(", "));
+        }
+        if old.mixed_content != new.mixed_content {
+            next.mixed_content = new.mixed_content.unwrap_or(self.base.mixed_content);
+            println!("Config reload: mixed content policy is now {:?}", next.mixed_content);
+        }
+        if old.concurrency != new.concurrency {
+            match new.concurrency {
+                Some(0) => eprintln!("Config reload: concurrency must be at least 1, keeping {}", next.concurrency),
+                concurrency => {
+                    next.concurrency = concurrency.unwrap_or(self.base.concurrency);
+                    println!("Config reload: crawling {} pages at once", next.concurrency);
+                }
+            }
+        }
+
+        self.concurrency.send_replace(next.concurrency);
+        *self.current.write().unwrap() = Arc::new(next);
+    }
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Poll the config file, read with `load`, and apply edits to the running
+// crawl. A file that fails to parse is reported and ignored until it is fixed.
+pub fn watch(
+    settings: Arc<LiveSettings>,
+    path: PathBuf,
+    mut loaded: FileSettings,
+    load: fn(&Path) -> Result<FileSettings>,
+) {
+    tokio::spawn(async move {
+        let mut last_modified = modified(&path);
+        loop {
+            sleep(POLL_INTERVAL).await;
+            let current = modified(&path);
+            if current == last_modified {
+                continue;
+            }
+            last_modified = current;
+
+            match load(&path) {
+                Ok(file) => {
+                    settings.apply(&loaded, &file);
+                    loaded = file;
+                }
+                Err(e) => eprintln!("Config reload skipped: {:#}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,161 @@
+use anyhow::{bail, Result};
+use reqwest::{header, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::time::Duration;
+
+// However many retries there are, none waits longer than this
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// A Retry-After further off than this gives up on the request instead
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(15 * 60);
+
+// How a request that failed in a way that may pass is tried again: up to
+// `attempts` more times, waiting about twice as long before each one
+#[derive(Clone, Copy)]
+pub struct Retry {
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
+impl Retry {
+    // The wait before retry number `retry` (from 1): half of the doubled
+    // backoff, plus a random part of the other half so requests that failed
+    // together don't all come back at once
+    pub fn delay(&self, retry: u32) -> Duration {
+        let full = self.backoff.saturating_mul(1 << (retry - 1).min(16)).min(MAX_BACKOFF);
+        let jitter = RandomState::new().build_hasher().finish() % (full.as_millis() as u64 / 2 + 1);
+        full / 2 + Duration::from_millis(jitter)
+    }
+}
+
+// The kinds of failure that are retried, each with a budget of its own
+// (`--retry CLASS=N[/MS]`), since a certificate that's wrong stays wrong
+// while a flaky CDN comes good a few retries later
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Failure {
+    // No response in time
+    Timeout,
+    // A 5xx or 429
+    Server,
+    // Refused or reset, or cut off before the response
+    Connection,
+    // The TLS handshake failed: a bad certificate, or no protocol in common
+    Tls,
+}
+
+impl Failure {
+    pub fn name(self) -> &'static str {
+        match self {
+            Failure::Timeout => "timeout",
+            Failure::Server => "server",
+            Failure::Connection => "connection",
+            Failure::Tls => "tls",
+        }
+    }
+}
+
+impl FromStr for Failure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "timeout" => Ok(Failure::Timeout),
+            "server" => Ok(Failure::Server),
+            "connection" => Ok(Failure::Connection),
+            "tls" => Ok(Failure::Tls),
+            other => bail!("Unknown failure '{}' (expected timeout, server, connection or tls)", other),
+        }
+    }
+}
+
+// How each kind of failure is retried
+#[derive(Clone, Copy)]
+pub struct Retries {
+    timeout: Retry,
+    server: Retry,
+    connection: Retry,
+    tls: Retry,
+}
+
+impl Retries {
+    // Every kind retried the same way
+    pub fn new(retry: Retry) -> Retries {
+        Retries { timeout: retry, server: retry, connection: retry, tls: retry }
+    }
+
+    pub fn set(&mut self, failure: Failure, retry: Retry) {
+        match failure {
+            Failure::Timeout => self.timeout = retry,
+            Failure::Server => self.server = retry,
+            Failure::Connection => self.connection = retry,
+            Failure::Tls => self.tls = retry,
+        }
+    }
+
+    pub fn get(&self, failure: Failure) -> Retry {
+        match failure {
+            Failure::Timeout => self.timeout,
+            Failure::Server => self.server,
+            Failure::Connection => self.connection,
+            Failure::Tls => self.tls,
+        }
+    }
+}
+
+// Responses that say the server had trouble, or wants us to slow down,
+// rather than that the URL is wrong
+pub fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+    )
+}
+
+// How long a 429 or 503 asks us to wait, from its Retry-After: a number of
+// seconds, or an HTTP date
+pub fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    if !matches!(response.status(), StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE) {
+        return None;
+    }
+    let value = response.headers().get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default())
+}
+
+// Requests that got no response: refused or reset connections, timeouts,
+// failed handshakes. None for the ones a retry can't help, like a URL
+// reqwest won't send.
+pub fn failure(error: &reqwest::Error) -> Option<Failure> {
+    if error.is_timeout() {
+        Some(Failure::Timeout)
+    } else if error.is_connect() && is_tls(error) {
+        Some(Failure::Tls)
+    } else if error.is_connect() || error.is_request() {
+        Some(Failure::Connection)
+    } else {
+        None
+    }
+}
+
+// reqwest doesn't say which step of connecting failed, and the TLS stacks'
+// own errors aren't types it exposes, so this goes by the messages below it
+fn is_tls(error: &reqwest::Error) -> bool {
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let message = cause.to_string().to_ascii_lowercase();
+        if ["certificate", "tls", "ssl", "handshake"].iter().any(|word| message.contains(word)) {
+            return true;
+        }
+        source = cause.source();
+    }
+    false
+}
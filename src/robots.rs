@@ -0,0 +1,325 @@
+use crate::verbosity::progress;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use url::Url;
+
+// Our name: the product token of the default User-Agent, which is looked
+// for in `User-agent:` lines before falling back to `*`
+pub const ROBOTS_AGENT: &str = "image_crawler";
+
+// Only this much of a robots.txt is read, as RFC 9309 allows
+const MAX_ROBOTS_SIZE: usize = 500 * 1024;
+// How long a robots.txt is trusted before it's fetched again, the longest
+// RFC 9309 recommends caching one
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+// How soon a robots.txt that failed with a server error is tried again
+const SERVER_ERROR_RETRY: Duration = Duration::from_secs(5 * 60);
+
+// The rules one robots.txt sets for us
+#[derive(Default, Debug)]
+pub struct Rules {
+    // (allowed, path pattern) pairs, `*` and a trailing `$` included
+    rules: Vec<(bool, String)>,
+    crawl_delay: Option<Duration>,
+    // `Sitemap:` lines, which belong to no group
+    sitemaps: Vec<Url>,
+}
+
+impl Rules {
+    // A site whose robots.txt can't be read for server errors is treated as
+    // off limits until it can
+    fn disallow_all() -> Rules {
+        Rules { rules: vec![(false, "/".to_string())], ..Rules::default() }
+    }
+
+    // Read the groups for `agent`, or for `*` if none name it. Groups that
+    // name the same agent are combined.
+    pub fn parse(text: &str, agent: &str) -> Rules {
+        struct Group {
+            agents: Vec<String>,
+            rules: Vec<(bool, String)>,
+            crawl_delay: Option<Duration>,
+        }
+
+        let mut groups: Vec<Group> = Vec::new();
+        let mut sitemaps = Vec::new();
+        // A run of `User-agent:` lines opens one group
+        let mut in_agents = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let Some((key, value)) = line.split_once(':') else { continue };
+            let (key, value) = (key.trim().to_ascii_lowercase(), value.trim());
+            if key == "user-agent" {
+                if !in_agents {
+                    groups.push(Group { agents: Vec::new(), rules: Vec::new(), crawl_delay: None });
+                }
+                in_agents = true;
+                if let Some(group) = groups.last_mut() {
+                    group.agents.push(value.to_ascii_lowercase());
+                }
+                continue;
+            }
+            if key == "sitemap" {
+                sitemaps.extend(Url::parse(value).ok());
+                continue;
+            }
+            in_agents = false;
+            let Some(group) = groups.last_mut() else { continue };
+            match key.as_str() {
+                "allow" | "disallow" if !value.is_empty() => group.rules.push((key == "allow", value.to_string())),
+                "crawl-delay" => {
+                    let secs = value.parse::<f64>().ok().filter(|secs| secs.is_finite() && *secs >= 0.0);
+                    group.crawl_delay = secs.map(Duration::from_secs_f64);
+                }
+                _ => {}
+            }
+        }
+
+        let agent = agent.to_ascii_lowercase();
+        let named = groups.iter().any(|group| group.agents.contains(&agent));
+        let wanted = if named { agent } else { "*".to_string() };
+        let mut rules = Rules { sitemaps, ..Rules::default() };
+        for group in groups.into_iter().filter(|group| group.agents.contains(&wanted)) {
+            rules.rules.extend(group.rules);
+            rules.crawl_delay = rules.crawl_delay.max(group.crawl_delay);
+        }
+        rules
+    }
+
+    // The longest matching pattern decides, and `Allow` wins a tie
+    pub fn allows(&self, url: &Url) -> bool {
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        self.rules
+            .iter()
+            .filter(|(_, pattern)| matches(pattern, &path))
+            .max_by_key(|(allowed, pattern)| (pattern.len(), *allowed))
+            .is_none_or(|(allowed, _)| *allowed)
+    }
+}
+
+// Whether `pattern` matches the start of `path`, with `*` standing for any
+// run of characters and a trailing `$` anchoring it to the end
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else { return false };
+    let parts: Vec<&str> = parts.collect();
+    if parts.is_empty() {
+        return !anchored || rest.is_empty();
+    }
+    for (index, part) in parts.iter().enumerate() {
+        let last = index == parts.len() - 1;
+        if last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+// An origin's rules and when to fetch its robots.txt again
+struct Cached {
+    rules: Rules,
+    expires: Instant,
+}
+
+// robots.txt for each origin the crawl touches, fetched the first time a
+// URL there comes up and again once what was fetched expires
+pub struct Robots {
+    client: reqwest::Client,
+    // Lowercased product token the groups are matched against
+    agent: String,
+    // Each origin's own lock, so one fetch at a time goes out for it while
+    // other origins are looked up meanwhile
+    origins: Mutex<HashMap<String, Arc<Mutex<Option<Cached>>>>>,
+}
+
+impl Robots {
+    pub fn new(client: reqwest::Client, agent: String) -> Robots {
+        Robots { client, agent, origins: Mutex::new(HashMap::new()) }
+    }
+
+    pub async fn allows(&self, url: &Url) -> bool {
+        self.rules(url, |rules| rules.allows(url)).await
+    }
+
+    pub async fn crawl_delay(&self, url: &Url) -> Option<Duration> {
+        self.rules(url, |rules| rules.crawl_delay).await
+    }
+
+    pub async fn sitemaps(&self, url: &Url) -> Vec<Url> {
+        self.rules(url, |rules| rules.sitemaps.clone()).await
+    }
+
+    async fn rules<T>(&self, url: &Url, read: impl FnOnce(&Rules) -> T) -> T {
+        let origin = url.origin().ascii_serialization();
+        let slot = self.origins.lock().await.entry(origin.clone()).or_default().clone();
+        let mut cached = slot.lock().await;
+        let cached = match cached.take() {
+            Some(fresh) if fresh.expires > Instant::now() => cached.insert(fresh),
+            _ => cached.insert(self.fetch(origin).await),
+        };
+        read(&cached.rules)
+    }
+
+    // A missing robots.txt allows everything; one the server fails to
+    // serve allows nothing, as RFC 9309 asks, until it's tried again
+    async fn fetch(&self, origin: String) -> Cached {
+        let robots_url = format!("{}/robots.txt", origin);
+        let mut expires = Instant::now() + MAX_AGE;
+        let rules = match self.client.get(&robots_url).send().await {
+            Ok(response) if response.status().is_success() => match read_limited(response).await {
+                Ok(body) => Rules::parse(&String::from_utf8_lossy(&body), &self.agent),
+                Err(_) => Rules::default(),
+            },
+            Ok(response) if response.status().is_server_error() => {
+                println!(
+                    "robots.txt for {} failed with {}; not crawling it for now, trying again in {}s",
+                    origin,
+                    response.status(),
+                    SERVER_ERROR_RETRY.as_secs()
+                );
+                expires = Instant::now() + SERVER_ERROR_RETRY;
+                Rules::disallow_all()
+            }
+            _ => Rules::default(),
+        };
+        if !rules.rules.is_empty() || rules.crawl_delay.is_some() {
+            progress!(
+                "robots.txt for {}: {} rules{}",
+                origin,
+                rules.rules.len(),
+                rules.crawl_delay.map(|delay| format!(", Crawl-delay {:?}", delay)).unwrap_or_default()
+            );
+        }
+        Cached { rules, expires }
+    }
+}
+
+// The first MAX_ROBOTS_SIZE bytes of the body, leaving the rest unread
+async fn read_limited(mut response: reqwest::Response) -> reqwest::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        let room = MAX_ROBOTS_SIZE - body.len();
+        body.extend_from_slice(&chunk[..room.min(chunk.len())]);
+        if body.len() == MAX_ROBOTS_SIZE {
+            break;
+        }
+    }
+    Ok(body)
+}
+
+// Robots directives that take a value after a colon, which isn't then the
+// name of the crawler the rest is addressed to
+const DIRECTIVES_WITH_VALUES: &[&str] = &["unavailable_after", "max-snippet", "max-image-preview", "max-video-preview"];
+
+// Whether the directives of a robots `<meta>` say `noimageindex`: the page's
+// images aren't to be indexed, or for us, saved
+pub fn forbids_images(directives: &str) -> bool {
+    directives.split([',', ' ']).any(|directive| directive.trim().eq_ignore_ascii_case("noimageindex"))
+}
+
+// The same for an `X-Robots-Tag` header, which can be addressed to one
+// crawler with a `name:` prefix; `agent` is ours
+pub fn header_forbids_images(value: &str, agent: &str) -> bool {
+    match value.split_once(':') {
+        Some((name, directives))
+            if !name.contains([',', ' '])
+                && !DIRECTIVES_WITH_VALUES.contains(&name.trim().to_ascii_lowercase().as_str()) =>
+        {
+            name.trim().eq_ignore_ascii_case(agent) && forbids_images(directives)
+        }
+        _ => forbids_images(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(rules: &Rules, path: &str) -> bool {
+        rules.allows(&Url::parse("https://example.com").unwrap().join(path).unwrap())
+    }
+
+    #[test]
+    fn wildcards_match_any_run_of_characters() {
+        assert!(matches("/*.jpg", "/photos/cat.jpg"));
+        assert!(matches("/photos/*/large", "/photos/2024/cat/large/1"));
+        assert!(matches("/private*", "/private"));
+        assert!(!matches("/*.jpg", "/photos/cat.png"));
+        assert!(!matches("/photos/*/large", "/photos/large"));
+    }
+
+    #[test]
+    fn dollar_anchors_to_the_end() {
+        assert!(matches("/*.gif$", "/a/b.gif"));
+        assert!(!matches("/*.gif$", "/a/b.gif?size=2"));
+        assert!(matches("/exact$", "/exact"));
+        assert!(!matches("/exact$", "/exactly"));
+        // Without one the pattern is a prefix
+        assert!(matches("/exact", "/exactly"));
+    }
+
+    #[test]
+    fn named_group_wins_over_star() {
+        let text = "User-agent: *\nDisallow: /\n\nUser-agent: OtherBot\nUser-agent: image_crawler\nDisallow: /private\nCrawl-delay: 2\n";
+        let rules = Rules::parse(text, "Image_Crawler");
+        assert!(allowed(&rules, "/gallery"));
+        assert!(!allowed(&rules, "/private/a.jpg"));
+        assert_eq!(rules.crawl_delay, Some(Duration::from_secs(2)));
+
+        let rules = Rules::parse(text, "acmebot");
+        assert!(!allowed(&rules, "/gallery"));
+        assert_eq!(rules.crawl_delay, None);
+    }
+
+    #[test]
+    fn groups_naming_the_same_agent_combine() {
+        let text = "User-agent: image_crawler\nDisallow: /a\n\nUser-agent: *\nDisallow: /b\n\nUser-agent: image_crawler\nDisallow: /c\n";
+        let rules = Rules::parse(text, "image_crawler");
+        assert!(!allowed(&rules, "/a"));
+        assert!(allowed(&rules, "/b"));
+        assert!(!allowed(&rules, "/c"));
+    }
+
+    #[test]
+    fn longest_match_decides_and_allow_wins_a_tie() {
+        let text = "User-agent: *\nDisallow: /photos\nAllow: /photos/public\nAllow: /same\nDisallow: /same\n";
+        let rules = Rules::parse(text, "image_crawler");
+        assert!(!allowed(&rules, "/photos/private.jpg"));
+        assert!(allowed(&rules, "/photos/public/cat.jpg"));
+        assert!(allowed(&rules, "/same/page"));
+        assert!(allowed(&rules, "/elsewhere"));
+    }
+
+    #[test]
+    fn queries_comments_and_sitemaps() {
+        let text = "Sitemap: https://example.com/sitemap.xml\nUser-agent: * # everyone\nDisallow: /*?session= # no sessions\nDisallow:\n";
+        let rules = Rules::parse(text, "image_crawler");
+        assert!(!allowed(&rules, "/page?session=1"));
+        assert!(allowed(&rules, "/page?id=1"));
+        assert_eq!(rules.sitemaps, vec![Url::parse("https://example.com/sitemap.xml").unwrap()]);
+    }
+
+    #[test]
+    fn x_robots_tag_names_the_configured_agent() {
+        assert!(header_forbids_images("noimageindex, nofollow", "acmebot"));
+        assert!(header_forbids_images("AcmeBot: noimageindex", "acmebot"));
+        assert!(!header_forbids_images("image_crawler: noimageindex", "acmebot"));
+        assert!(!header_forbids_images("unavailable_after: 2030-01-01", "acmebot"));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::pause::{PauseGate, PauseReason};
+use anyhow::{bail, Context, Result};
+use chrono::Timelike;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+// A daily window of local time, e.g. `22:00-06:00`, during which the crawl
+// may issue requests. Windows that end before they start wrap past midnight.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ActiveHours {
+    // Minutes since midnight
+    start: u32,
+    end: u32,
+}
+
+impl ActiveHours {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    // Seconds from `second_of_day` until the window next opens or closes
+    fn secs_until_boundary(&self, second_of_day: u32) -> u32 {
+        [self.start, self.end]
+            .iter()
+            .map(|minute| (minute * 60 + MINUTES_PER_DAY * 60 - second_of_day) % (MINUTES_PER_DAY * 60))
+            .filter(|&secs| secs > 0)
+            .min()
+            .unwrap_or(MINUTES_PER_DAY * 60)
+    }
+}
+
+impl fmt::Display for ActiveHours {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = |minute: u32| format!("{:02}:{:02}", minute / 60, minute % 60);
+        write!(f, "{}-{}", time(self.start), time(self.end))
+    }
+}
+
+fn parse_time(text: &str) -> Result<u32> {
+    let (hours, minutes) = text.split_once(':').context("expected HH:MM")?;
+    let hours: u32 = hours.parse().context("bad hour")?;
+    let minutes: u32 = minutes.parse().context("bad minute")?;
+    if hours > 23 || minutes > 59 {
+        bail!("{} is not a time of day", text);
+    }
+    Ok(hours * 60 + minutes)
+}
+
+impl FromStr for ActiveHours {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s.split_once('-').context("expected HH:MM-HH:MM")?;
+        let hours = ActiveHours { start: parse_time(start.trim())?, end: parse_time(end.trim())? };
+        if hours.start == hours.end {
+            bail!("the window must not start and end at the same time");
+        }
+        Ok(hours)
+    }
+}
+
+fn second_of_day() -> u32 {
+    chrono::Local::now().num_seconds_from_midnight()
+}
+
+// Hold the crawl outside the window. The gate is set before returning, so a
+// crawl started off-hours waits from its very first request.
+pub fn enforce(hours: ActiveHours, gate: Arc<PauseGate>) {
+    let mut open = hours.contains(second_of_day() / 60);
+    if !open {
+        gate.pause(PauseReason::OutsideActiveHours);
+        println!("Outside active hours ({}); waiting for the window to open", hours);
+    }
+
+    tokio::spawn(async move {
+        loop {
+            sleep(Duration::from_secs(hours.secs_until_boundary(second_of_day()).into())).await;
+            let now_open = hours.contains(second_of_day() / 60);
+            if now_open == open {
+                continue;
+            }
+            open = now_open;
+            if open {
+                gate.resume(PauseReason::OutsideActiveHours);
+                println!("Active hours started; crawl resumed");
+            } else {
+                gate.pause(PauseReason::OutsideActiveHours);
+                println!("Active hours ended; crawl paused, in-flight requests will finish");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> u32 {
+        parse_time(time).unwrap()
+    }
+
+    #[test]
+    fn daytime_window() {
+        let hours: ActiveHours = "09:00-17:30".parse().unwrap();
+        assert!(hours.contains(at("12:00")));
+        assert!(!hours.contains(at("08:59")));
+        assert!(!hours.contains(at("18:00")));
+        assert!(hours.contains(at("09:00")));
+        assert!(!hours.contains(at("17:30")));
+    }
+
+    #[test]
+    fn window_wrapping_past_midnight() {
+        let hours: ActiveHours = "22:00-06:00".parse().unwrap();
+        assert!(hours.contains(at("23:30")));
+        assert!(hours.contains(at("00:00")));
+        assert!(hours.contains(at("05:59")));
+        assert!(!hours.contains(at("12:00")));
+        assert!(hours.contains(at("22:00")));
+        assert!(!hours.contains(at("06:00")));
+        assert_eq!(hours.to_string(), "22:00-06:00");
+    }
+
+    #[test]
+    fn seconds_until_the_next_boundary() {
+        let hours: ActiveHours = "22:00-06:00".parse().unwrap();
+        // Before midnight the window closes the next morning
+        assert_eq!(hours.secs_until_boundary(at("23:00") * 60), 7 * 3600);
+        // After midnight, the same boundary
+        assert_eq!(hours.secs_until_boundary(at("01:00") * 60 + 30), 5 * 3600 - 30);
+        // Outside the window, until it opens
+        assert_eq!(hours.secs_until_boundary(at("21:59") * 60), 60);
+        // Exactly on a boundary, the next one
+        assert_eq!(hours.secs_until_boundary(at("22:00") * 60), 8 * 3600);
+    }
+
+    #[test]
+    fn rejects_bad_windows() {
+        assert!("25:00-06:00".parse::<ActiveHours>().is_err());
+        assert!("10:00-25:00".parse::<ActiveHours>().is_err());
+        assert!("aa-bb".parse::<ActiveHours>().is_err());
+        assert!("10:00-10:00".parse::<ActiveHours>().is_err());
+        assert!("10:00".parse::<ActiveHours>().is_err());
+    }
+}
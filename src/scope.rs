@@ -0,0 +1,123 @@
+use crate::config::CrawlScope;
+use crate::verbosity::progress;
+use std::collections::HashSet;
+use std::sync::RwLock;
+use url::{Origin, Url};
+
+// Decides which URLs belong to the crawl and maps hosts that serve the same
+// site (e.g. `example.com` and `www.example.com`) onto one canonical host,
+// so both scope checks and dedup treat them as a single site.
+pub struct Scope {
+    origin: Origin,
+    canonical_host: String,
+    // Hosts known to serve the same site as `canonical_host`
+    aliases: RwLock<HashSet<String>>,
+    extent: CrawlScope,
+    unify_hosts: bool,
+    // Rewrite http:// links on the canonical host to https://
+    upgrade_http: bool,
+}
+
+impl Scope {
+    pub fn new(seed: &Url, extent: CrawlScope, unify_hosts: bool) -> Scope {
+        Scope {
+            origin: seed.origin(),
+            canonical_host: seed.host_str().unwrap_or_default().to_string(),
+            aliases: RwLock::new(HashSet::new()),
+            extent,
+            unify_hosts,
+            upgrade_http: false,
+        }
+    }
+
+    // The site is crawled over https, so in-scope http:// links get upgraded
+    // instead of being treated as a different origin.
+    pub fn upgrade_http_links(&mut self) {
+        self.upgrade_http = true;
+    }
+
+    // The seed redirected somewhere else before crawling started. If it's
+    // the www/apex twin, adopt the redirect target as the canonical host.
+    pub fn adopt_seed_redirect(&mut self, final_url: &Url) {
+        let Some(host) = final_url.host_str() else { return };
+        if !self.unify_hosts || host == self.canonical_host || !is_www_twin(host, &self.canonical_host) {
+            return;
+        }
+
+        progress!("Unifying {} with {}", self.canonical_host, host);
+        let previous = std::mem::replace(&mut self.canonical_host, host.to_string());
+        self.aliases.get_mut().unwrap().insert(previous);
+        self.origin = final_url.origin();
+    }
+
+    // Record evidence (a redirect or `<link rel="canonical">`) that `other`
+    // serves the same site as `page`.
+    pub fn note_same_site(&self, page: &Url, other: &Url) {
+        if !self.unify_hosts || page.host_str() != Some(self.canonical_host.as_str()) {
+            return;
+        }
+        let Some(host) = other.host_str() else { return };
+        if host == self.canonical_host || !is_www_twin(host, &self.canonical_host) {
+            return;
+        }
+
+        if self.aliases.write().unwrap().insert(host.to_string()) {
+            progress!("Unifying {} with {}", host, self.canonical_host);
+        }
+    }
+
+    // The host the crawl treats as the site's name
+    pub fn host(&self) -> &str {
+        &self.canonical_host
+    }
+
+    // Rewrite aliased hosts to the canonical host and report whether the
+    // URL is within the crawl scope.
+    pub fn canonicalize(&self, url: &mut Url) -> bool {
+        let is_alias = url
+            .host_str()
+            .is_some_and(|host| self.aliases.read().unwrap().contains(host));
+        if is_alias {
+            let _ = url.set_host(Some(&self.canonical_host));
+        }
+        if self.upgrade_http && url.scheme() == "http" && url.host_str() == Some(self.canonical_host.as_str()) {
+            let _ = url.set_scheme("https");
+        }
+        self.contains(url)
+    }
+
+    fn contains(&self, url: &Url) -> bool {
+        if self.extent == CrawlScope::Origin {
+            return url.origin() == self.origin;
+        }
+        let Some(host) = url.host_str().filter(|_| matches!(url.scheme(), "http" | "https")) else {
+            return false;
+        };
+        match self.extent {
+            CrawlScope::Domain => {
+                let domain = self.canonical_host.strip_prefix("www.").unwrap_or(&self.canonical_host);
+                host == domain || host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.'))
+            }
+            _ => host == self.canonical_host,
+        }
+    }
+
+    // Whether following a redirect to `target` keeps the crawl in scope.
+    // Being sent from http to https on the same host doesn't leave the site,
+    // even when the crawl started on http.
+    pub fn allows_redirect(&self, target: &Url) -> bool {
+        let mut target = target.clone();
+        if self.canonicalize(&mut target) {
+            return true;
+        }
+        target.scheme() == "https"
+            && target.port().is_none()
+            && target.set_scheme("http").is_ok()
+            && self.canonicalize(&mut target)
+    }
+}
+
+fn is_www_twin(a: &str, b: &str) -> bool {
+    let strip = |host: &str| host.strip_prefix("www.").unwrap_or(host).to_string();
+    a != b && strip(a) == strip(b)
+}
@@ -0,0 +1,155 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash};
+use std::sync::Mutex;
+
+// Enough that a few hundred concurrent downloads seldom land on the same one
+const SHARDS: usize = 64;
+
+fn shard_of<Q: Hash + ?Sized>(hasher: &RandomState, key: &Q) -> usize {
+    hasher.hash_one(key) as usize % SHARDS
+}
+
+// A map split by key over locks of its own, so tasks working on different
+// keys don't queue behind one another. A shard is only locked for the
+// lookup or update itself, never across an await.
+pub struct ShardedMap<K, V> {
+    shards: Vec<Mutex<HashMap<K, V>>>,
+    hasher: RandomState,
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+    pub fn new() -> ShardedMap<K, V> {
+        ShardedMap { shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(), hasher: RandomState::new() }
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> std::sync::MutexGuard<'_, HashMap<K, V>> {
+        self.shards[shard_of(&self.hasher, key)].lock().unwrap()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.shard(key).get(key).cloned()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).insert(key, value)
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).remove(key)
+    }
+
+    // Visits every shard in turn, so it's for the rare cleanup that can't
+    // go by key
+    pub fn retain(&self, mut keep: impl FnMut(&K, &mut V) -> bool) {
+        for shard in &self.shards {
+            shard.lock().unwrap().retain(&mut keep);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for ShardedMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> ShardedMap<K, V> {
+        let map = ShardedMap::new();
+        for (key, value) in entries {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+// A set split the same way, for the URLs a crawl has seen
+pub struct ShardedSet<T> {
+    shards: Vec<Mutex<HashSet<T>>>,
+    hasher: RandomState,
+}
+
+impl<T: Hash + Eq> ShardedSet<T> {
+    pub fn new() -> ShardedSet<T> {
+        ShardedSet { shards: (0..SHARDS).map(|_| Mutex::new(HashSet::new())).collect(), hasher: RandomState::new() }
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, value: &Q) -> std::sync::MutexGuard<'_, HashSet<T>> {
+        self.shards[shard_of(&self.hasher, value)].lock().unwrap()
+    }
+
+    // Whether `value` is new to the set
+    pub fn insert(&self, value: T) -> bool {
+        self.shard(&value).insert(value)
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(value).contains(value)
+    }
+
+    pub fn remove<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(value).remove(value)
+    }
+
+    // Counted shard by shard, so only exact once the crawl has settled
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().len()).sum()
+    }
+
+    pub fn to_vec(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        self.shards.iter().flat_map(|shard| shard.lock().unwrap().iter().cloned().collect::<Vec<_>>()).collect()
+    }
+}
+
+impl<T: Hash + Eq> FromIterator<T> for ShardedSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(values: I) -> ShardedSet<T> {
+        let set = ShardedSet::new();
+        for value in values {
+            set.insert(value);
+        }
+        set
+    }
+}
+
+// Async locks by key, for work that has to be done one at a time per key
+// (saving the same bytes, or the same file name) but can overlap for
+// different ones. Keys sharing a shard wait on each other too.
+pub struct KeyLocks {
+    shards: Vec<tokio::sync::Mutex<()>>,
+    hasher: RandomState,
+}
+
+impl KeyLocks {
+    pub fn new() -> KeyLocks {
+        KeyLocks { shards: (0..SHARDS).map(|_| tokio::sync::Mutex::new(())).collect(), hasher: RandomState::new() }
+    }
+
+    // Hold every key at once. Shards are always taken in the same order, so
+    // two callers after overlapping keys can't deadlock.
+    pub async fn lock(&self, keys: &[&str]) -> Vec<tokio::sync::MutexGuard<'_, ()>> {
+        let mut shards: Vec<usize> = keys.iter().map(|key| shard_of(&self.hasher, key)).collect();
+        shards.sort_unstable();
+        shards.dedup();
+        let mut guards = Vec::with_capacity(shards.len());
+        for shard in shards {
+            guards.push(self.shards[shard].lock().await);
+        }
+        guards
+    }
+}
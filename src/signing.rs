@@ -0,0 +1,88 @@
+use crate::dates::unix_now;
+use crate::manifest::MANIFEST_FILE;
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use ed25519_dalek::{Signature, Signer as _, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+// Written next to the manifest it signs
+pub const SIGNATURE_FILE: &str = "manifest.json.sig";
+
+// What `--sign-key` leaves behind: who signed which manifest, and when. The
+// signature is over the manifest's exact bytes, and since the manifest lists
+// every image's SHA-256 it vouches for the images as well.
+#[derive(Serialize, Deserialize)]
+struct Provenance {
+    file: String,
+    sha256: String,
+    algorithm: String,
+    // Base64, as are the signature's bytes
+    public_key: String,
+    signature: String,
+    signed_at: u64,
+}
+
+pub struct Signer {
+    key: SigningKey,
+}
+
+impl Signer {
+    // An ed25519 private key in PKCS#8 PEM, as `openssl genpkey -algorithm
+    // ed25519` writes it
+    pub fn load(path: &Path) -> Result<Signer> {
+        let pem = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let key = SigningKey::from_pkcs8_pem(&pem)
+            .map_err(|e| anyhow::anyhow!("{} isn't an ed25519 private key in PKCS#8 PEM: {}", path.display(), e))?;
+        Ok(Signer { key })
+    }
+
+    // Sign the manifest in `dir` as it is on disk now
+    pub async fn sign(&self, dir: &Path) -> Result<PathBuf> {
+        let manifest = tokio::fs::read(dir.join(MANIFEST_FILE)).await?;
+        let base64 = base64::engine::general_purpose::STANDARD;
+        let provenance = Provenance {
+            file: MANIFEST_FILE.to_string(),
+            sha256: format!("{:x}", Sha256::digest(&manifest)),
+            algorithm: "ed25519".to_string(),
+            public_key: base64.encode(self.key.verifying_key().as_bytes()),
+            signature: base64.encode(self.key.sign(&manifest).to_bytes()),
+            signed_at: unix_now(),
+        };
+        let path = dir.join(SIGNATURE_FILE);
+        tokio::fs::write(&path, serde_json::to_vec_pretty(&provenance)?).await?;
+        Ok(path)
+    }
+}
+
+// Check the signature on the manifest in `dir`, if it has one, returning the
+// public key it was signed with so it can be compared to the expected one
+pub fn check(dir: &Path) -> Result<Option<String>> {
+    let path = dir.join(SIGNATURE_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let provenance: Provenance =
+        serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+    let manifest = std::fs::read(dir.join(&provenance.file))?;
+
+    let base64 = base64::engine::general_purpose::STANDARD;
+    let key_bytes: [u8; 32] = base64
+        .decode(&provenance.public_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .with_context(|| format!("Malformed public key in {}", path.display()))?;
+    let signature_bytes: [u8; 64] = base64
+        .decode(&provenance.signature)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .with_context(|| format!("Malformed signature in {}", path.display()))?;
+    let key = VerifyingKey::from_bytes(&key_bytes).with_context(|| format!("Invalid public key in {}", path.display()))?;
+    if key.verify(&manifest, &Signature::from_bytes(&signature_bytes)).is_err() {
+        bail!("{} doesn't match the signature in {}", provenance.file, SIGNATURE_FILE);
+    }
+    Ok(Some(provenance.public_key))
+}
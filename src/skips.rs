@@ -0,0 +1,118 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use url::Url;
+
+// Written to the job's output directory with `--skip-log`
+pub const SKIP_LOG_FILE: &str = "skipped.jsonl";
+
+// Why a page or image the crawl came across was left alone
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Skip {
+    // Another site's, or not http(s)
+    OutOfScope,
+    Robots,
+    Blacklisted,
+    // An http:// image on an https:// page, with `--mixed-content skip`
+    MixedContent,
+    // An image format left out of `--formats`
+    Format,
+    // Under `--min-width`, `--min-height` or `--min-data-uri-size`
+    TooSmall,
+    // The same image as one already saved, or a smaller copy of it
+    Duplicate,
+    // A page whose images robots meta or X-Robots-Tag asks us to leave alone
+    Noimageindex,
+    // A link to something that's neither a page nor an image
+    NotAPage,
+}
+
+impl Skip {
+    fn label(self) -> &'static str {
+        match self {
+            Skip::OutOfScope => "out of scope",
+            Skip::Robots => "robots.txt",
+            Skip::Blacklisted => "blacklisted host",
+            Skip::MixedContent => "mixed content",
+            Skip::Format => "format",
+            Skip::TooSmall => "too small",
+            Skip::Duplicate => "duplicate",
+            Skip::Noimageindex => "noimageindex",
+            Skip::NotAPage => "not a page",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Skipped<'a> {
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<&'a str>,
+    reason: Skip,
+}
+
+// What a job skipped and why, each URL counted once per reason however often
+// it turns up, so a filter that's too aggressive shows in the summary. With
+// `--skip-log` each one also gets a line in `skipped.jsonl`.
+pub struct Skips {
+    seen: Mutex<HashSet<(Skip, String)>>,
+    log: Option<Mutex<BufWriter<File>>>,
+}
+
+impl Skips {
+    // The log starts over each run, to match the summary
+    pub fn open(output_dir: &Path, log: bool) -> Result<Skips> {
+        let log = match log {
+            true => {
+                let path = output_dir.join(SKIP_LOG_FILE);
+                let file = File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+                Some(Mutex::new(BufWriter::new(file)))
+            }
+            false => None,
+        };
+        Ok(Skips { seen: Mutex::new(HashSet::new()), log })
+    }
+
+    pub fn record(&self, skip: Skip, url: &Url, page: Option<&Url>) {
+        if !self.seen.lock().unwrap().insert((skip, url.to_string())) {
+            return;
+        }
+        if let Some(log) = &self.log {
+            let line = Skipped { url: url.as_str(), page: page.map(Url::as_str), reason: skip };
+            // The log is a diagnostic, not worth stopping the crawl for
+            if let Ok(line) = serde_json::to_string(&line) {
+                let _ = writeln!(log.lock().unwrap(), "{}", line);
+            }
+        }
+    }
+
+    pub fn logged(&self) -> bool {
+        self.log.is_some()
+    }
+
+    // "12 (7 out of scope, 5 too small)", or None if nothing was skipped
+    pub fn summary(&self) -> Option<String> {
+        let seen = self.seen.lock().unwrap();
+        if seen.is_empty() {
+            return None;
+        }
+        let mut counts: BTreeMap<Skip, usize> = BTreeMap::new();
+        for (skip, _) in seen.iter() {
+            *counts.entry(*skip).or_default() += 1;
+        }
+        let counts: Vec<String> = counts.iter().map(|(skip, count)| format!("{} {}", count, skip.label())).collect();
+        Some(format!("{} ({})", seen.len(), counts.join(", ")))
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        if let Some(log) = &self.log {
+            log.lock().unwrap().flush().context("Failed to write the skip log")?;
+        }
+        Ok(())
+    }
+}
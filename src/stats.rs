@@ -0,0 +1,147 @@
+use crate::checkpoint;
+use crate::dates::{date, DAY};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+const WEEK: u64 = 7 * DAY;
+// The Unix epoch fell on a Thursday; shifting by this many days makes weeks start on Monday
+const MONDAY_OFFSET: u64 = 3 * DAY;
+
+// Totals for one run of one job, appended to the stats DB when it ends
+#[derive(Serialize, Deserialize)]
+pub struct RunRecord {
+    pub site: String,
+    pub job: String,
+    pub started_at: u64,
+    pub duration_secs: u64,
+    pub pages_visited: usize,
+    pub images_downloaded: usize,
+    // Images no earlier run of this job had downloaded
+    pub new_images: usize,
+    pub failed: bool,
+    // Stopped early on request, so the totals are partial
+    #[serde(default)]
+    pub cancelled: bool,
+}
+
+pub fn db_path(output_dir: &Path) -> PathBuf {
+    output_dir.join(".state").join("runs.jsonl")
+}
+
+// Count the images this run is the first to see, and remember them for
+// the next run
+pub async fn count_new_images(output_dir: &Path, job: &str, images: &HashSet<String>) -> Result<usize> {
+    let dir = checkpoint::state_dir(output_dir, job);
+    fs::create_dir_all(&dir).await?;
+    let path = dir.join("seen_images.txt");
+
+    let seen: HashSet<String> = match fs::read_to_string(&path).await {
+        Ok(text) => text.lines().map(str::to_string).collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let new: Vec<&String> = images.iter().filter(|url| !seen.contains(*url)).collect();
+    if !new.is_empty() {
+        let mut file = OpenOptions::new().create(true).append(true).open(&path).await?;
+        let mut lines = String::new();
+        for url in &new {
+            lines.push_str(url);
+            lines.push('\n');
+        }
+        file.write_all(lines.as_bytes()).await?;
+    }
+    Ok(new.len())
+}
+
+pub async fn record_run(output_dir: &Path, record: &RunRecord) -> Result<()> {
+    let path = db_path(output_dir);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(&path).await?;
+    file.write_all(&line).await?;
+    Ok(())
+}
+
+fn load_runs(output_dir: &Path) -> Result<Vec<RunRecord>> {
+    let text = match std::fs::read_to_string(db_path(output_dir)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+}
+
+// `report` subcommand: per-site history of recurring crawls
+pub fn print_trends(output_dir: &Path, site_filter: Option<&str>) -> Result<()> {
+    let mut by_site: BTreeMap<String, Vec<RunRecord>> = BTreeMap::new();
+    for run in load_runs(output_dir)? {
+        if site_filter.is_none_or(|filter| run.site.contains(filter)) {
+            by_site.entry(run.site.clone()).or_default().push(run);
+        }
+    }
+
+    if by_site.is_empty() {
+        println!("No crawl runs recorded yet in {}", db_path(output_dir).display());
+        return Ok(());
+    }
+
+    for (site, mut runs) in by_site {
+        runs.sort_by_key(|run| run.started_at);
+        let first = &runs[0];
+        let last = &runs[runs.len() - 1];
+        let failed = runs.iter().filter(|run| run.failed).count();
+        let cancelled = runs.iter().filter(|run| run.cancelled).count();
+        let average_secs = runs.iter().map(|run| run.duration_secs).sum::<u64>() / runs.len() as u64;
+
+        println!("{}", site);
+        println!(
+            "  Runs: {} ({} failed, {} stopped early), first {}, last {}",
+            runs.len(),
+            failed,
+            cancelled,
+            date(first.started_at),
+            date(last.started_at)
+        );
+        println!("  Average duration: {}", duration(average_secs));
+        println!(
+            "  Pages: {} -> {} ({:+})",
+            first.pages_visited,
+            last.pages_visited,
+            last.pages_visited as i64 - first.pages_visited as i64
+        );
+        println!(
+            "  Images per run: {} -> {} ({:+})",
+            first.images_downloaded,
+            last.images_downloaded,
+            last.images_downloaded as i64 - first.images_downloaded as i64
+        );
+
+        let mut weekly: BTreeMap<u64, usize> = BTreeMap::new();
+        for run in &runs {
+            let week_start = (run.started_at + MONDAY_OFFSET) / WEEK * WEEK - MONDAY_OFFSET;
+            *weekly.entry(week_start).or_default() += run.new_images;
+        }
+        println!("  New images per week:");
+        for (week, new_images) in weekly {
+            println!("    week of {}: {}", date(week), new_images);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn duration(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
@@ -0,0 +1,251 @@
+use crate::dates::unix_now;
+use crate::CrawlState;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use url::Url;
+
+// Where a container orchestrator looks for a terminated pod's message
+pub const TERMINATION_LOG: &str = "/dev/termination-log";
+pub const DEFAULT_STATUS_ADDR: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 8080));
+// `/crawl` takes pages from anyone who can reach it, so a daemon only listens
+// on other interfaces when given an address
+pub const DAEMON_STATUS_ADDR: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8080));
+
+// Exit codes besides 0. A stopped crawl only exits with EXIT_STOPPED as a
+// container job, where exiting 0 would mark the job as done.
+const EXIT_FAILED: i32 = 1;
+const EXIT_STOPPED: i32 = 3;
+
+// Requests longer than this, or slower to arrive, are dropped
+const MAX_REQUEST: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct Progress {
+    paused: bool,
+    jobs: Vec<JobProgress>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct JobProgress {
+    pub name: String,
+    // running, done or stopping
+    pub status: &'static str,
+    pub pages_visited: usize,
+    pub pages_queued: usize,
+    pub images_downloaded: usize,
+    pub bytes_saved: u64,
+    pub errors: usize,
+    pub requests_in_flight: usize,
+}
+
+async fn job_progress(state: &CrawlState) -> JobProgress {
+    let status = if state.finished.load(Ordering::Relaxed) {
+        "done"
+    } else if state.cancel.is_cancelled() {
+        "stopping"
+    } else {
+        "running"
+    };
+    JobProgress {
+        name: state.name.clone(),
+        status,
+        pages_visited: state.visited_urls.len(),
+        pages_queued: state.pending_urls.lock().await.len(),
+        images_downloaded: state.downloaded_images.len(),
+        bytes_saved: state.bytes_saved.load(Ordering::Relaxed),
+        errors: state.errors.lock().await.len(),
+        requests_in_flight: state.in_flight.len(),
+    }
+}
+
+// Serve `/healthz` for liveness probes and `/progress` as JSON for whoever
+// is watching the crawl, and with `--daemon` take pages to crawl. Binding
+// happens up front so a taken port fails the run before any requests go out.
+pub async fn serve(addr: SocketAddr, states: Vec<Arc<CrawlState>>) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    let endpoints = if states.iter().any(|state| state.daemon) {
+        "/healthz, /progress and POST /crawl"
+    } else {
+        "/healthz and /progress"
+    };
+    println!("Serving {} on {}", endpoints, listener.local_addr()?);
+    let states = Arc::new(states);
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else { continue };
+            let states = states.clone();
+            tokio::spawn(async move {
+                let _ = timeout(REQUEST_TIMEOUT, respond(stream, &states)).await;
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn respond(mut stream: TcpStream, states: &[Arc<CrawlState>]) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    let head_length = loop {
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    };
+    let head = String::from_utf8_lossy(&request[..head_length]).into_owned();
+    let body_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    if head_length + body_length > MAX_REQUEST {
+        return Ok(());
+    }
+    while request.len() < head_length + body_length {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    let posted = &request[head_length..head_length + body_length];
+
+    let mut parts = head.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let path = target.split('?').next().unwrap_or_default();
+    let (status, content_type, body) = match (method, path) {
+        ("GET" | "HEAD", "/healthz") => ("200 OK", "text/plain", "ok\n".to_string()),
+        ("GET" | "HEAD", "/progress") => {
+            let mut jobs = Vec::new();
+            for state in states {
+                jobs.push(job_progress(state).await);
+            }
+            let paused = states.first().is_some_and(|state| state.pause.is_paused());
+            let body = serde_json::to_string_pretty(&Progress { paused, jobs }).unwrap_or_default();
+            ("200 OK", "application/json", body + "\n")
+        }
+        ("POST", "/crawl") if states.iter().any(|state| state.daemon) => {
+            let (status, message) = post(states, posted).await;
+            (status, "text/plain", message + "\n")
+        }
+        ("GET" | "HEAD", _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "method not allowed\n".to_string()),
+    };
+
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    if method != "HEAD" {
+        response.push_str(&body);
+    }
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[derive(Deserialize)]
+struct Posted {
+    url: String,
+}
+
+// `POST /crawl` with `{"url": "..."}`: hand the page to the first job whose
+// scope it's in
+async fn post(states: &[Arc<CrawlState>], body: &[u8]) -> (&'static str, String) {
+    let url = match serde_json::from_slice::<Posted>(body) {
+        Ok(posted) => posted.url,
+        Err(e) => return ("400 Bad Request", format!("expected {{\"url\": \"...\"}}: {}", e)),
+    };
+    let Ok(page) = Url::parse(&url) else {
+        return ("400 Bad Request", format!("{} isn't a URL", url));
+    };
+    for state in states {
+        if state.post(page.clone()).await {
+            return ("202 Accepted", format!("queued {} in {}", page, state.name));
+        }
+    }
+    ("422 Unprocessable Entity", format!("{} isn't in the scope of any job", page))
+}
+
+// How a run, or one of its jobs, ended
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Completed,
+    // Stopped early, e.g. by Ctrl-C, keeping what it had found
+    Stopped,
+    Failed,
+}
+
+// What `Crawler::run` returns, and what `--status-file` holds as JSON
+#[derive(Serialize, Debug)]
+pub struct CrawlReport {
+    pub outcome: Outcome,
+    pub exit_code: i32,
+    pub finished_at: u64,
+    pub jobs: Vec<JobReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct JobReport {
+    #[serde(flatten)]
+    pub progress: JobProgress,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Sum up how every job ended. `failures` holds each job's error message,
+// if it failed.
+pub async fn report(states: &[Arc<CrawlState>], failures: Vec<Option<String>>, container: bool) -> CrawlReport {
+    let mut jobs = Vec::new();
+    for (state, error) in states.iter().zip(failures) {
+        let outcome = match (&error, state.cancel.is_cancelled()) {
+            (Some(_), _) => Outcome::Failed,
+            (None, true) => Outcome::Stopped,
+            (None, false) => Outcome::Completed,
+        };
+        jobs.push(JobReport { progress: job_progress(state).await, outcome, error });
+    }
+    let outcome = if jobs.iter().any(|job| job.outcome == Outcome::Failed) {
+        Outcome::Failed
+    } else if jobs.iter().any(|job| job.outcome == Outcome::Stopped) {
+        Outcome::Stopped
+    } else {
+        Outcome::Completed
+    };
+    let exit_code = match outcome {
+        Outcome::Failed => EXIT_FAILED,
+        Outcome::Stopped if container => EXIT_STOPPED,
+        _ => 0,
+    };
+    CrawlReport { outcome, exit_code, finished_at: unix_now(), jobs }
+}
+
+impl CrawlReport {
+    // Write the report for a job runner to pick up once the process exits
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        let mut bytes = serde_json::to_vec_pretty(self)?;
+        bytes.push(b'\n');
+        fs::write(path, bytes)
+            .await
+            .with_context(|| format!("Failed to write exit status to {}", path.display()))
+    }
+}
@@ -0,0 +1,123 @@
+use base64::Engine;
+use url::Url;
+
+// Remove session-ID style query parameters (and `;jsessionid=` path
+// parameters) so the same page reached from different sessions dedups.
+pub fn strip_session_params(url: &mut Url, strip_params: &[String]) {
+    if strip_params.is_empty() {
+        return;
+    }
+
+    // Java servlet containers append the session to the path itself
+    let path = url.path();
+    if let Some(pos) = path.to_ascii_lowercase().find(";jsessionid=") {
+        if strip_params.iter().any(|p| p == "jsessionid") {
+            let stripped = path[..pos].to_string();
+            url.set_path(&stripped);
+        }
+    }
+
+    if url.query().is_none() {
+        return;
+    }
+
+    let total = url.query_pairs().count();
+    let kept: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !strip_params.contains(&key.to_ascii_lowercase()))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+
+    // Leave untouched queries byte-for-byte as they were
+    if kept.len() == total {
+        return;
+    }
+
+    if kept.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(kept);
+    }
+}
+
+// Resolve an href against the page URL and normalize it for dedup
+pub fn resolve(base: &Url, href: &str, strip_params: &[String]) -> Option<Url> {
+    let mut url = base.join(href).ok()?;
+    strip_session_params(&mut url, strip_params);
+    Some(url)
+}
+
+// Whether the URL's host answers over https at all
+pub async fn supports_https(client: &reqwest::Client, url: &Url) -> bool {
+    let mut probe = url.clone();
+    if probe.set_scheme("https").is_err() {
+        return false;
+    }
+    probe.set_path("/");
+    probe.set_query(None);
+    client.head(probe.as_str()).send().await.is_ok()
+}
+
+// The bytes of a base64 `data:image/...` URI. Other data URIs (plain text,
+// percent-encoded SVG) yield None.
+pub fn decode_data_image(uri: &str) -> Option<Vec<u8>> {
+    let (meta, data) = uri.strip_prefix("data:")?.split_once(',')?;
+    let mut params = meta.split(';');
+    let mime = params.next()?;
+    if !mime.to_ascii_lowercase().starts_with("image/") || !params.any(|param| param.eq_ignore_ascii_case("base64")) {
+        return None;
+    }
+    let data: String = data.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    base64::engine::general_purpose::STANDARD.decode(data).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stripped(url: &str, params: &[&str]) -> String {
+        let mut url = Url::parse(url).unwrap();
+        let params: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+        strip_session_params(&mut url, &params);
+        url.to_string()
+    }
+
+    #[test]
+    fn drops_listed_params_whatever_their_case() {
+        assert_eq!(
+            stripped("https://example.com/p?id=7&PHPSESSID=abc&page=2", &["phpsessid"]),
+            "https://example.com/p?id=7&page=2"
+        );
+    }
+
+    #[test]
+    fn drops_the_query_once_nothing_is_left() {
+        assert_eq!(stripped("https://example.com/p?sid=1&sid=2", &["sid"]), "https://example.com/p");
+    }
+
+    #[test]
+    fn leaves_other_queries_as_they_were() {
+        let url = "https://example.com/p?q=a+b&x=%7E";
+        assert_eq!(stripped(url, &["sid"]), url);
+        assert_eq!(stripped(url, &[]), url);
+    }
+
+    #[test]
+    fn strips_jsessionid_from_the_path() {
+        assert_eq!(
+            stripped("https://example.com/shop;jsessionid=0A1B?item=3", &["jsessionid"]),
+            "https://example.com/shop?item=3"
+        );
+        assert_eq!(
+            stripped("https://example.com/shop;jsessionid=0A1B", &["sid"]),
+            "https://example.com/shop;jsessionid=0A1B"
+        );
+    }
+
+    #[test]
+    fn resolve_strips_too() {
+        let base = Url::parse("https://example.com/gallery/").unwrap();
+        let url = resolve(&base, "photo.html?sid=9&n=1", &["sid".to_string()]).unwrap();
+        assert_eq!(url.as_str(), "https://example.com/gallery/photo.html?n=1");
+    }
+}
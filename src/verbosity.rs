@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// How much a run prints. Warnings, errors and the summaries at the end show
+// at every level.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Verbosity {
+    // `--quiet`: no line per page or image
+    Quiet,
+    // A line per page crawled and image downloaded or saved
+    Normal,
+    // `--verbose`: also why links and images were passed over
+    Verbose,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+// Set once for the whole process, before anything is crawled
+pub fn set_verbosity(verbosity: Verbosity) {
+    LEVEL.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn shows(verbosity: Verbosity) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= verbosity as u8
+}
+
+// A progress line, which `--quiet` leaves out
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if $crate::verbosity::shows($crate::verbosity::Verbosity::Normal) {
+            println!($($arg)*);
+        }
+    };
+}
+
+// A line only `--verbose` prints
+macro_rules! detail {
+    ($($arg:tt)*) => {
+        if $crate::verbosity::shows($crate::verbosity::Verbosity::Verbose) {
+            println!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use {detail, progress};